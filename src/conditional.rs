@@ -0,0 +1,255 @@
+// Conditional assembly, decided line by line while the source files are read.
+//
+// .ifdef FORWARDING
+//        ADA R3, R1, R2
+// .else
+//        NOP
+//        ADA R3, R1, R2
+// .endif
+//
+// - The condition of .if is a constant expression, as for .org: numbers, defines (-D NAME=1)
+//   and .equ constants from earlier lines. A condition that is not 0 is true.
+// - .ifdef and .ifndef test whether a name is a define or an .equ constant from an earlier
+//   line. Labels do not count.
+// - Lines in a block that is not assembled are dropped before anything else looks at them, so
+//   an .include there is not read and a .macro there is not defined.
+// - A macro body is read once, where it is defined, so an .if inside it cannot test the
+//   parameters of the macro.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::diagnostic::Diagnostic;
+use crate::expression::{Expression, Value};
+use crate::lexer::{parse_number, Directive, SpannedToken, Token};
+
+// An .if, .ifdef or .ifndef whose .endif has not been seen yet.
+#[derive(Debug, Clone)]
+struct Block {
+    span: Range<usize>,              // the opening directive
+    else_span: Option<Range<usize>>, // the .else, once seen
+    outer: bool,                     // whether the lines around the block are assembled
+    condition: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    constants: BTreeMap<String, Option<Value>>, // defines and .equ so far; None if not constant
+    blocks: Vec<Block>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Conditions {
+    pub fn new(defines: &BTreeMap<String, i64>) -> Conditions {
+        Conditions {
+            constants: defines
+                .iter()
+                .map(|(name, value)| (name.clone(), Some(Value::number(*value))))
+                .collect(),
+            ..Conditions::default()
+        }
+    }
+
+    // Whether the current line is in a block that is assembled.
+    pub fn active(&self) -> bool {
+        self.blocks
+            .last()
+            .is_none_or(|block| block.outer && block.condition != block.else_span.is_some())
+    }
+
+    // Reads one line. Returns whether it is assembled; the conditional directives themselves
+    // never are.
+    pub fn line(&mut self, line: &[SpannedToken]) -> bool {
+        let position = match line.first().map(|token| &token.token) {
+            Some(Token::Label(_)) => 1,
+            _ => 0,
+        };
+        let directive = match line.get(position).map(|token| &token.token) {
+            Some(Token::Directive(directive)) => *directive,
+            _ => return self.active(),
+        };
+        let span = line[position].span.range();
+        if position == 1
+            && matches!(
+                directive,
+                Directive::If
+                    | Directive::Ifdef
+                    | Directive::Ifndef
+                    | Directive::Else
+                    | Directive::Endif
+            )
+        {
+            self.diagnostics.push(Diagnostic::error(
+                format!("{} cannot have a label", directive),
+                line[0].span.range(),
+            ));
+        }
+
+        match directive {
+            Directive::If | Directive::Ifdef | Directive::Ifndef => {
+                let outer = self.active();
+                // the condition of a block inside one that is skipped is not evaluated
+                let condition = if outer {
+                    match self.condition(directive, line, position) {
+                        Ok(condition) => condition,
+                        Err(diagnostic) => {
+                            self.diagnostics.push(diagnostic);
+                            false
+                        }
+                    }
+                } else {
+                    false
+                };
+                self.blocks.push(Block {
+                    span,
+                    else_span: None,
+                    outer,
+                    condition,
+                });
+                false
+            }
+            Directive::Else => {
+                match self.blocks.last_mut() {
+                    Some(Block {
+                        else_span: Some(first),
+                        ..
+                    }) => {
+                        self.diagnostics.extend([
+                            Diagnostic::error("Duplicate .else", span),
+                            Diagnostic::note("the first .else is here", first.clone()),
+                        ]);
+                    }
+                    Some(block) => block.else_span = Some(span),
+                    None => self
+                        .diagnostics
+                        .push(Diagnostic::error(".else without an .if", span)),
+                }
+                false
+            }
+            Directive::Endif => {
+                if self.blocks.pop().is_none() {
+                    self.diagnostics
+                        .push(Diagnostic::error(".endif without an .if", span));
+                }
+                false
+            }
+            Directive::Equ if self.active() => {
+                self.define(line, position);
+                true
+            }
+            _ => self.active(),
+        }
+    }
+
+    // The diagnostics, once every line has been read.
+    pub fn finish(mut self) -> Vec<Diagnostic> {
+        for block in std::mem::take(&mut self.blocks) {
+            self.diagnostics.push(Diagnostic::error(
+                "This conditional block has no .endif",
+                block.span,
+            ));
+        }
+        self.diagnostics
+    }
+
+    fn condition(
+        &self,
+        directive: Directive,
+        line: &[SpannedToken],
+        position: usize,
+    ) -> Result<bool, Diagnostic> {
+        let operands = &line[position + 1..];
+        let end = |length: usize| {
+            operands.get(length).is_none_or(|token| {
+                matches!(token.token, Token::Comment(_) | Token::NewLine | Token::EOF)
+            })
+        };
+        let expects = || {
+            let end = line
+                .iter()
+                .rev()
+                .find(|token| {
+                    !matches!(token.token, Token::Comment(_) | Token::NewLine | Token::EOF)
+                })
+                .map_or(line[position].span.end, |token| token.span.end);
+            Diagnostic::error(
+                format!("{} expects {}", directive, directive.syntax()),
+                line[position].span.start..end,
+            )
+        };
+
+        if directive != Directive::If {
+            return match operands.first().map(|token| &token.token) {
+                Some(Token::Identifier(name)) if end(1) => {
+                    Ok(self.constants.contains_key(name) == (directive == Directive::Ifdef))
+                }
+                _ => Err(expects()),
+            };
+        }
+
+        if end(0) {
+            return Err(expects());
+        }
+        let (expression, length) = Expression::parse(operands)?;
+        if !end(length) {
+            return Err(expects());
+        }
+        Ok(self.constant(&expression)? != 0)
+    }
+
+    // A value from numbers, defines and .equ constants defined before it is used.
+    fn constant(&self, expression: &Expression) -> Result<i64, Diagnostic> {
+        let undefined = expression
+            .symbols()
+            .into_iter()
+            .find(|(name, _)| !matches!(self.constants.get(*name), Some(Some(_))));
+        if let Some((name, span)) = undefined {
+            return Err(Diagnostic::error(
+                format!("{} must be a constant defined before it is used", name),
+                span,
+            ));
+        }
+        let value = expression.evaluate(&|name| self.constants.get(name).copied().flatten())?;
+        Ok(value.value)
+    }
+
+    // Follows an .equ NAME, VALUE; the parser reports anything wrong with it.
+    fn define(&mut self, line: &[SpannedToken], position: usize) {
+        let name = match line.get(position + 1).map(|token| &token.token) {
+            Some(Token::Identifier(name)) => name.clone(),
+            _ => return,
+        };
+        if self.constants.contains_key(&name) {
+            return;
+        }
+        let value = match line.get(position + 2).map(|token| &token.token) {
+            Some(Token::Comma) => Expression::parse(&line[position + 3..])
+                .ok()
+                .and_then(|(expression, _)| self.constant(&expression).ok())
+                .map(Value::number),
+            _ => None,
+        };
+        self.constants.insert(name, value);
+    }
+}
+
+// Parses a define as given on the command line: NAME=VALUE, or NAME for NAME=1.
+pub fn parse_define(define: &str) -> Result<(String, i64), String> {
+    let (name, value) = match define.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (define, None),
+    };
+    let valid_name = name
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_alphabetic() || first == '_')
+        && name.chars().all(|ch| ch.is_alphanumeric() || ch == '_');
+    if !valid_name {
+        return Err(format!("Invalid define name: {}", name));
+    }
+    let value = match value {
+        Some(value) => parse_number(value.trim())?,
+        None => 1,
+    };
+    Ok((name.to_string(), value as i64))
+}
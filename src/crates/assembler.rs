@@ -17,120 +17,370 @@
 //
 // Method used to mitigate hazards: [List Scheduling](https://en.wikipedia.org/wiki/List_scheduling).
 
-use crate::lexer;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::diagnostic::{has_errors, sort_by_position, Diagnostic, Severity};
+use crate::isa::{self, Immediate, Opcode, Operands};
+use crate::lexer::{Directive, Processor, Span, SpannedToken, Token};
 use crate::parser::{Instruction, Parser};
+use crate::pseudo::PseudoOp;
+use crate::source::{Position, SourceMap};
 
-pub fn dissasembler(parser: Parser) {
-    let instructions = parser.instructions;
-    let labels = parser.labels;
-    let label_line_numbers = parser.label_line_numbers;
-    let lexer_string: String = parser.lexer.input.iter().collect();
-    let mut lines_traversed = 0;
-
-    for instruction in instructions.iter() {
-        if instruction.line_number > lines_traversed {
-            let line = lexer_string
-                .lines()
-                .nth(instruction.line_number - 1)
-                .unwrap();
-            println!(
-                "[INFO] Instruction: {:016b}, {}",
-                instruction_to_binary(instruction.clone()),
-                instruction.opcode
-            );
-            lines_traversed = instruction.line_number;
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleOptions {
+    pub processor: Processor,
+    pub include_paths: Vec<PathBuf>, // searched for .include files, after the including file's directory
+    pub defines: BTreeMap<String, i64>, // constants for the whole program, e.g. -D FORWARDING=1
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        AssembleOptions {
+            processor: Processor::Pipelined,
+            include_paths: Vec::new(),
+            defines: BTreeMap::new(),
         }
     }
 }
 
-fn instruction_to_binary(instruction: Instruction) -> i16 {
-    let instruction_bin: i16;
+// An assembled program: the memory image plus what is needed to map it back to the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub words: Vec<u16>,                    // the memory image, starting at address 0
+    pub line_numbers: BTreeMap<u16, usize>, // address -> source line of the word
+    pub files: BTreeMap<u16, usize>,        // address -> id of the source file of the word
+    pub opcodes: BTreeMap<u16, Opcode>,     // address -> opcode of the instruction; not for data
+    pub symbol_table: BTreeMap<String, u16>, // label -> address
+    pub warnings: Vec<Diagnostic>,          // warnings and notes from a successful build
+    pub processor: Processor,
+    pub file_names: Vec<String>, // by file id, as in `source::SourceMap`
+    pub pseudos: BTreeMap<u16, PseudoOp>, // address -> pseudo-instruction the word was lowered from
+    pub statements: Vec<Statement>, // every line that places words, in source order
+    pub constants: BTreeMap<String, i64>, // .equ and -D constant -> value
+    pub definitions: BTreeMap<String, Position>, // label or .equ constant -> where it is defined
+    pub symbol_uses: BTreeMap<String, Vec<Position>>, // label or constant -> where it is used
+    pub register_uses: BTreeMap<i32, Vec<Position>>, // register -> where it is used
+}
 
-    let opcode_bin = String::from(opcode_to_binary(instruction.opcode.as_str()));
-    let opcode = instruction.clone().opcode;
-    let reg_a = register_to_binary(instruction.reg_a);
+// A line that places words, e.g. for a listing or debug information.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub position: Position, // of the mnemonic or directive; in the macro body, if expanded
+    pub address: u16,
+    pub size: usize,            // words placed
+    pub macros: Vec<Expansion>, // the calls it was expanded from, innermost first
+}
 
-    if lexer::OPCODES_WITH_SINGLE_REGISTER_PIPELINED.contains(&opcode.as_str()) {
-        if instruction.imm > 511 {
-            panic!("[ERROR] Immediate value out of range");
-        }
-        let imm = format!("{:09b}", instruction.imm);
+// A macro call that a statement was expanded from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expansion {
+    pub name: String,
+    pub call: Position,
+}
 
-        let instruction_bin_str = format!("{}{}{}", opcode_bin, reg_a, imm);
-        instruction_bin = i16::from_str_radix(&instruction_bin_str, 2).unwrap();
-    } else if lexer::OPCODES_WITH_TWO_REGISTERS_PIPELINED.contains(&opcode.as_str()) {
-        let reg_b = register_to_binary(instruction.reg_b.expect("[ERROR] Missing register B"));
+// Parses and encodes a whole program. On failure, every diagnostic found is returned, errors
+// and warnings alike, in source order.
+//
+// Instructions and data are placed at the addresses given by the parser; words that nothing
+// was placed at are zero.
+pub fn assemble(source: &str, options: &AssembleOptions) -> Result<Program, Vec<Diagnostic>> {
+    let mut sources = SourceMap::new();
+    sources.add("<input>", source);
+    assemble_sources(&mut sources, options)
+}
 
-        if instruction.imm > 63 {
-            panic!("[ERROR] Immediate value out of range");
+// Assembles file 0 of `sources`. The files it includes are added to `sources`, and every
+// diagnostic points at the file it belongs to, so it can be rendered with `SourceMap::render`.
+pub fn assemble_sources(
+    sources: &mut SourceMap,
+    options: &AssembleOptions,
+) -> Result<Program, Vec<Diagnostic>> {
+    let mut parser = Parser::with_sources(
+        std::mem::take(sources),
+        options.processor,
+        &options.include_paths,
+        &options.defines,
+    );
+    let mut diagnostics = parser.parse();
+
+    // (address, words, line, span) of every statement that emits words
+    let mut blocks = Vec::with_capacity(parser.instructions.len() + parser.data.len());
+    let mut opcodes = BTreeMap::new();
+    for instruction in parser.instructions.iter() {
+        opcodes.insert(instruction.address, instruction.opcode);
+        match instruction_to_binary(instruction) {
+            Ok(word) => blocks.push((
+                instruction.address,
+                vec![word],
+                instruction.line_number,
+                instruction.span.clone(),
+            )),
+            Err(message) => diagnostics.push(Diagnostic::error(message, instruction.span.clone())),
         }
-        let imm = format!("{:06b}", instruction.imm);
+    }
+    for data in parser.data.iter() {
+        blocks.push((
+            data.address,
+            data.words.clone(),
+            data.line_number,
+            data.span.clone(),
+        ));
+    }
+    blocks.sort_by_key(|block| block.3.start);
 
-        let instruction_bin_str = format!("{}{}{}{}", opcode_bin, reg_a, reg_b, imm);
+    let mut words = Vec::new();
+    let mut line_numbers = BTreeMap::new();
+    let mut files = BTreeMap::new();
+    for (address, block_words, line_number, span) in blocks {
+        let file = parser.sources.file_at(span.start);
+        let mut overlap = None;
+        for (index, word) in block_words.into_iter().enumerate() {
+            let address = address as usize + index;
+            if words.len() <= address {
+                words.resize(address + 1, 0);
+            }
+            match line_numbers.get(&(address as u16)) {
+                Some(line) if overlap.is_none() => overlap = Some((address, *line)),
+                Some(_) => {}
+                None => {
+                    words[address] = word;
+                    line_numbers.insert(address as u16, line_number);
+                    files.insert(address as u16, file);
+                }
+            }
+        }
+        if let Some((address, line)) = overlap {
+            let mut user = format!("line {}", line);
+            let user_file = files[&(address as u16)];
+            if user_file != file {
+                user.push_str(&format!(" of {}", parser.sources.files[user_file].name));
+            }
+            diagnostics.push(
+                Diagnostic::error(
+                    format!("Address 0x{:04X} is already used by {}", address, user),
+                    span,
+                )
+                .with_help("check the .org directives and the sizes of .fill and .space"),
+            );
+        }
+    }
 
-        instruction_bin = i16::from_str_radix(&instruction_bin_str, 2).unwrap();
-    } else if lexer::OPCODES_WITH_THREE_REGISTERS_PIPELINED.contains(&opcode.as_str()) {
-        let reg_b = register_to_binary(instruction.reg_b.expect("[ERROR] Missing register B"));
-        let reg_c = register_to_binary(instruction.reg_c.expect("[ERROR] Missing register C"));
+    // program-wide spans put the diagnostics of each file in the order it was included
+    sort_by_position(&mut diagnostics);
+    for diagnostic in diagnostics.iter_mut() {
+        parser.sources.localize(diagnostic);
+    }
+    *sources = std::mem::take(&mut parser.sources);
+    if has_errors(&diagnostics) {
+        return Err(diagnostics);
+    }
 
-        let instruction_bin_str = format!("{}{}{}{}", opcode_bin, reg_a, reg_b, reg_c);
+    let (symbol_uses, register_uses) = uses(&parser);
+    Ok(Program {
+        words,
+        line_numbers,
+        files,
+        opcodes,
+        warnings: diagnostics
+            .into_iter()
+            .filter(|diagnostic| diagnostic.severity != Severity::Error)
+            .collect(),
+        processor: options.processor,
+        file_names: sources.files.iter().map(|file| file.name.clone()).collect(),
+        pseudos: parser
+            .instructions
+            .iter()
+            .filter_map(|instruction| Some((instruction.address, instruction.pseudo?)))
+            .collect(),
+        statements: statements(&parser, sources),
+        constants: parser
+            .constants
+            .iter()
+            .map(|(name, value)| (name.clone(), value.value))
+            .collect(),
+        definitions: definitions(&parser),
+        symbol_uses,
+        register_uses,
+        symbol_table: parser.symbol_table,
+    })
+}
+
+// The position of the first token of a line after its label, if it has one.
+fn statement_start(tokens: &[SpannedToken]) -> usize {
+    usize::from(matches!(
+        tokens.first().map(|token| &token.token),
+        Some(Token::Label(_))
+    ))
+}
 
-        instruction_bin = i16::from_str_radix(&instruction_bin_str, 2).unwrap();
-    } else {
-        panic!("Invalid opcode: {}", instruction.opcode.as_str());
-        // Technically, this should never be reached.
+fn position(span: Span) -> Position {
+    Position {
+        file: span.file,
+        line: span.line,
+        column: span.column,
     }
+}
 
-    instruction_bin
+// The lines of the expanded source that place words.
+fn statements(parser: &Parser, sources: &SourceMap) -> Vec<Statement> {
+    let mut statements = Vec::new();
+    for (index, tokens) in parser.token_stream.tokens_by_line.iter().enumerate() {
+        let size = parser.line_sizes.get(index).copied().unwrap_or(0) as usize;
+        let (Some(statement), Some(address)) = (
+            tokens.get(statement_start(tokens)),
+            parser.line_addresses[index],
+        ) else {
+            continue;
+        };
+        if size == 0 {
+            continue;
+        }
+        statements.push(Statement {
+            position: position(statement.span),
+            address,
+            size,
+            macros: parser.macro_calls[index]
+                .iter()
+                .map(|call| Expansion {
+                    name: call.name.clone(),
+                    call: sources.position(call.span.start),
+                })
+                .collect(),
+        });
+    }
+    statements
 }
 
-fn register_to_binary(reg: i32) -> String {
-    if reg > 7 {
-        panic!("[ERROR] Register out of range");
+// Where each label and .equ constant is first defined.
+fn definitions(parser: &Parser) -> BTreeMap<String, Position> {
+    let mut definitions = BTreeMap::new();
+    for tokens in parser.token_stream.tokens_by_line.iter() {
+        let position_after_label = statement_start(tokens);
+        if let Some(Token::Label(label)) = tokens.first().map(|token| &token.token) {
+            definitions
+                .entry(label.trim_end_matches(':').to_string())
+                .or_insert(position(tokens[0].span));
+        }
+        if let (Some(Token::Directive(Directive::Equ)), Some(name)) = (
+            tokens.get(position_after_label).map(|token| &token.token),
+            tokens.get(position_after_label + 1),
+        ) {
+            if let Token::Identifier(identifier) = &name.token {
+                definitions
+                    .entry(identifier.clone())
+                    .or_insert(position(name.span));
+            }
+        }
     }
-    format!("{:03b}", reg)
+    definitions
 }
 
-fn opcode_to_binary(opcode: &str) -> &str {
-    match opcode {
-        "ADA" => "0001", // RA RB RC 0 00
-        "ADC" => "0001", // RA RB RC 0 10
-        "ADZ" => "0001", // RA RB RC 0 01
-        "AWC" => "0001", // RA RB RC 0 11
-        "ACA" => "0001", // RA RB RC 1 00
-        "ACC" => "0001", // RA RB RC 1 10
-        "ACZ" => "0001", // RA RB RC 1 01
-        "ACW" => "0001", // RA RB RC 1 11
-        "ADI" => "0000", //RA RB IMM6
-        "NDU" => "0010", // RA RB RC 0 00
-        "NDC" => "0010", // RA RB RC 0 10
-        "NDZ" => "0010", // RA RB RC 0 01
-        "NCU" => "0010", // RA RB RC 1 00
-        "NCC" => "0010", // RA RB RC 1 10
-        "NCZ" => "0010", // RA RB RC 1 01
-        "LLI" => "0011", // RA IMM9
-        "LW" => "0100",  //0 RA RB IMM6
-        "SW" => "0101",  //1 RA RB IMM6
-        "LM" => "0110",  //0 RA 0 + 8 bits corresponding to R0 to R7
-        "SM" => "0111",  //1 RA 0 + 8 bits corresponding to R0 to R7
-        "BEQ" => "1000", // RA RB IMM6
-        "BLT" => "1001", // RA RB IMM6
-        "BLE" => "1010", // RA RB IMM6
-        "JAL" => "1100", // RA IMM9
-        "JLR" => "1101", // RA 0 0000
-        "JRI" => "1111", //"; RA 0 000
-        _ => panic!("Invalid opcode"),
+// Where each label, constant and register is used. Every label and constant has an entry, so
+// the ones that are never used can be listed too.
+fn uses(
+    parser: &Parser,
+) -> (
+    BTreeMap<String, Vec<Position>>,
+    BTreeMap<i32, Vec<Position>>,
+) {
+    let mut symbol_uses: BTreeMap<String, Vec<Position>> = parser
+        .symbol_table
+        .keys()
+        .chain(parser.constants.keys())
+        .map(|name| (name.clone(), Vec::new()))
+        .collect();
+    let mut register_uses: BTreeMap<i32, Vec<Position>> = BTreeMap::new();
+    for tokens in parser.token_stream.tokens_by_line.iter() {
+        // the mnemonic, and the name a .equ defines, are not uses
+        let mut start = statement_start(tokens);
+        if let Some(Token::Directive(Directive::Equ)) = tokens.get(start).map(|t| &t.token) {
+            start += 1;
+        }
+        for token in tokens.iter().skip(start + 1) {
+            match &token.token {
+                Token::Register(register) => {
+                    register_uses
+                        .entry(*register)
+                        .or_default()
+                        .push(position(token.span));
+                }
+                Token::Identifier(name) => {
+                    if let Some(uses) = symbol_uses.get_mut(name) {
+                        uses.push(position(token.span));
+                    }
+                }
+                _ => {}
+            }
+        }
     }
+    (symbol_uses, register_uses)
 }
 
-fn immediate6_to_binary(imm: i32) -> String {
-    let imm_binary = format!("{:6b}", imm.to_owned());
+// Encodes a parsed instruction into its 16-bit machine word.
+//
+// Layouts (MSB first), as given by the operands of the opcode in `isa`:
+//   RA, RB, RC:  opcode(4) RA(3) RB(3) RC(3) C(1) CZ(2)
+//   RA, RB, IMM: opcode(4) RA(3) RB(3) IMM6
+//   RA, RB:      opcode(4) RA(3) RB(3) 000000
+//   RA, IMM:     opcode(4) RA(3) IMM9, or 0 + IMM8 / 0 + 8 bits corresponding to R0 to R7
+// Immediates are stored in two's complement and truncated to the field width.
+pub fn instruction_to_binary(instruction: &Instruction) -> Result<u16, String> {
+    let descriptor = match isa::descriptor(instruction.processor, instruction.opcode) {
+        Some(descriptor) => descriptor,
+        None => {
+            return Err(format!(
+                "Invalid opcode: {} is not an instruction of the {:?} processor",
+                instruction.opcode, instruction.processor
+            ))
+        }
+    };
+    let opcode_bin = descriptor.opcode_bits << 12;
+    let reg_a = register_to_binary(instruction.reg_a)? << 9;
+    let reg_b = || match instruction.reg_b {
+        Some(reg) => register_to_binary(reg),
+        None => Err(format!("{} is missing register B", instruction.opcode)),
+    };
+    let reg_c = || match instruction.reg_c {
+        Some(reg) => register_to_binary(reg),
+        None => Err(format!("{} is missing register C", instruction.opcode)),
+    };
 
-    imm_binary
+    let word = match descriptor.operands {
+        Operands::RaRbRc => {
+            opcode_bin | reg_a | reg_b()? << 6 | reg_c()? << 3 | descriptor.suffix_bits
+        }
+        Operands::RaRbImm => {
+            opcode_bin
+                | reg_a
+                | reg_b()? << 6
+                | immediate_to_binary(descriptor.immediate, instruction.imm)?
+        }
+        Operands::RaRb => opcode_bin | reg_a | reg_b()? << 6,
+        Operands::RaImm => {
+            opcode_bin | reg_a | immediate_to_binary(descriptor.immediate, instruction.imm)?
+        }
+    };
+    Ok(word)
 }
 
-fn immediate9_to_binary(imm: i32) -> String {
-    let imm_binary = format!("{:9b}", imm.to_owned());
+fn register_to_binary(reg: i32) -> Result<u16, String> {
+    if !(0..=7).contains(&reg) {
+        return Err(format!("Register R{} is out of range (R0 to R7)", reg));
+    }
+    Ok(reg as u16)
+}
 
-    imm_binary
+// Accepts both the signed and the unsigned reading of the field, so a raw bit pattern can be
+// encoded as well; the parser has already checked the immediate against its signedness.
+fn immediate_to_binary(immediate: Immediate, imm: i32) -> Result<u16, String> {
+    let bits = immediate.bits();
+    let range = match immediate {
+        Immediate::Signed(_) | Immediate::Offset(_) => -(1 << (bits - 1))..=(1 << bits) - 1,
+        _ => 0..=(1 << bits) - 1,
+    };
+    if !range.contains(&imm) {
+        return Err(format!("Immediate {} does not fit in IMM{}", imm, bits));
+    }
+    Ok((imm as u16) & ((1 << bits) - 1))
 }
@@ -0,0 +1,248 @@
+// Debug information, written next to the memory image so that a program can be followed at
+// source level from its image alone, e.g. a .hex dumped from an FPGA board in the lab.
+//
+// iitb-debug 6 A3F0
+// file 0 main.asm
+// label LOOP 0003
+// addr 0000 0 3 8
+// addr 0001 0 4 8 pseudo LI
+// addr 0004 0 2 8 macro PUSH 0 9 8
+//
+// - The header gives the length of the image and the sum of its words, modulo 2^16, so a
+//   loader can tell whether an image belongs to the file.
+// - Every word placed by a statement has the file, line and column of the statement. A word
+//   lowered from a pseudo-instruction names it. A word of an expanded macro points at the line
+//   of the body, and lists the calls it was expanded from, innermost first.
+// - Files are numbered as in `source::SourceMap`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Component, Path};
+
+use crate::crates::assembler::{Expansion, Program};
+use crate::pseudo::PseudoOp;
+use crate::source::Position;
+
+// Where a word of the image comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub position: Position,
+    pub pseudo: Option<PseudoOp>,
+    pub macros: Vec<Expansion>, // innermost first
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub length: usize, // of the image, in words
+    pub checksum: u16,
+    pub files: Vec<String>, // indexed by file id
+    pub labels: BTreeMap<String, u16>,
+    pub origins: BTreeMap<u16, Origin>, // address -> where the word comes from
+}
+
+// The debug information of an assembled program.
+pub fn debug_info(program: &Program) -> DebugInfo {
+    let mut origins = BTreeMap::new();
+    for statement in program.statements.iter() {
+        for offset in 0..statement.size {
+            let address = statement.address.wrapping_add(offset as u16);
+            origins.entry(address).or_insert_with(|| Origin {
+                position: statement.position,
+                pseudo: program.pseudos.get(&address).copied(),
+                macros: statement.macros.clone(),
+            });
+        }
+    }
+
+    DebugInfo {
+        length: program.words.len(),
+        checksum: checksum(&program.words),
+        files: program.file_names.clone(),
+        labels: program.symbol_table.clone(),
+        origins,
+    }
+}
+
+// The components of a path, without the . of ./main.asm.
+fn components(path: &Path) -> Vec<Component<'_>> {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn checksum(words: &[u16]) -> u16 {
+    words.iter().fold(0, |sum, word| sum.wrapping_add(*word))
+}
+
+impl DebugInfo {
+    // Whether `words` is the image this information was written for.
+    pub fn matches(&self, words: &[u16]) -> bool {
+        words.len() == self.length && checksum(words) == self.checksum
+    }
+
+    pub fn origin(&self, address: u16) -> Option<&Origin> {
+        self.origins.get(&address)
+    }
+
+    // The source of a word, e.g. "main.asm:4:8 (LI)" or "stack.asm:2:8 (in PUSH at
+    // main.asm:9:8)", for traces.
+    pub fn describe(&self, address: u16) -> Option<String> {
+        let origin = self.origin(address)?;
+        let mut text = self.position_text(origin.position);
+        let mut notes = Vec::new();
+        if let Some(pseudo) = origin.pseudo {
+            notes.push(pseudo.to_string());
+        }
+        for expansion in origin.macros.iter() {
+            notes.push(format!(
+                "in {} at {}",
+                expansion.name,
+                self.position_text(expansion.call)
+            ));
+        }
+        if !notes.is_empty() {
+            let _ = write!(text, " ({})", notes.join(", "));
+        }
+        Some(text)
+    }
+
+    fn position_text(&self, position: Position) -> String {
+        let file = self.files.get(position.file).map_or("?", String::as_str);
+        format!("{}:{}:{}", file, position.line, position.column)
+    }
+
+    // The addresses of the words that come from a line of `path`, for an editor. A file
+    // matches if `path` ends with its name, so main.asm matches /home/lab/main.asm.
+    pub fn addresses(&self, path: &Path, line: usize) -> Vec<u16> {
+        let path = components(path);
+        self.origins
+            .iter()
+            .filter(|(_, origin)| {
+                origin.position.line == line
+                    && self.files.get(origin.position.file).is_some_and(|name| {
+                        let name = components(Path::new(name));
+                        !name.is_empty() && path.ends_with(&name)
+                    })
+            })
+            .map(|(address, _)| *address)
+            .collect()
+    }
+
+    // The debug file.
+    pub fn write(&self) -> String {
+        let mut output = String::new();
+        let _ = writeln!(output, "iitb-debug {} {:04X}", self.length, self.checksum);
+        for (id, name) in self.files.iter().enumerate() {
+            let _ = writeln!(output, "file {} {}", id, name);
+        }
+        for (name, address) in self.labels.iter() {
+            let _ = writeln!(output, "label {} {:04X}", name, address);
+        }
+        for (address, origin) in self.origins.iter() {
+            let position = origin.position;
+            let _ = write!(
+                output,
+                "addr {:04X} {} {} {}",
+                address, position.file, position.line, position.column
+            );
+            if let Some(pseudo) = origin.pseudo {
+                let _ = write!(output, " pseudo {}", pseudo);
+            }
+            for expansion in origin.macros.iter() {
+                let call = expansion.call;
+                let _ = write!(
+                    output,
+                    " macro {} {} {} {}",
+                    expansion.name, call.file, call.line, call.column
+                );
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    // Reads a debug file. Errors name the line they are on.
+    pub fn read(text: &str) -> Result<DebugInfo, String> {
+        let mut info = DebugInfo::default();
+        let mut header = false;
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() {
+                continue;
+            }
+            let number = |index: usize| -> Result<usize, String> {
+                let field = fields.get(index).copied().unwrap_or("");
+                field
+                    .parse()
+                    .map_err(|_| error(format!("invalid number {:?}", field)))
+            };
+            let address = |index: usize| -> Result<u16, String> {
+                let field = fields.get(index).copied().unwrap_or("");
+                u16::from_str_radix(field, 16)
+                    .map_err(|_| error(format!("invalid address {:?}", field)))
+            };
+            let position = |index: usize| -> Result<Position, String> {
+                Ok(Position {
+                    file: number(index)?,
+                    line: number(index + 1)?,
+                    column: number(index + 2)?,
+                })
+            };
+
+            match (header, fields[0]) {
+                (false, "iitb-debug") if fields.len() == 3 => {
+                    info.length = number(1)?;
+                    info.checksum = address(2)?;
+                    header = true;
+                }
+                (false, _) => return Err(error("not an iitb-debug file".to_string())),
+                (true, "file") if fields.len() >= 3 => {
+                    if number(1)? != info.files.len() {
+                        return Err(error("files must be numbered in order".to_string()));
+                    }
+                    // the name is the rest of the line, spaces and all
+                    let name = line.trim().splitn(3, ' ').nth(2).unwrap_or("").trim();
+                    info.files.push(name.to_string());
+                }
+                (true, "label") if fields.len() == 3 => {
+                    info.labels.insert(fields[1].to_string(), address(2)?);
+                }
+                (true, "addr") if fields.len() >= 5 => {
+                    let mut origin = Origin {
+                        position: position(2)?,
+                        pseudo: None,
+                        macros: Vec::new(),
+                    };
+                    let mut next = 5;
+                    while next < fields.len() {
+                        match fields[next] {
+                            "pseudo" => {
+                                let name = fields.get(next + 1).copied().unwrap_or("");
+                                origin.pseudo =
+                                    Some(PseudoOp::from_mnemonic(name).ok_or_else(|| {
+                                        error(format!("unknown pseudo-instruction {:?}", name))
+                                    })?);
+                                next += 2;
+                            }
+                            "macro" if next + 4 < fields.len() => {
+                                origin.macros.push(Expansion {
+                                    name: fields[next + 1].to_string(),
+                                    call: position(next + 2)?,
+                                });
+                                next += 5;
+                            }
+                            field => return Err(error(format!("unexpected {:?}", field))),
+                        }
+                    }
+                    info.origins.insert(address(1)?, origin);
+                }
+                (true, record) => return Err(error(format!("invalid record {}", record))),
+            }
+        }
+        if !header {
+            return Err("the debug file is empty".to_string());
+        }
+        Ok(info)
+    }
+}
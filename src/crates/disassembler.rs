@@ -0,0 +1,161 @@
+// A disassembler for the IITB RISC-V Processor.
+//
+// Turns 16-bit machine words, e.g. a memory dump taken from an FPGA board, back into
+// `Instruction` values and assembly text.
+//
+// - The mnemonic is rebuilt from the opcode plus the complement and CZ bits.
+// - IMM6 and IMM9 are sign-extended, except for LLI which loads an unsigned constant.
+// - LM and SM masks are printed as register lists, e.g. {R0, R2-R4, R7}.
+// - Words that do not decode to a valid instruction are printed as `.word 0xXXXX`.
+// - An instruction that is also a pseudo-instruction is followed by it as a comment, e.g.
+//   `ADI R2, R1, 0 ; MOV R1, R2`.
+
+use crate::isa::{self, Immediate, Operands};
+use crate::lexer::Processor;
+use crate::parser::Instruction;
+use crate::pseudo;
+
+pub fn decode_instruction(word: u16, processor: Processor) -> Option<Instruction> {
+    let opcode_bits = word >> 12;
+    let reg_a = ((word >> 9) & 0b111) as i32;
+    let reg_b = ((word >> 6) & 0b111) as i32;
+    let reg_c = ((word >> 3) & 0b111) as i32;
+    let suffix_bits = word & 0b111;
+
+    let descriptor = isa::instruction_set(processor).iter().find(|descriptor| {
+        descriptor.opcode_bits == opcode_bits
+            && (descriptor.operands != Operands::RaRbRc || descriptor.suffix_bits == suffix_bits)
+    })?;
+
+    let (reg_b, reg_c, field) = match descriptor.operands {
+        Operands::RaRbRc => (Some(reg_b), Some(reg_c), 0),
+        Operands::RaRbImm => (Some(reg_b), None, word & 0x3F),
+        Operands::RaRb => {
+            // the low six bits are padding and must be zero
+            if word & 0x3F != 0 {
+                return None;
+            }
+            (Some(reg_b), None, 0)
+        }
+        Operands::RaImm => (None, None, word & 0x1FF),
+    };
+
+    let imm = match descriptor.immediate {
+        Immediate::None => 0,
+        Immediate::Signed(bits) | Immediate::Offset(bits) => sign_extend(field, bits),
+        Immediate::Unsigned(_) | Immediate::RegisterMask => {
+            // bits above an 8-bit field must be zero
+            if field >> descriptor.immediate.bits() != 0 {
+                return None;
+            }
+            field as i32
+        }
+    };
+
+    Some(Instruction::new(
+        descriptor.opcode,
+        reg_a,
+        reg_b,
+        reg_c,
+        imm,
+        0,
+        0,
+        processor,
+    ))
+}
+
+// Decodes a whole memory image, one entry per word. `None` marks an undecodable word.
+// The line number of each decoded instruction is the line it takes in `disassemble`.
+pub fn decode_image(image: &[u16], processor: Processor) -> Vec<Option<Instruction>> {
+    image
+        .iter()
+        .enumerate()
+        .map(|(index, word)| {
+            decode_instruction(*word, processor).map(|mut instruction| {
+                instruction.line_number = index + 1;
+                instruction.address = index as u16;
+                instruction
+            })
+        })
+        .collect()
+}
+
+pub fn instruction_to_assembly(instruction: &Instruction) -> String {
+    let descriptor = match isa::descriptor(instruction.processor, instruction.opcode) {
+        Some(descriptor) => descriptor,
+        None => return format!("{} ???", instruction.opcode),
+    };
+    let reg_b = instruction.reg_b.unwrap_or(0);
+    let reg_c = instruction.reg_c.unwrap_or(0);
+
+    match descriptor.operands {
+        Operands::RaRbRc => format!(
+            "{} R{}, R{}, R{}",
+            instruction.opcode, instruction.reg_a, reg_b, reg_c
+        ),
+        Operands::RaRbImm => format!(
+            "{} R{}, R{}, {}",
+            instruction.opcode, instruction.reg_a, reg_b, instruction.imm
+        ),
+        Operands::RaRb => format!("{} R{}, R{}", instruction.opcode, instruction.reg_a, reg_b),
+        Operands::RaImm if descriptor.immediate == Immediate::RegisterMask => format!(
+            "{} R{}, {}",
+            instruction.opcode,
+            instruction.reg_a,
+            register_list(instruction.imm)
+        ),
+        Operands::RaImm => format!(
+            "{} R{}, {}",
+            instruction.opcode, instruction.reg_a, instruction.imm
+        ),
+    }
+}
+
+// Prints an LM/SM mask (bit 7 is R0) as a register list, e.g. {R0, R2-R4, R7}. Runs of three
+// or more registers are written as a range. An empty mask has no list syntax and stays a number.
+pub fn register_list(mask: i32) -> String {
+    let registers: Vec<i32> = (0..8).filter(|reg| mask & (1 << (7 - reg)) != 0).collect();
+    if registers.is_empty() {
+        return mask.to_string();
+    }
+
+    let mut parts = Vec::new();
+    let mut index = 0;
+    while index < registers.len() {
+        let first = registers[index];
+        let mut last = first;
+        while index + 1 < registers.len() && registers[index + 1] == last + 1 {
+            index += 1;
+            last += 1;
+        }
+        match last - first {
+            0 => parts.push(format!("R{}", first)),
+            1 => parts.push(format!("R{}, R{}", first, last)),
+            _ => parts.push(format!("R{}-R{}", first, last)),
+        }
+        index += 1;
+    }
+    format!("{{{}}}", parts.join(", "))
+}
+
+pub fn disassemble(image: &[u16], processor: Processor) -> String {
+    let mut output = String::new();
+    for word in image.iter() {
+        match decode_instruction(*word, processor) {
+            Some(instruction) => {
+                output.push_str(&instruction_to_assembly(&instruction));
+                if let Some(pseudo) = pseudo::recognise(&instruction) {
+                    output.push_str(&format!(" ; {}", pseudo));
+                }
+            }
+            None => output.push_str(&format!(".word 0x{:04X}", word)),
+        }
+        output.push('\n');
+    }
+    output
+}
+
+fn sign_extend(value: u16, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    ((value as i32) << shift) >> shift
+}
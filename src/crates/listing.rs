@@ -0,0 +1,213 @@
+// An assembly listing: every source line with the address and encoding of the words it emits,
+// then the symbol table and a cross-reference of where each symbol and register is used.
+//
+// main.asm
+//  LINE  ADDR  HEX   BINARY                 SOURCE
+//     1  0000  3205  0011 001 000000101     START: LLI R1, 5
+//     2  0001  1299  0001 001 010 011 0 01  ADC R1, R2, R3
+//     3                                     ; done
+//
+// - The binary encoding is split into the fields of the instruction format:
+//   opcode | RA | RB | RC | complement | CZ, opcode | RA | RB | IMM6, or opcode | RA | IMM9.
+//   Data words are not split.
+// - A line that emits several words (a pseudo-instruction, .word with several values, .fill)
+//   lists one word per row. The words of an expanded macro are listed at the outermost call.
+// - Every file of the program is listed in turn, the top-level file first.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::crates::assembler::Program;
+use crate::isa::{self, Opcode, Operands};
+use crate::lexer::Processor;
+use crate::source::{Position, SourceMap};
+
+const BINARY_WIDTH: usize = 21; // 16 bits and the spaces between the fields of RA, RB, RC
+
+// A (file id, line) pair, with a 1-based line.
+type Location = (usize, usize);
+
+// The listing of an assembled program. `sources` are the files it was assembled from.
+pub fn listing(program: &Program, sources: &SourceMap) -> String {
+    // the addresses placed by each source line; the words of an expanded macro are listed at
+    // the outermost call
+    let mut statements: BTreeMap<Location, Vec<u16>> = BTreeMap::new();
+    for statement in program.statements.iter() {
+        let site = statement
+            .macros
+            .last()
+            .map_or(statement.position, |expansion| expansion.call);
+        statements
+            .entry((site.file, site.line))
+            .or_default()
+            .extend(
+                (0..statement.size).map(|offset| statement.address.wrapping_add(offset as u16)),
+            );
+    }
+
+    let mut output = String::new();
+    for (id, file) in sources.files.iter().enumerate() {
+        if id > 0 {
+            output.push('\n');
+        }
+        let _ = writeln!(output, "{}", file.name);
+        let _ = writeln!(
+            output,
+            " LINE  ADDR  HEX   {:<width$}  SOURCE",
+            "BINARY",
+            width = BINARY_WIDTH
+        );
+        for (index, text) in file.text.lines().enumerate() {
+            let number = index + 1;
+            let addresses = statements.get(&(id, number)).map_or(&[][..], Vec::as_slice);
+            if addresses.is_empty() {
+                let row = format!(
+                    "{:>5}{:width$}{}",
+                    number,
+                    "",
+                    text,
+                    width = BINARY_WIDTH + 16
+                );
+                let _ = writeln!(output, "{}", row.trim_end());
+                continue;
+            }
+            for (row, address) in addresses.iter().enumerate() {
+                let (hex, binary) = match program.words.get(*address as usize) {
+                    Some(word) => (
+                        format!("{:04X}", word),
+                        fields(*word, program.opcodes.get(address), program.processor),
+                    ),
+                    None => ("????".to_string(), String::new()),
+                };
+                let (number, text) = match row {
+                    0 => (number.to_string(), text),
+                    _ => (String::new(), ""),
+                };
+                let row = format!(
+                    "{:>5}  {:04X}  {}  {:<width$}  {}",
+                    number,
+                    address,
+                    hex,
+                    binary,
+                    text,
+                    width = BINARY_WIDTH
+                );
+                let _ = writeln!(output, "{}", row.trim_end());
+            }
+        }
+    }
+
+    symbols(program, sources, &mut output);
+    cross_reference(program, sources, &mut output);
+    output
+}
+
+// The binary of a word, split into the fields of its instruction format.
+fn fields(word: u16, opcode: Option<&Opcode>, processor: Processor) -> String {
+    let operands = opcode
+        .and_then(|opcode| isa::descriptor(processor, *opcode))
+        .map(|descriptor| descriptor.operands);
+    let widths: &[usize] = match operands {
+        Some(Operands::RaRbRc) => &[4, 3, 3, 3, 1, 2],
+        Some(Operands::RaRbImm | Operands::RaRb) => &[4, 3, 3, 6],
+        Some(Operands::RaImm) => &[4, 3, 9],
+        None => &[16],
+    };
+    let bits = format!("{:016b}", word);
+    let mut start = 0;
+    let mut parts = Vec::with_capacity(widths.len());
+    for width in widths {
+        parts.push(&bits[start..start + width]);
+        start += width;
+    }
+    parts.join(" ")
+}
+
+// Labels and constants, with their values and where they are defined. A constant defined
+// from outside the program has no definition, and is marked -D.
+fn symbols(program: &Program, sources: &SourceMap, output: &mut String) {
+    let mut rows: Vec<(&str, String, String)> = Vec::new();
+    for (name, address) in program.symbol_table.iter() {
+        rows.push((name, format!("0x{:04X}", address), String::new()));
+    }
+    for (name, value) in program.constants.iter() {
+        rows.push((name, value.to_string(), String::new()));
+    }
+    rows.sort_by(|a, b| a.0.cmp(b.0));
+    for row in rows.iter_mut() {
+        row.2 = match program.definitions.get(row.0) {
+            Some(position) => format_location(sources, location(position), true),
+            None => "-D".to_string(),
+        };
+    }
+
+    let name_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0).max(4);
+    let value_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0).max(5);
+    let _ = writeln!(output, "\nSymbols");
+    let _ = writeln!(
+        output,
+        "  {:<name_width$}  {:<value_width$}  DEFINED",
+        "NAME",
+        "VALUE",
+        name_width = name_width,
+        value_width = value_width
+    );
+    for (name, value, defined) in rows {
+        let _ = writeln!(
+            output,
+            "  {:<name_width$}  {:<value_width$}  {}",
+            name,
+            value,
+            defined,
+            name_width = name_width,
+            value_width = value_width
+        );
+    }
+}
+
+// Where each symbol and register is used, by line.
+fn cross_reference(program: &Program, sources: &SourceMap, output: &mut String) {
+    let lines = |uses: &[Position]| -> BTreeSet<Location> { uses.iter().map(location).collect() };
+    let rows: Vec<(String, BTreeSet<Location>)> = program
+        .symbol_uses
+        .iter()
+        .map(|(name, uses)| (name.clone(), lines(uses)))
+        .chain(
+            program
+                .register_uses
+                .iter()
+                .map(|(register, uses)| (format!("R{}", register), lines(uses))),
+        )
+        .collect();
+    let name_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+    let _ = writeln!(output, "\nCross-reference");
+    for (name, uses) in rows {
+        let uses: Vec<String> = uses
+            .iter()
+            .map(|location| format_location(sources, *location, false))
+            .collect();
+        let uses = match uses.is_empty() {
+            true => "not used".to_string(),
+            false => uses.join(", "),
+        };
+        let _ = writeln!(
+            output,
+            "  {:<name_width$}  {}",
+            name,
+            uses,
+            name_width = name_width
+        );
+    }
+}
+
+fn location(position: &Position) -> Location {
+    (position.file, position.line)
+}
+
+// main.asm:3, or just 3 for a line of the top-level file unless `with_file` is set.
+fn format_location(sources: &SourceMap, (file, line): Location, with_file: bool) -> String {
+    match sources.file(file) {
+        Some(source) if file > 0 || with_file => format!("{}:{}", source.name, line),
+        _ => line.to_string(),
+    }
+}
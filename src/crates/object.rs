@@ -0,0 +1,467 @@
+// Relocatable objects and a linker, for programs whose modules are assembled one at a time.
+//
+//        .global MAIN                  ; main.asm
+//        .extern PRINT
+// MAIN:  CALL PRINT
+//
+//        .global PRINT                 ; print.asm
+// PRINT: ...
+//
+// iitb_cpu main.asm --object main.o
+// iitb_cpu print.asm --object print.o
+// iitb_cpu link -o program.hex main.o print.o
+//
+// - An object holds its .text and .data, each counted from address 0, the labels it exports
+//   with .global, the labels it uses with .extern, and a relocation for every field whose
+//   value depends on where the sections end up: IMM6 and IMM9 fields holding an address or a
+//   branch target, and .word values. Branches within a section need none.
+// - The linker places the .text of each object one after another from the text address, then
+//   the .data of each object from the data address, which defaults to the end of the .text.
+//   It reports undefined and multiply-defined labels, sections that do not fit, and fields
+//   that cannot hold their relocated value.
+//
+// The object file is text, one record per line:
+//
+//   iitb-object Pipelined
+//   .text 2
+//   C000 3205
+//   .data 1
+//   BEEF
+//   .global MAIN .text 0
+//   .extern PRINT
+//   .reloc .text 0 offset9 PRINT 0
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::crates::assembler::{instruction_to_binary, AssembleOptions};
+use crate::diagnostic::{has_errors, sort_by_position, Diagnostic, Severity};
+use crate::isa::Immediate;
+use crate::lexer::Processor;
+use crate::parser::{Parser, Relocation, RelocationKind, Section};
+use crate::source::SourceMap;
+
+const WORDS_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub processor: Processor,
+    pub text: Vec<u16>,
+    pub data: Vec<u16>,
+    pub globals: BTreeMap<String, (Section, u16)>, // label -> section and offset in it
+    pub externs: BTreeSet<String>,
+    pub relocations: Vec<Relocation>,
+    pub warnings: Vec<Diagnostic>, // from assembling it; not written to the file
+}
+
+// Where the linker places the sections.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkOptions {
+    pub text: u16,         // address of the first .text
+    pub data: Option<u16>, // address of the first .data; None for right after the last .text
+}
+
+// A linked program: the memory image and the address of every .global label.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub words: Vec<u16>,
+    pub symbol_table: BTreeMap<String, u16>,
+}
+
+// Assembles file 0 of `sources` into an object. Diagnostics are returned as by
+// `assemble_sources`.
+pub fn assemble_object(
+    sources: &mut SourceMap,
+    options: &AssembleOptions,
+) -> Result<Object, Vec<Diagnostic>> {
+    let mut parser = Parser::with_sources(
+        std::mem::take(sources),
+        options.processor,
+        &options.include_paths,
+        &options.defines,
+    );
+    parser.relocatable = true;
+    let mut diagnostics = parser.parse();
+
+    // without .org, .text and .data follow each other from address 0
+    let mut words = vec![0; parser.text_end as usize];
+    let mut place = |address: u16, word: u16| {
+        let address = address as usize;
+        if words.len() <= address {
+            words.resize(address + 1, 0);
+        }
+        words[address] = word;
+    };
+    let mut relocations = Vec::new();
+    for instruction in parser.instructions.iter() {
+        match instruction_to_binary(instruction) {
+            Ok(word) => place(instruction.address, word),
+            Err(message) => diagnostics.push(Diagnostic::error(message, instruction.span.clone())),
+        }
+        relocations.extend(instruction.relocation.clone());
+    }
+    for data in parser.data.iter() {
+        for (index, word) in data.words.iter().enumerate() {
+            place(data.address.wrapping_add(index as u16), *word);
+        }
+        relocations.extend(data.relocations.iter().cloned());
+    }
+    relocations.sort_by_key(|relocation| (relocation.section == Section::Data, relocation.offset));
+
+    sort_by_position(&mut diagnostics);
+    for diagnostic in diagnostics.iter_mut() {
+        parser.sources.localize(diagnostic);
+    }
+    *sources = std::mem::take(&mut parser.sources);
+    if has_errors(&diagnostics) {
+        return Err(diagnostics);
+    }
+
+    let data = words.split_off(parser.text_end as usize);
+    let globals = parser
+        .globals
+        .keys()
+        .map(|name| {
+            let section = parser.label_sections[name];
+            let mut offset = parser.symbol_table[name];
+            if section == Section::Data {
+                offset -= parser.text_end as u16;
+            }
+            (name.clone(), (section, offset))
+        })
+        .collect();
+    Ok(Object {
+        processor: options.processor,
+        text: words,
+        data,
+        globals,
+        externs: parser.externs.keys().cloned().collect(),
+        relocations,
+        warnings: diagnostics
+            .into_iter()
+            .filter(|diagnostic| diagnostic.severity != Severity::Error)
+            .collect(),
+    })
+}
+
+impl Object {
+    // The object file.
+    pub fn write(&self) -> String {
+        let mut output = String::new();
+        let _ = writeln!(output, "iitb-object {:?}", self.processor);
+        for (section, words) in [(Section::Text, &self.text), (Section::Data, &self.data)] {
+            let _ = writeln!(output, "{} {}", section.name(), words.len());
+            for line in words.chunks(WORDS_PER_LINE) {
+                let line: Vec<String> = line.iter().map(|word| format!("{:04X}", word)).collect();
+                let _ = writeln!(output, "{}", line.join(" "));
+            }
+        }
+        for (name, (section, offset)) in self.globals.iter() {
+            let _ = writeln!(output, ".global {} {} {}", name, section.name(), offset);
+        }
+        for name in self.externs.iter() {
+            let _ = writeln!(output, ".extern {}", name);
+        }
+        for relocation in self.relocations.iter() {
+            let _ = writeln!(
+                output,
+                ".reloc {} {} {} {} {}",
+                relocation.section.name(),
+                relocation.offset,
+                kind_name(relocation.kind),
+                relocation.symbol,
+                relocation.addend
+            );
+        }
+        output
+    }
+
+    // Reads an object file. Errors name the line they are on.
+    pub fn read(text: &str) -> Result<Object, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty());
+        let error = |number: usize, message: String| format!("line {}: {}", number, message);
+
+        let processor = match lines.next() {
+            Some((_, "iitb-object Pipelined")) => Processor::Pipelined,
+            Some((_, "iitb-object SingleCycle")) => Processor::SingleCycle,
+            Some((number, _)) => return Err(error(number, "not an iitb-object file".into())),
+            None => return Err("the object file is empty".to_string()),
+        };
+        let mut object = Object {
+            processor,
+            text: Vec::new(),
+            data: Vec::new(),
+            globals: BTreeMap::new(),
+            externs: BTreeSet::new(),
+            relocations: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let mut words_left: usize = 0; // still to read for the last section header
+        let mut section = Section::Text;
+        for (number, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if words_left > 0 {
+                for field in fields.iter() {
+                    let word = u16::from_str_radix(field, 16)
+                        .map_err(|_| error(number, format!("invalid word {}", field)))?;
+                    match section {
+                        Section::Text => object.text.push(word),
+                        Section::Data => object.data.push(word),
+                    }
+                }
+                words_left = words_left
+                    .checked_sub(fields.len())
+                    .ok_or_else(|| error(number, "more words than the section holds".into()))?;
+                continue;
+            }
+            let number_at = |index: usize| -> Result<i64, String> {
+                let field = fields
+                    .get(index)
+                    .ok_or_else(|| error(number, format!("{} is missing a field", fields[0])))?;
+                field
+                    .parse()
+                    .map_err(|_| error(number, format!("invalid number {}", field)))
+            };
+            let section_at = |index: usize| match fields.get(index) {
+                Some(&".text") => Ok(Section::Text),
+                Some(&".data") => Ok(Section::Data),
+                field => Err(error(
+                    number,
+                    format!("invalid section {}", field.unwrap_or(&"")),
+                )),
+            };
+            match fields[0] {
+                ".text" | ".data" => {
+                    section = section_at(0)?;
+                    words_left = number_at(1)? as usize;
+                }
+                ".global" if fields.len() == 4 => {
+                    let offset = number_at(3)? as u16;
+                    object
+                        .globals
+                        .insert(fields[1].to_string(), (section_at(2)?, offset));
+                }
+                ".extern" if fields.len() == 2 => {
+                    object.externs.insert(fields[1].to_string());
+                }
+                ".reloc" if fields.len() == 6 => {
+                    let kind = kind_from_name(fields[3])
+                        .ok_or_else(|| error(number, format!("invalid field {}", fields[3])))?;
+                    object.relocations.push(Relocation {
+                        section: section_at(1)?,
+                        offset: number_at(2)? as u16,
+                        kind,
+                        symbol: fields[4].to_string(),
+                        addend: number_at(5)?,
+                    });
+                }
+                record => return Err(error(number, format!("invalid record {}", record))),
+            }
+        }
+        if words_left > 0 {
+            return Err(format!("the object file ends {} words early", words_left));
+        }
+        Ok(object)
+    }
+}
+
+// Links `objects`, given as (name, object), into one memory image. The names are used in the
+// errors, which are all returned at once.
+pub fn link(objects: &[(String, Object)], options: &LinkOptions) -> Result<Image, Vec<String>> {
+    let mut errors = Vec::new();
+    if let Some((first_name, first)) = objects.first() {
+        for (name, object) in objects.iter().skip(1) {
+            if object.processor != first.processor {
+                errors.push(format!(
+                    "{} is assembled for the {:?} processor, but {} for the {:?} one",
+                    name, object.processor, first_name, first.processor
+                ));
+            }
+        }
+    }
+
+    // (.text, .data) address of each object
+    let mut bases = Vec::with_capacity(objects.len());
+    let mut text_end = options.text as usize;
+    for (_, object) in objects.iter() {
+        bases.push((text_end, 0));
+        text_end += object.text.len();
+    }
+    let data_start = options.data.map_or(text_end, usize::from);
+    let mut data_end = data_start;
+    for (base, (_, object)) in bases.iter_mut().zip(objects.iter()) {
+        base.1 = data_end;
+        data_end += object.data.len();
+    }
+    for (section, start, end) in [
+        (".text", options.text as usize, text_end),
+        (".data", data_start, data_end),
+    ] {
+        if end > 0x10000 {
+            errors.push(format!(
+                "{} runs from 0x{:04X} past the end of memory, to 0x{:X}",
+                section,
+                start,
+                end - 1
+            ));
+        }
+    }
+    if options.text as usize != text_end
+        && data_start != data_end
+        && (options.text as usize) < data_end
+        && data_start < text_end
+    {
+        errors.push(format!(
+            ".text (0x{:04X} to 0x{:04X}) and .data (0x{:04X} to 0x{:04X}) overlap",
+            options.text,
+            text_end - 1,
+            data_start,
+            data_end - 1
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // every .global, with the object that defines it
+    let mut symbols: BTreeMap<&str, (usize, &str)> = BTreeMap::new();
+    for ((name, object), (text, data)) in objects.iter().zip(bases.iter()) {
+        for (symbol, (section, offset)) in object.globals.iter() {
+            let base = match section {
+                Section::Text => text,
+                Section::Data => data,
+            };
+            match symbols.get(symbol.as_str()) {
+                Some((_, first)) => errors.push(format!(
+                    "{} is defined by both {} and {}",
+                    symbol, first, name
+                )),
+                None => {
+                    symbols.insert(symbol, (base + *offset as usize, name));
+                }
+            }
+        }
+    }
+
+    let mut words = vec![0; text_end.max(data_end)];
+    let mut undefined: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for ((name, object), (text, data)) in objects.iter().zip(bases.iter()) {
+        words[*text..*text + object.text.len()].copy_from_slice(&object.text);
+        words[*data..*data + object.data.len()].copy_from_slice(&object.data);
+        for relocation in object.relocations.iter() {
+            let target = match relocation.symbol.as_str() {
+                ".text" => *text as i64,
+                ".data" => *data as i64,
+                symbol => match symbols.get(symbol) {
+                    Some((address, _)) => *address as i64,
+                    None => {
+                        undefined.entry(symbol).or_default().insert(name);
+                        continue;
+                    }
+                },
+            } + relocation.addend;
+            let address = match relocation.section {
+                Section::Text => text,
+                Section::Data => data,
+            } + relocation.offset as usize;
+            if address >= words.len() {
+                errors.push(format!(
+                    "{}: relocation at {}+{} is outside the section",
+                    name,
+                    relocation.section.name(),
+                    relocation.offset
+                ));
+                continue;
+            }
+            match patch(words[address], relocation.kind, target, address as i64) {
+                Ok(word) => words[address] = word,
+                Err(message) => errors.push(format!(
+                    "{}: {}+{}: {} {}",
+                    name,
+                    relocation.section.name(),
+                    relocation.offset,
+                    relocation.symbol,
+                    message
+                )),
+            }
+        }
+    }
+    for (symbol, users) in undefined {
+        let users: Vec<&str> = users.into_iter().collect();
+        errors.push(format!(
+            "Undefined symbol {}, used by {}",
+            symbol,
+            users.join(", ")
+        ));
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(Image {
+        words,
+        symbol_table: symbols
+            .into_iter()
+            .map(|(symbol, (address, _))| (symbol.to_string(), address as u16))
+            .collect(),
+    })
+}
+
+// The word at `address` with the field of `kind` set to `target`, or to the offset to it.
+fn patch(word: u16, kind: RelocationKind, target: i64, address: i64) -> Result<u16, String> {
+    match kind {
+        RelocationKind::Word => match (-0x8000..=0xFFFF).contains(&target) {
+            true => Ok(target as u16),
+            false => Err(format!("is {}, which does not fit in a word", target)),
+        },
+        RelocationKind::Field(immediate) => {
+            let (value, what) = match immediate {
+                Immediate::Offset(_) => (target - address, "an offset"),
+                _ => (target, "a value"),
+            };
+            let range = immediate.range();
+            if !(*range.start() as i64..=*range.end() as i64).contains(&value) {
+                return Err(format!(
+                    "is {} of {}, which does not fit in IMM{} ({} to {})",
+                    what,
+                    value,
+                    immediate.bits(),
+                    range.start(),
+                    range.end()
+                ));
+            }
+            let mask = ((1u32 << immediate.bits()) - 1) as u16;
+            Ok(word & !mask | value as u16 & mask)
+        }
+    }
+}
+
+fn kind_name(kind: RelocationKind) -> String {
+    match kind {
+        RelocationKind::Word => "word".to_string(),
+        RelocationKind::Field(Immediate::Offset(bits)) => format!("offset{}", bits),
+        RelocationKind::Field(Immediate::Unsigned(bits)) => format!("uimm{}", bits),
+        RelocationKind::Field(immediate) => format!("imm{}", immediate.bits()),
+    }
+}
+
+fn kind_from_name(name: &str) -> Option<RelocationKind> {
+    if name == "word" {
+        return Some(RelocationKind::Word);
+    }
+    let field = |prefix: &str| name.strip_prefix(prefix)?.parse::<u32>().ok();
+    let immediate = if let Some(bits) = field("offset") {
+        Immediate::Offset(bits)
+    } else if let Some(bits) = field("uimm") {
+        Immediate::Unsigned(bits)
+    } else {
+        Immediate::Signed(field("imm")?)
+    };
+    (1..=9)
+        .contains(&immediate.bits())
+        .then_some(RelocationKind::Field(immediate))
+}
@@ -0,0 +1,402 @@
+// Memory image files: the words of an assembled program in the formats FPGA tools load, and
+// readers that load them back.
+//
+// - raw binary: two bytes per word, big-endian (bin) or little-endian (bin-le)
+// - Intel HEX (ihex): data records of up to 8 words. Addresses count 16-bit words, as Quartus
+//   expects for a memory that is 16 bits wide, and each word is written high byte first.
+// - Verilog .mem: one word per line, in binary for $readmemb (memb) or hex for $readmemh (memh)
+// - Altera/Intel MIF (mif) and Xilinx COE (coe), with binary data
+//
+// Every image starts at address 0; the words of a program are contiguous from there.
+
+use std::fmt::Write;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    RawBigEndian,
+    RawLittleEndian,
+    IntelHex,
+    MemBinary,
+    MemHex,
+    Mif,
+    Coe,
+}
+
+impl Format {
+    pub const ALL: [Format; 7] = [
+        Format::RawBigEndian,
+        Format::RawLittleEndian,
+        Format::IntelHex,
+        Format::MemBinary,
+        Format::MemHex,
+        Format::Mif,
+        Format::Coe,
+    ];
+
+    // The name given to --format.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Format::RawBigEndian => "bin",
+            Format::RawLittleEndian => "bin-le",
+            Format::IntelHex => "ihex",
+            Format::MemBinary => "memb",
+            Format::MemHex => "memh",
+            Format::Mif => "mif",
+            Format::Coe => "coe",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::ALL
+            .iter()
+            .find(|format| format.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    // The format a file name implies: .bin, .hex, .mem, .mif or .coe.
+    pub fn from_extension(path: &str) -> Option<Format> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "bin" => Some(Format::RawBigEndian),
+            "hex" => Some(Format::IntelHex),
+            "mem" => Some(Format::MemBinary),
+            "mif" => Some(Format::Mif),
+            "coe" => Some(Format::Coe),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// The image file for `words`.
+pub fn write(words: &[u16], format: Format) -> Vec<u8> {
+    match format {
+        Format::RawBigEndian => words.iter().flat_map(|word| word.to_be_bytes()).collect(),
+        Format::RawLittleEndian => words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+        Format::IntelHex => write_intel_hex(words).into_bytes(),
+        Format::MemBinary => write_mem(words, 2).into_bytes(),
+        Format::MemHex => write_mem(words, 16).into_bytes(),
+        Format::Mif => write_mif(words).into_bytes(),
+        Format::Coe => write_coe(words).into_bytes(),
+    }
+}
+
+// The words of an image file.
+pub fn read(bytes: &[u8], format: Format) -> Result<Vec<u16>, String> {
+    let text = || std::str::from_utf8(bytes).map_err(|_| format!("{} file is not text", format));
+    match format {
+        Format::RawBigEndian | Format::RawLittleEndian => {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(format!(
+                    "Raw image has an odd number of bytes ({}); words are 2 bytes",
+                    bytes.len()
+                ));
+            }
+            Ok(bytes
+                .chunks(2)
+                .map(|pair| match format {
+                    Format::RawBigEndian => u16::from_be_bytes([pair[0], pair[1]]),
+                    _ => u16::from_le_bytes([pair[0], pair[1]]),
+                })
+                .collect())
+        }
+        Format::IntelHex => read_intel_hex(text()?),
+        Format::MemBinary => read_mem(text()?, 2),
+        Format::MemHex => read_mem(text()?, 16),
+        Format::Mif => read_mif(text()?),
+        Format::Coe => read_coe(text()?),
+    }
+}
+
+const HEX_RECORD_WORDS: usize = 8;
+
+pub fn write_intel_hex(words: &[u16]) -> String {
+    let mut output = String::new();
+    for (index, chunk) in words.chunks(HEX_RECORD_WORDS).enumerate() {
+        let address = (index * HEX_RECORD_WORDS) as u16;
+        let data: Vec<u8> = chunk.iter().flat_map(|word| word.to_be_bytes()).collect();
+        output.push_str(&hex_record(address, 0x00, &data));
+    }
+    output.push_str(&hex_record(0, 0x01, &[]));
+    output
+}
+
+// :LLAAAATT<data>CC, where CC makes the bytes of the record sum to 0.
+fn hex_record(address: u16, record_type: u8, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend(address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend(data);
+    let checksum = bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg();
+    bytes.push(checksum);
+    let mut record = String::from(":");
+    for byte in bytes {
+        let _ = write!(record, "{:02X}", byte);
+    }
+    record.push('\n');
+    record
+}
+
+pub fn read_intel_hex(text: &str) -> Result<Vec<u16>, String> {
+    let mut words = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", index + 1, message);
+        let digits = line
+            .strip_prefix(':')
+            .ok_or_else(|| error("a record starts with ':'"))?;
+        if !digits.is_ascii() || !digits.len().is_multiple_of(2) || digits.len() < 10 {
+            return Err(error(
+                "a record is an even number of hex digits, at least 10",
+            ));
+        }
+        let bytes = (0..digits.len())
+            .step_by(2)
+            .map(|at| u8::from_str_radix(&digits[at..at + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| error("invalid hex digit"))?;
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(error("checksum does not match"));
+        }
+        let length = bytes[0] as usize;
+        if bytes.len() != length + 5 {
+            return Err(error("record length does not match its data"));
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        match bytes[3] {
+            0x00 => {
+                if !length.is_multiple_of(2) {
+                    return Err(error("data records hold whole 16-bit words"));
+                }
+                for (offset, pair) in bytes[4..4 + length].chunks(2).enumerate() {
+                    store(
+                        &mut words,
+                        address + offset,
+                        u16::from_be_bytes([pair[0], pair[1]]),
+                    )
+                    .map_err(|message| error(&message))?;
+                }
+            }
+            0x01 => return Ok(words),
+            record_type => {
+                return Err(error(&format!(
+                    "record type {:02X} is not supported",
+                    record_type
+                )))
+            }
+        }
+    }
+    Err("missing end-of-file record (:00000001FF)".to_string())
+}
+
+// Writes `word` at `address`, filling any gap before it with zeros.
+fn store(words: &mut Vec<u16>, address: usize, word: u16) -> Result<(), String> {
+    if address > 0xFFFF {
+        return Err(format!("address 0x{:X} is past 0xFFFF", address));
+    }
+    if words.len() <= address {
+        words.resize(address + 1, 0);
+    }
+    words[address] = word;
+    Ok(())
+}
+
+fn word_digits(word: u16, radix: u32) -> String {
+    match radix {
+        2 => format!("{:016b}", word),
+        _ => format!("{:04X}", word),
+    }
+}
+
+fn parse_word(digits: &str, radix: u32) -> Result<u16, String> {
+    u16::from_str_radix(digits, radix).map_err(|_| {
+        let kind = match radix {
+            2 => "binary",
+            10 => "decimal",
+            _ => "hexadecimal",
+        };
+        format!("{} is not a 16-bit {} word", digits, kind)
+    })
+}
+
+pub fn write_mem(words: &[u16], radix: u32) -> String {
+    let mut output = String::new();
+    for word in words {
+        output.push_str(&word_digits(*word, radix));
+        output.push('\n');
+    }
+    output
+}
+
+// Words separated by white space, with // comments and @ADDRESS (hex) to move on.
+pub fn read_mem(text: &str, radix: u32) -> Result<Vec<u16>, String> {
+    let mut words = Vec::new();
+    let mut address = 0;
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", index + 1, message);
+        let line = line.split("//").next().unwrap_or("");
+        for item in line.split_whitespace() {
+            if let Some(digits) = item.strip_prefix('@') {
+                address = usize::from_str_radix(digits, 16)
+                    .map_err(|_| error(format!("{} is not a hex address", item)))?;
+                continue;
+            }
+            let word = parse_word(&item.replace('_', ""), radix).map_err(error)?;
+            store(&mut words, address, word).map_err(error)?;
+            address += 1;
+        }
+    }
+    Ok(words)
+}
+
+pub fn write_mif(words: &[u16]) -> String {
+    let mut output = format!(
+        "DEPTH = {};\nWIDTH = 16;\nADDRESS_RADIX = HEX;\nDATA_RADIX = BIN;\nCONTENT\nBEGIN\n",
+        words.len().max(1)
+    );
+    for (address, word) in words.iter().enumerate() {
+        let _ = writeln!(output, "{:04X} : {};", address, word_digits(*word, 2));
+    }
+    output.push_str("END;\n");
+    output
+}
+
+// The header settings, then `ADDRESS : WORD WORD ...;` or `[FIRST..LAST] : WORD;` entries
+// between BEGIN and END. Comments start with --.
+pub fn read_mif(text: &str) -> Result<Vec<u16>, String> {
+    let mut words = Vec::new();
+    let mut address_radix = 16;
+    let mut data_radix = 16;
+    let mut in_content = false;
+    for (index, line) in text.lines().enumerate() {
+        let error = |message: String| format!("line {}: {}", index + 1, message);
+        let line = line.split("--").next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let upper = line.to_ascii_uppercase();
+        if !in_content {
+            if upper.split_whitespace().any(|word| word == "BEGIN") {
+                in_content = true;
+            } else if let Some((key, value)) = upper.split_once('=') {
+                let value = value.trim().trim_end_matches(';').trim();
+                match key.trim() {
+                    "WIDTH" if value != "16" => {
+                        return Err(error(format!("WIDTH is {}; words are 16 bits", value)))
+                    }
+                    "ADDRESS_RADIX" => address_radix = mif_radix(value).map_err(error)?,
+                    "DATA_RADIX" => data_radix = mif_radix(value).map_err(error)?,
+                    _ => {}
+                }
+            }
+            continue;
+        }
+        if upper.starts_with("END") {
+            return Ok(words);
+        }
+
+        let (addresses, data) = line
+            .trim_end_matches(';')
+            .split_once(':')
+            .ok_or_else(|| error("expected ADDRESS : WORD;".to_string()))?;
+        let parse_address = |digits: &str| {
+            usize::from_str_radix(digits.trim(), address_radix)
+                .map_err(|_| error(format!("{} is not an address", digits.trim())))
+        };
+        let addresses = addresses.trim();
+        let data: Vec<u16> = data
+            .split_whitespace()
+            .map(|digits| parse_word(digits, data_radix).map_err(error))
+            .collect::<Result<_, _>>()?;
+        if let Some(range) = addresses
+            .strip_prefix('[')
+            .and_then(|range| range.strip_suffix(']'))
+        {
+            let (first, last) = range
+                .split_once("..")
+                .ok_or_else(|| error(format!("{} is not a range", addresses)))?;
+            let word = match data.as_slice() {
+                [word] => *word,
+                _ => return Err(error("a range takes exactly one word".to_string())),
+            };
+            for address in parse_address(first)?..=parse_address(last)? {
+                store(&mut words, address, word).map_err(error)?;
+            }
+        } else {
+            let first = parse_address(addresses)?;
+            for (offset, word) in data.into_iter().enumerate() {
+                store(&mut words, first + offset, word).map_err(error)?;
+            }
+        }
+    }
+    Err("missing END;".to_string())
+}
+
+fn mif_radix(name: &str) -> Result<u32, String> {
+    match name {
+        "BIN" => Ok(2),
+        "OCT" => Ok(8),
+        "DEC" | "UNS" => Ok(10),
+        "HEX" => Ok(16),
+        _ => Err(format!("radix {} is not supported", name)),
+    }
+}
+
+pub fn write_coe(words: &[u16]) -> String {
+    let mut output =
+        String::from("memory_initialization_radix=2;\nmemory_initialization_vector=\n");
+    let vector: Vec<String> = words.iter().map(|word| word_digits(*word, 2)).collect();
+    output.push_str(&vector.join(",\n"));
+    output.push_str(";\n");
+    output
+}
+
+// memory_initialization_radix=R; then memory_initialization_vector= with the words separated
+// by commas or white space, up to a ;. Comments start with ;.
+pub fn read_coe(text: &str) -> Result<Vec<u16>, String> {
+    // comment lines start with ';', which also ends each setting
+    let text: String = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with(';'))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut radix = 10;
+    let mut words = None;
+    for statement in text.split(';') {
+        let Some((key, value)) = statement.split_once('=') else {
+            continue;
+        };
+        match key.trim().to_ascii_lowercase().as_str() {
+            "memory_initialization_radix" => {
+                radix = match value.trim() {
+                    "2" => 2,
+                    "10" => 10,
+                    "16" => 16,
+                    other => return Err(format!("radix {} is not supported", other)),
+                }
+            }
+            "memory_initialization_vector" => {
+                words = Some(
+                    value
+                        .split(|ch: char| ch == ',' || ch.is_whitespace())
+                        .filter(|digits| !digits.is_empty())
+                        .map(|digits| parse_word(digits, radix))
+                        .collect::<Result<Vec<u16>, String>>()?,
+                );
+            }
+            _ => {}
+        }
+    }
+    words.ok_or_else(|| "missing memory_initialization_vector".to_string())
+}
@@ -0,0 +1,161 @@
+// VHDL for the memory image of a program: a package with a constant array that a ROM or RAM
+// can be initialised from, or a complete ROM entity with a synchronous read port.
+//
+//     constant ROM : rom_type := (
+//         0 => "0011001000000101", -- LLI    main.asm:1  LLI R1, 5
+//         ...
+//         others => (others => '0')
+//     );
+//
+// - Every word is commented with its mnemonic (.word for data) and its source line.
+// - Words are zero-extended to WIDTH bits. DEPTH defaults to the next power of two that holds
+//   the whole image.
+
+use std::fmt::Write;
+
+use crate::crates::assembler::Program;
+use crate::source::SourceMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VhdlOptions {
+    pub name: String,         // of the package or entity, e.g. program_rom
+    pub width: usize,         // bits per word, at least 16
+    pub depth: Option<usize>, // words; None for the smallest power of two that fits
+    pub entity: bool,         // a ROM entity instead of a package
+}
+
+impl Default for VhdlOptions {
+    fn default() -> Self {
+        VhdlOptions {
+            name: "program_rom".to_string(),
+            width: 16,
+            depth: None,
+            entity: false,
+        }
+    }
+}
+
+pub fn write_vhdl(
+    program: &Program,
+    sources: &SourceMap,
+    options: &VhdlOptions,
+) -> Result<String, String> {
+    if options.width < 16 {
+        return Err(format!(
+            "A width of {} bits cannot hold the 16-bit words of the program",
+            options.width
+        ));
+    }
+    let depth = options
+        .depth
+        .unwrap_or_else(|| program.words.len().max(1).next_power_of_two());
+    if depth < program.words.len() || depth == 0 {
+        return Err(format!(
+            "A depth of {} words cannot hold the {} words of the program",
+            depth,
+            program.words.len()
+        ));
+    }
+    let valid_name = options
+        .name
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic())
+        && options
+            .name
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+    if !valid_name {
+        return Err(format!("{} is not a VHDL identifier", options.name));
+    }
+
+    let mut output = String::new();
+    let source_name = sources.file(0).map_or("<input>", |file| file.name.as_str());
+    let _ = writeln!(
+        output,
+        "-- {}: generated by iitb_cpu from {}",
+        options.name, source_name
+    );
+    output.push_str("library ieee;\nuse ieee.std_logic_1164.all;\n");
+    if options.entity {
+        output.push_str("use ieee.numeric_std.all;\n\n");
+        let address_width = (usize::BITS - (depth - 1).leading_zeros()).max(1);
+        let _ = write!(
+            output,
+            "entity {name} is\n\
+             \x20   port (\n\
+             \x20       clk     : in  std_logic;\n\
+             \x20       address : in  std_logic_vector({address} downto 0);\n\
+             \x20       data    : out std_logic_vector({width} downto 0)\n\
+             \x20   );\n\
+             end entity {name};\n\n\
+             architecture rtl of {name} is\n",
+            name = options.name,
+            address = address_width - 1,
+            width = options.width - 1,
+        );
+        write_constants(&mut output, program, sources, options.width, depth);
+        output.push_str(
+            "begin\n\
+             \x20   process (clk)\n\
+             \x20   begin\n\
+             \x20       if rising_edge(clk) then\n\
+             \x20           data <= ROM(to_integer(unsigned(address)));\n\
+             \x20       end if;\n\
+             \x20   end process;\n",
+        );
+        let _ = writeln!(output, "end architecture rtl;");
+    } else {
+        let _ = writeln!(output, "\npackage {} is", options.name);
+        write_constants(&mut output, program, sources, options.width, depth);
+        let _ = writeln!(output, "end package {};", options.name);
+    }
+    Ok(output)
+}
+
+// The declarations shared by the package and the architecture.
+fn write_constants(
+    output: &mut String,
+    program: &Program,
+    sources: &SourceMap,
+    width: usize,
+    depth: usize,
+) {
+    let _ = write!(
+        output,
+        "    constant ROM_WIDTH : natural := {};\n\
+         \x20   constant ROM_DEPTH : natural := {};\n\
+         \x20   type rom_type is array (0 to ROM_DEPTH - 1) of \
+         std_logic_vector(ROM_WIDTH - 1 downto 0);\n\
+         \x20   constant ROM : rom_type := (\n",
+        width, depth
+    );
+    for (address, word) in program.words.iter().enumerate() {
+        let address = address as u16;
+        // words between blocks that nothing was placed at are left to `others`
+        let Some(&line) = program.line_numbers.get(&address) else {
+            continue;
+        };
+        let mnemonic = program
+            .opcodes
+            .get(&address)
+            .map_or(".word", |opcode| opcode.mnemonic());
+        let file = program.files.get(&address).and_then(|id| sources.file(*id));
+        let (file_name, text) = match file {
+            Some(file) => (file.name.as_str(), file.line(line).unwrap_or("").trim()),
+            None => ("<input>", ""),
+        };
+        let _ = writeln!(
+            output,
+            "        {} => \"{:0width$b}\", -- {:<6} {}:{}  {}",
+            address,
+            word,
+            mnemonic,
+            file_name,
+            line,
+            text,
+            width = width
+        );
+    }
+    output.push_str("        others => (others => '0')\n    );\n");
+}
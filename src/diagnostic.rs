@@ -0,0 +1,149 @@
+// Diagnostics reported while parsing and assembling a program.
+//
+// A diagnostic points at a byte range of a source file and is rendered rustc-style:
+//
+// error: Expected register
+//  --> ./src/test/test.asm:9:13
+//   |
+// 9 |     SW R3 , RA , 100
+//   |             ^^
+//   = help: registers are R0 to R7
+
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => f.write_str("error"),
+            Severity::Warning => f.write_str("warning"),
+            Severity::Note => f.write_str("note"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub span: Range<usize>, // byte offsets into the source file
+    pub file: usize,        // id of the source file, see `source::SourceMap`
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Range<usize>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            span,
+            file: 0,
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Range<usize>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            span,
+            file: 0,
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    pub fn note(message: impl Into<String>, span: Range<usize>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Note,
+            span,
+            file: 0,
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // 1-based line and column (in characters) of the start of the span.
+    pub fn line_and_column(&self, source: &str) -> (usize, usize) {
+        let start = char_boundary(source, self.span.start);
+        let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line = source[..start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+        (line, column)
+    }
+
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let (line, column) = self.line_and_column(source);
+        let start = char_boundary(source, self.span.start);
+        let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |index| start + index);
+        let text = &source[line_start..line_end];
+
+        // underline at least one character, and never past the end of the line
+        let end = char_boundary(source, self.span.end).clamp(start, line_end);
+        let width = source[start..end].chars().count().max(1);
+
+        let gutter = " ".repeat(line.to_string().len());
+        let mut output = format!("{}: {}\n", self.severity, self.message);
+        output.push_str(&format!(
+            "{}--> {}:{}:{}\n",
+            gutter, file_name, line, column
+        ));
+        output.push_str(&format!("{} |\n", gutter));
+        output.push_str(&format!("{} | {}\n", line, text));
+        output.push_str(&format!(
+            "{} | {}{}\n",
+            gutter,
+            " ".repeat(column - 1),
+            "^".repeat(width)
+        ));
+        if let Some(help) = &self.help {
+            output.push_str(&format!("{} = help: {}\n", gutter, help));
+        }
+        output
+    }
+}
+
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics.iter().any(Diagnostic::is_error)
+}
+
+// Puts diagnostics in source order. A note stays right after the diagnostic it belongs to.
+pub fn sort_by_position(diagnostics: &mut Vec<Diagnostic>) {
+    let mut groups: Vec<Vec<Diagnostic>> = Vec::new();
+    for diagnostic in diagnostics.drain(..) {
+        match groups.last_mut() {
+            Some(group) if diagnostic.severity == Severity::Note => group.push(diagnostic),
+            _ => groups.push(vec![diagnostic]),
+        }
+    }
+    groups.sort_by_key(|group| (group[0].file, group[0].span.start));
+    diagnostics.extend(groups.into_iter().flatten());
+}
+
+// Spans come from the lexer, but a hand-built one may point past the end of the source or
+// into the middle of a character; move it back to the nearest character boundary.
+fn char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
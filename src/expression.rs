@@ -0,0 +1,386 @@
+// Constant expressions in operands, e.g. BUF+3, (1<<5)-1, END-START or lo(TABLE).
+//
+// Operators, from loosest to tightest binding:
+//   |   ^   &   << >>   + -   * / %   unary - + ~
+// lo(X) and hi(X) are the low and high bytes of X, as loaded by LLI and LHI.
+//
+// Expressions are evaluated in 64 bits once every label has an address; the caller checks the
+// result against the width of its field. A value also counts the label addresses it is made
+// of: BUF+3 is an address, END-START and (1<<5)-1 are plain numbers. Branch and jump targets
+// use this to tell a label apart from a literal offset.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{SpannedToken, Token};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Operator {
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+impl Operator {
+    pub const fn symbol(self) -> &'static str {
+        match self {
+            Operator::Plus => "+",
+            Operator::Minus => "-",
+            Operator::Star => "*",
+            Operator::Slash => "/",
+            Operator::Percent => "%",
+            Operator::ShiftLeft => "<<",
+            Operator::ShiftRight => ">>",
+            Operator::And => "&",
+            Operator::Or => "|",
+            Operator::Xor => "^",
+            Operator::Not => "~",
+        }
+    }
+
+    // Binding strength as a binary operator; higher binds tighter. `~` is unary only.
+    const fn precedence(self) -> Option<u8> {
+        match self {
+            Operator::Or => Some(1),
+            Operator::Xor => Some(2),
+            Operator::And => Some(3),
+            Operator::ShiftLeft | Operator::ShiftRight => Some(4),
+            Operator::Plus | Operator::Minus => Some(5),
+            Operator::Star | Operator::Slash | Operator::Percent => Some(6),
+            Operator::Not => None,
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionKind {
+    Number(i64),
+    Symbol(String),
+    Unary(Operator, Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    Low(Box<Expression>),
+    High(Box<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Value {
+    pub value: i64,
+    pub labels: i32, // label addresses added minus label addresses subtracted
+}
+
+impl Value {
+    pub fn number(value: i64) -> Value {
+        Value { value, labels: 0 }
+    }
+
+    pub fn address(address: u16) -> Value {
+        Value {
+            value: address as i64,
+            labels: 1,
+        }
+    }
+}
+
+impl Expression {
+    // Parses the longest expression at the start of `tokens` and returns it with the number
+    // of tokens it took. The expression ends at the first token that cannot continue it,
+    // usually a comma or the end of the line.
+    pub fn parse(tokens: &[SpannedToken]) -> Result<(Expression, usize), Diagnostic> {
+        let mut parser = ExpressionParser {
+            tokens,
+            position: 0,
+        };
+        let expression = parser.binary(1)?;
+        Ok((expression, parser.position))
+    }
+
+    // Every symbol the expression names, in order.
+    pub fn symbols(&self) -> Vec<(&str, Range<usize>)> {
+        let mut symbols = Vec::new();
+        self.collect_symbols(&mut symbols);
+        symbols
+    }
+
+    fn collect_symbols<'a>(&'a self, symbols: &mut Vec<(&'a str, Range<usize>)>) {
+        match &self.kind {
+            ExpressionKind::Number(_) => {}
+            ExpressionKind::Symbol(name) => symbols.push((name, self.span.clone())),
+            ExpressionKind::Unary(_, operand)
+            | ExpressionKind::Low(operand)
+            | ExpressionKind::High(operand) => operand.collect_symbols(symbols),
+            ExpressionKind::Binary(_, left, right) => {
+                left.collect_symbols(symbols);
+                right.collect_symbols(symbols);
+            }
+        }
+    }
+
+    // Evaluates the expression; `lookup` gives the value of a constant or label.
+    pub fn evaluate(&self, lookup: &dyn Fn(&str) -> Option<Value>) -> Result<Value, Diagnostic> {
+        let overflow = || {
+            Diagnostic::error(
+                format!("Expression {} overflows 64 bits", self),
+                self.span.clone(),
+            )
+        };
+
+        match &self.kind {
+            ExpressionKind::Number(value) => Ok(Value::number(*value)),
+            ExpressionKind::Symbol(name) => lookup(name).ok_or_else(|| {
+                Diagnostic::error(format!("Undefined label: {}", name), self.span.clone())
+            }),
+            ExpressionKind::Unary(operator, operand) => {
+                let operand = operand.evaluate(lookup)?;
+                match operator {
+                    Operator::Minus => Ok(Value {
+                        value: operand.value.checked_neg().ok_or_else(overflow)?,
+                        labels: -operand.labels,
+                    }),
+                    Operator::Not => Ok(Value::number(!operand.value)),
+                    _ => Ok(operand),
+                }
+            }
+            ExpressionKind::Low(operand) => {
+                Ok(Value::number(operand.evaluate(lookup)?.value & 0xFF))
+            }
+            ExpressionKind::High(operand) => {
+                Ok(Value::number((operand.evaluate(lookup)?.value >> 8) & 0xFF))
+            }
+            ExpressionKind::Binary(operator, left_expression, right_expression) => {
+                let left = left_expression.evaluate(lookup)?;
+                let right = right_expression.evaluate(lookup)?;
+                let (a, b) = (left.value, right.value);
+                let shift = || match u32::try_from(b) {
+                    Ok(shift) if shift < 64 => Ok(shift),
+                    _ => Err(Diagnostic::error(
+                        format!("Shift amount {} is out of range (0 to 63)", b),
+                        right_expression.span.clone(),
+                    )),
+                };
+                let divisor = || {
+                    if b == 0 {
+                        Err(Diagnostic::error(
+                            format!("Division by zero in {}", self),
+                            right_expression.span.clone(),
+                        ))
+                    } else {
+                        Ok(b)
+                    }
+                };
+
+                let (value, labels) = match operator {
+                    Operator::Plus => (a.checked_add(b), left.labels + right.labels),
+                    Operator::Minus => (a.checked_sub(b), left.labels - right.labels),
+                    Operator::Star => (a.checked_mul(b), 0),
+                    Operator::Slash => (a.checked_div(divisor()?), 0),
+                    Operator::Percent => (a.checked_rem(divisor()?), 0),
+                    Operator::ShiftLeft => {
+                        let shift = shift()?;
+                        // the bits shifted out must all be copies of the sign bit
+                        let value = a << shift;
+                        ((value >> shift == a).then_some(value), 0)
+                    }
+                    Operator::ShiftRight => (Some(a >> shift()?), 0),
+                    Operator::And => (Some(a & b), 0),
+                    Operator::Or => (Some(a | b), 0),
+                    Operator::Xor => (Some(a ^ b), 0),
+                    Operator::Not => (None, 0),
+                };
+                Ok(Value {
+                    value: value.ok_or_else(overflow)?,
+                    labels,
+                })
+            }
+        }
+    }
+}
+
+// Prints the expression back in source syntax, with parentheses only where they are needed.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExpressionKind::Number(value) => write!(f, "{}", value),
+            ExpressionKind::Symbol(name) => f.write_str(name),
+            ExpressionKind::Unary(operator, operand) => match operand.kind {
+                ExpressionKind::Binary(..) => write!(f, "{}({})", operator, operand),
+                _ => write!(f, "{}{}", operator, operand),
+            },
+            ExpressionKind::Low(operand) => write!(f, "lo({})", operand),
+            ExpressionKind::High(operand) => write!(f, "hi({})", operand),
+            ExpressionKind::Binary(operator, left, right) => {
+                let precedence = operator.precedence().unwrap_or(0);
+                let binds_looser = |operand: &Expression, right: bool| match &operand.kind {
+                    ExpressionKind::Binary(inner, ..) => {
+                        let inner = inner.precedence().unwrap_or(0);
+                        inner < precedence || (right && inner == precedence)
+                    }
+                    ExpressionKind::Number(value) => right && *value < 0,
+                    _ => false,
+                };
+                // operators are left-associative, so a right operand of equal precedence needs
+                // parentheses: A-(B-C), and so does a negative number: A-(-1)
+                if binds_looser(left, false) {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                f.write_str(operator.symbol())?;
+                if binds_looser(right, true) {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+        }
+    }
+}
+
+// A precedence-climbing parser over the tokens of one operand.
+struct ExpressionParser<'a> {
+    tokens: &'a [SpannedToken],
+    position: usize,
+}
+
+impl ExpressionParser<'_> {
+    fn peek(&self) -> Option<&SpannedToken> {
+        self.tokens.get(self.position)
+    }
+
+    // Where a missing operand is reported: the next token, or just past the last one.
+    fn span_here(&self) -> Range<usize> {
+        match self.peek() {
+            Some(token) if !matches!(token.token, Token::NewLine | Token::EOF) => {
+                token.span.range()
+            }
+            _ => {
+                let end = match self.position.checked_sub(1) {
+                    Some(previous) => self.tokens[previous].span.end,
+                    None => self.tokens.first().map_or(0, |token| token.span.start),
+                };
+                end..end
+            }
+        }
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expression, Diagnostic> {
+        let mut left = self.unary()?;
+        while let Some(Token::Operator(operator)) = self.peek().map(|token| &token.token) {
+            let operator = *operator;
+            let precedence = match operator.precedence() {
+                Some(precedence) if precedence >= min_precedence => precedence,
+                _ => break,
+            };
+            self.position += 1;
+            let right = self.binary(precedence + 1)?;
+            let span = left.span.start..right.span.end;
+            left = Expression {
+                kind: ExpressionKind::Binary(operator, Box::new(left), Box::new(right)),
+                span,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, Diagnostic> {
+        match self.peek().map(|token| (&token.token, token.span)) {
+            Some((Token::Operator(operator), span))
+                if matches!(operator, Operator::Minus | Operator::Plus | Operator::Not) =>
+            {
+                let operator = *operator;
+                self.position += 1;
+                let operand = self.unary()?;
+                let span = span.start..operand.span.end;
+                // fold a negative literal, so -5 stays a number
+                if let (Operator::Minus, ExpressionKind::Number(value)) = (operator, &operand.kind)
+                {
+                    return Ok(Expression {
+                        kind: ExpressionKind::Number(-value),
+                        span,
+                    });
+                }
+                Ok(Expression {
+                    kind: ExpressionKind::Unary(operator, Box::new(operand)),
+                    span,
+                })
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expression, Diagnostic> {
+        let token = match self.peek() {
+            Some(token) => token.clone(),
+            None => return Err(Diagnostic::error("Expected a value", self.span_here())),
+        };
+        match token.token {
+            Token::Number(value) => {
+                self.position += 1;
+                Ok(Expression {
+                    kind: ExpressionKind::Number(value as i64),
+                    span: token.span.range(),
+                })
+            }
+            Token::Identifier(name) => {
+                self.position += 1;
+                let function = name.to_ascii_lowercase();
+                let is_call = self.peek().map(|token| &token.token) == Some(&Token::LeftParen);
+                if is_call && (function == "lo" || function == "hi") {
+                    let operand = self.parenthesised()?;
+                    let span = token.span.start..self.tokens[self.position - 1].span.end;
+                    let kind = if function == "lo" {
+                        ExpressionKind::Low(Box::new(operand))
+                    } else {
+                        ExpressionKind::High(Box::new(operand))
+                    };
+                    return Ok(Expression { kind, span });
+                }
+                Ok(Expression {
+                    kind: ExpressionKind::Symbol(name),
+                    span: token.span.range(),
+                })
+            }
+            Token::LeftParen => {
+                let mut expression = self.parenthesised()?;
+                // keep the parentheses in the span, so diagnostics underline all of them
+                expression.span = token.span.start..self.tokens[self.position - 1].span.end;
+                Ok(expression)
+            }
+            Token::Error(message) => Err(Diagnostic::error(message, token.span.range())),
+            _ => Err(Diagnostic::error("Expected a value", self.span_here())),
+        }
+    }
+
+    // ( expression ), starting at the '('.
+    fn parenthesised(&mut self) -> Result<Expression, Diagnostic> {
+        self.position += 1;
+        let expression = self.binary(1)?;
+        match self.peek().map(|token| &token.token) {
+            Some(Token::RightParen) => {
+                self.position += 1;
+                Ok(expression)
+            }
+            _ => Err(Diagnostic::error("Expected ')'", self.span_here())),
+        }
+    }
+}
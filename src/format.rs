@@ -0,0 +1,228 @@
+// A formatter for assembly source, working on the token stream of the lexer.
+//
+// LOOP:  ADA   R3, R3, R1    ; add the next term
+//        ADI   R2, R2, -1
+//        BEQ   R2, R0, DONE
+//
+// - Labels, mnemonics, operands and trailing comments each start in a column of their own,
+//   wide enough for the longest entry in the file.
+// - Instruction and pseudo-instruction mnemonics and registers are upper-cased, directives
+//   lower-cased. Numbers, names and comments are kept as written.
+// - Operands are separated by ", ", and binary operators in expressions by spaces.
+// - A line the lexer cannot read is kept as it is, so formatting never changes what a program
+//   assembles to.
+
+use crate::expression::Operator;
+use crate::lexer::{Lexer, Processor, SpannedToken, Token, TokenStream};
+use crate::pseudo::PseudoOp;
+
+// One line, split into its columns.
+#[derive(Debug, Clone, Default)]
+struct Line {
+    label: String,
+    mnemonic: String,
+    operands: String,
+    comment: String,
+    directive: bool,          // directives do not widen the mnemonic column
+    verbatim: Option<String>, // a line that is kept as written
+    indented: bool,           // whether a comment-only line was indented
+}
+
+// Formats a whole source file. This is what `fmt` writes, and what the editor calls on save.
+pub fn format(source: &str, processor: Processor) -> String {
+    let lines: Vec<Line> = lex(source, processor)
+        .iter()
+        .zip(source.split('\n').chain(std::iter::repeat("")))
+        .map(|(tokens, text)| split(tokens, text, source))
+        .collect();
+
+    let formatted = |line: &Line| line.verbatim.is_none() && !line.mnemonic.is_empty();
+    let label_width = lines
+        .iter()
+        .map(|line| line.label.len() + 1)
+        .filter(|width| *width > 1)
+        .max()
+        .unwrap_or(0);
+    let mnemonic_width = lines
+        .iter()
+        .filter(|line| formatted(line) && !line.directive)
+        .map(|line| line.mnemonic.len() + 1)
+        .max()
+        .unwrap_or(0);
+    let code = |line: &Line| {
+        let mut code = pad(&line.label, label_width);
+        if !line.mnemonic.is_empty() {
+            // a directive longer than the column still gets a space
+            code.push_str(&pad(
+                &line.mnemonic,
+                mnemonic_width.max(line.mnemonic.len() + 1),
+            ));
+            code.push_str(&line.operands);
+        }
+        code.trim_end().to_string()
+    };
+    let comment_column = lines
+        .iter()
+        .filter(|line| line.verbatim.is_none() && !line.comment.is_empty())
+        .map(|line| code(line).len())
+        .filter(|width| *width > 0)
+        .max()
+        .map_or(0, |width| width + 1);
+
+    let mut output: Vec<String> = lines
+        .iter()
+        .map(|line| {
+            if let Some(text) = &line.verbatim {
+                return text.clone();
+            }
+            let code = code(line);
+            match (code.is_empty(), line.comment.is_empty()) {
+                (_, true) => code,
+                (true, false) if line.indented => pad("", label_width) + &line.comment,
+                (true, false) => line.comment.clone(),
+                (false, false) => pad(&code, comment_column) + &line.comment,
+            }
+        })
+        .collect();
+    while output.last().is_some_and(|line| line.is_empty()) {
+        output.pop();
+    }
+    if output.is_empty() {
+        return String::new();
+    }
+    output.join("\n") + "\n"
+}
+
+// Whether `source` is already formatted, for `fmt --check`.
+pub fn is_formatted(source: &str, processor: Processor) -> bool {
+    format(source, processor) == source
+}
+
+fn lex(source: &str, processor: Processor) -> Vec<Vec<SpannedToken>> {
+    let mut lexer = Lexer::new(source);
+    let mut token_stream = TokenStream::new();
+    loop {
+        let token = lexer.next_token(processor);
+        token_stream.add(token.clone());
+        if token.token == Token::EOF {
+            break;
+        }
+    }
+    token_stream.tokens_by_line
+}
+
+// Splits the tokens of one line, whose text is `text`, into columns.
+fn split(tokens: &[SpannedToken], text: &str, source: &str) -> Line {
+    let text = text.trim_end();
+    let tokens: Vec<&SpannedToken> = tokens
+        .iter()
+        .filter(|token| !matches!(token.token, Token::NewLine | Token::EOF))
+        .collect();
+    let verbatim = || Line {
+        verbatim: Some(text.to_string()),
+        ..Line::default()
+    };
+    let misplaced_label = tokens
+        .iter()
+        .skip(1)
+        .any(|token| matches!(token.token, Token::Label(_)));
+    if misplaced_label
+        || tokens
+            .iter()
+            .any(|token| matches!(token.token, Token::Error(_)))
+    {
+        return verbatim();
+    }
+
+    let mut line = Line {
+        indented: text.starts_with(char::is_whitespace),
+        ..Line::default()
+    };
+    let mut rest = &tokens[..];
+    if let Some((last, code)) = rest.split_last() {
+        if matches!(last.token, Token::Comment(_)) {
+            line.comment = source[last.span.range()].trim_end().to_string();
+            rest = code;
+        }
+    }
+    if let Some((first, operands)) = rest.split_first() {
+        if let Token::Label(label) = &first.token {
+            line.label = label.clone();
+            rest = operands;
+        }
+    }
+    if let Some((first, operands)) = rest.split_first() {
+        line.mnemonic = match &first.token {
+            Token::Opcode(opcode) => opcode.mnemonic().to_string(),
+            Token::Directive(directive) => {
+                line.directive = true;
+                directive.name().to_string()
+            }
+            Token::Identifier(name) => match PseudoOp::from_mnemonic(name) {
+                Some(op) => op.mnemonic().to_string(),
+                None => name.clone(), // a macro call
+            },
+            _ => return verbatim(),
+        };
+        line.operands = operands_text(operands, source);
+    }
+    line
+}
+
+// The operands of a statement, spaced the canonical way.
+fn operands_text(tokens: &[&SpannedToken], source: &str) -> String {
+    let mut text = String::new();
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 {
+            let previous = &tokens[index - 1].token;
+            let space = match (previous, &token.token) {
+                (_, Token::Comma | Token::RightParen | Token::RightBrace) => false,
+                (Token::Comma, _) => true,
+                (Token::LeftParen | Token::LeftBrace, _) => false,
+                (Token::Identifier(_), Token::LeftParen) => false, // a call, e.g. lo(X)
+                (Token::Operator(_), _) => operator_kind(tokens, index - 1) == Kind::Binary,
+                (_, Token::Operator(_)) => operator_kind(tokens, index) == Kind::Binary,
+                _ => true,
+            };
+            if space {
+                text.push(' ');
+            }
+        }
+        match &token.token {
+            Token::Register(register) => text.push_str(&format!("R{}", register)),
+            Token::Opcode(opcode) => text.push_str(opcode.mnemonic()),
+            Token::Directive(directive) => text.push_str(directive.name()),
+            Token::Operator(operator) => text.push_str(operator.symbol()),
+            _ => text.push_str(&source[token.span.range()]),
+        }
+    }
+    text
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Kind {
+    Unary,
+    Binary,
+    Range, // the - of a register range, e.g. R2-R4
+}
+
+// Binary operators come after an operand, and get a space on either side.
+fn operator_kind(tokens: &[&SpannedToken], index: usize) -> Kind {
+    let previous = index.checked_sub(1).map(|index| &tokens[index].token);
+    let next = tokens.get(index + 1).map(|token| &token.token);
+    match (previous, &tokens[index].token, next) {
+        (Some(Token::Register(_)), Token::Operator(Operator::Minus), Some(Token::Register(_))) => {
+            Kind::Range
+        }
+        (
+            Some(Token::Identifier(_) | Token::Number(_) | Token::Register(_) | Token::RightParen),
+            _,
+            _,
+        ) => Kind::Binary,
+        _ => Kind::Unary,
+    }
+}
+
+fn pad(text: &str, width: usize) -> String {
+    format!("{:width$}", text, width = width)
+}
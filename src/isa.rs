@@ -0,0 +1,304 @@
+// The instruction sets of the IITB processors, described in one place.
+//
+// - 26 instructions for the Pipelined Architecture (EE309)
+// - 14 instructions for the Single Cycle Architecture (EE224)
+//
+// Every opcode has one descriptor per processor that implements it. The lexer, parser,
+// assembler and disassembler are all driven from these tables, so adding or fixing an
+// instruction only ever touches this file.
+
+use std::ops::RangeInclusive;
+
+use crate::lexer::Processor;
+
+use Immediate::{Offset, RegisterMask, Signed, Unsigned};
+use InstructionClass::{Alu, Branch, Jump, Load, Store};
+use Operands::{RaImm, RaRb, RaRbImm, RaRbRc};
+
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub enum Opcode {
+    Ada,
+    Adc,
+    Adz,
+    Awc,
+    Aca,
+    Acc,
+    Acz,
+    Acw,
+    Adi,
+    Ndu,
+    Ndc,
+    Ndz,
+    Ncu,
+    Ncc,
+    Ncz,
+    Lli,
+    Lw,
+    Sw,
+    Lm,
+    Sm,
+    Beq,
+    Blt,
+    Ble,
+    Jal,
+    Jlr,
+    Jri,
+    Add,
+    Sub,
+    Mul,
+    And,
+    Ora,
+    Imp,
+    Lhi,
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 33] = [
+        Opcode::Ada,
+        Opcode::Adc,
+        Opcode::Adz,
+        Opcode::Awc,
+        Opcode::Aca,
+        Opcode::Acc,
+        Opcode::Acz,
+        Opcode::Acw,
+        Opcode::Adi,
+        Opcode::Ndu,
+        Opcode::Ndc,
+        Opcode::Ndz,
+        Opcode::Ncu,
+        Opcode::Ncc,
+        Opcode::Ncz,
+        Opcode::Lli,
+        Opcode::Lw,
+        Opcode::Sw,
+        Opcode::Lm,
+        Opcode::Sm,
+        Opcode::Beq,
+        Opcode::Blt,
+        Opcode::Ble,
+        Opcode::Jal,
+        Opcode::Jlr,
+        Opcode::Jri,
+        Opcode::Add,
+        Opcode::Sub,
+        Opcode::Mul,
+        Opcode::And,
+        Opcode::Ora,
+        Opcode::Imp,
+        Opcode::Lhi,
+    ];
+
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Ada => "ADA",
+            Opcode::Adc => "ADC",
+            Opcode::Adz => "ADZ",
+            Opcode::Awc => "AWC",
+            Opcode::Aca => "ACA",
+            Opcode::Acc => "ACC",
+            Opcode::Acz => "ACZ",
+            Opcode::Acw => "ACW",
+            Opcode::Adi => "ADI",
+            Opcode::Ndu => "NDU",
+            Opcode::Ndc => "NDC",
+            Opcode::Ndz => "NDZ",
+            Opcode::Ncu => "NCU",
+            Opcode::Ncc => "NCC",
+            Opcode::Ncz => "NCZ",
+            Opcode::Lli => "LLI",
+            Opcode::Lw => "LW",
+            Opcode::Sw => "SW",
+            Opcode::Lm => "LM",
+            Opcode::Sm => "SM",
+            Opcode::Beq => "BEQ",
+            Opcode::Blt => "BLT",
+            Opcode::Ble => "BLE",
+            Opcode::Jal => "JAL",
+            Opcode::Jlr => "JLR",
+            Opcode::Jri => "JRI",
+            Opcode::Add => "ADD",
+            Opcode::Sub => "SUB",
+            Opcode::Mul => "MUL",
+            Opcode::And => "AND",
+            Opcode::Ora => "ORA",
+            Opcode::Imp => "IMP",
+            Opcode::Lhi => "LHI",
+        }
+    }
+
+    // Case-insensitive lookup of a mnemonic, regardless of processor.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Opcode> {
+        Opcode::ALL
+            .iter()
+            .find(|opcode| opcode.mnemonic().eq_ignore_ascii_case(mnemonic))
+            .copied()
+    }
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+// The operands written after the mnemonic, in source order.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Operands {
+    RaRbRc,  // RA, RB, RC
+    RaRbImm, // RA, RB, IMM
+    RaRb,    // RA, RB
+    RaImm,   // RA, IMM
+}
+
+// How the immediate operand is stored in the instruction word.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Immediate {
+    None,
+    Signed(u32),   // two's complement constant or displacement of the given width
+    Unsigned(u32), // zero-extended constant of the given width
+    Offset(u32),   // signed PC-relative target, counted in instructions
+    RegisterMask,  // 8 bits corresponding to R0 to R7, left to right
+}
+
+impl Immediate {
+    pub fn bits(self) -> u32 {
+        match self {
+            Immediate::None => 0,
+            Immediate::Signed(bits) | Immediate::Unsigned(bits) | Immediate::Offset(bits) => bits,
+            Immediate::RegisterMask => 8,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, Immediate::Signed(_) | Immediate::Offset(_))
+    }
+
+    // The values that can be written for this immediate.
+    pub fn range(self) -> RangeInclusive<i32> {
+        let bits = self.bits();
+        match self {
+            Immediate::None => 0..=0,
+            _ if self.is_signed() => -(1 << (bits - 1))..=(1 << (bits - 1)) - 1,
+            _ => 0..=(1 << bits) - 1,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InstructionClass {
+    Alu,
+    Load,
+    Store,
+    Branch,
+    Jump,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct InstructionDescriptor {
+    pub opcode: Opcode,
+    pub mnemonic: &'static str,
+    pub operands: Operands,
+    pub immediate: Immediate,
+    pub opcode_bits: u16, // bits 15..12
+    pub suffix_bits: u16, // bits 2..0 of register-register instructions: complement bit + CZ
+    pub class: InstructionClass,
+}
+
+impl InstructionDescriptor {
+    // The operands as written, for help text: LW expects RA, RB, IMM6.
+    pub fn syntax(&self) -> String {
+        match (self.operands, self.immediate) {
+            (Operands::RaRbRc, _) => "RA, RB, RC".to_string(),
+            (Operands::RaRbImm, immediate) => format!("RA, RB, IMM{}", immediate.bits()),
+            (Operands::RaRb, _) => "RA, RB".to_string(),
+            (Operands::RaImm, Immediate::RegisterMask) => "RA, {REGISTERS}".to_string(),
+            (Operands::RaImm, immediate) => format!("RA, IMM{}", immediate.bits()),
+        }
+    }
+}
+
+const fn describe(
+    opcode: Opcode,
+    operands: Operands,
+    immediate: Immediate,
+    opcode_bits: u16,
+    suffix_bits: u16,
+    class: InstructionClass,
+) -> InstructionDescriptor {
+    InstructionDescriptor {
+        opcode,
+        mnemonic: opcode.mnemonic(),
+        operands,
+        immediate,
+        opcode_bits,
+        suffix_bits,
+        class,
+    }
+}
+
+pub const PIPELINED: [InstructionDescriptor; 26] = [
+    describe(Opcode::Ada, RaRbRc, Immediate::None, 0b0001, 0b0_00, Alu),
+    describe(Opcode::Adc, RaRbRc, Immediate::None, 0b0001, 0b0_10, Alu),
+    describe(Opcode::Adz, RaRbRc, Immediate::None, 0b0001, 0b0_01, Alu),
+    describe(Opcode::Awc, RaRbRc, Immediate::None, 0b0001, 0b0_11, Alu),
+    describe(Opcode::Aca, RaRbRc, Immediate::None, 0b0001, 0b1_00, Alu),
+    describe(Opcode::Acc, RaRbRc, Immediate::None, 0b0001, 0b1_10, Alu),
+    describe(Opcode::Acz, RaRbRc, Immediate::None, 0b0001, 0b1_01, Alu),
+    describe(Opcode::Acw, RaRbRc, Immediate::None, 0b0001, 0b1_11, Alu),
+    describe(Opcode::Adi, RaRbImm, Signed(6), 0b0000, 0, Alu),
+    describe(Opcode::Ndu, RaRbRc, Immediate::None, 0b0010, 0b0_00, Alu),
+    describe(Opcode::Ndc, RaRbRc, Immediate::None, 0b0010, 0b0_10, Alu),
+    describe(Opcode::Ndz, RaRbRc, Immediate::None, 0b0010, 0b0_01, Alu),
+    describe(Opcode::Ncu, RaRbRc, Immediate::None, 0b0010, 0b1_00, Alu),
+    describe(Opcode::Ncc, RaRbRc, Immediate::None, 0b0010, 0b1_10, Alu),
+    describe(Opcode::Ncz, RaRbRc, Immediate::None, 0b0010, 0b1_01, Alu),
+    describe(Opcode::Lli, RaImm, Unsigned(9), 0b0011, 0, Alu),
+    describe(Opcode::Lw, RaRbImm, Signed(6), 0b0100, 0, Load),
+    describe(Opcode::Sw, RaRbImm, Signed(6), 0b0101, 0, Store),
+    describe(Opcode::Lm, RaImm, RegisterMask, 0b0110, 0, Load),
+    describe(Opcode::Sm, RaImm, RegisterMask, 0b0111, 0, Store),
+    describe(Opcode::Beq, RaRbImm, Offset(6), 0b1000, 0, Branch),
+    describe(Opcode::Blt, RaRbImm, Offset(6), 0b1001, 0, Branch),
+    describe(Opcode::Ble, RaRbImm, Offset(6), 0b1010, 0, Branch),
+    describe(Opcode::Jal, RaImm, Offset(9), 0b1100, 0, Jump),
+    describe(Opcode::Jlr, RaRb, Immediate::None, 0b1101, 0, Jump),
+    describe(Opcode::Jri, RaImm, Signed(9), 0b1111, 0, Jump),
+];
+
+pub const SINGLE_CYCLE: [InstructionDescriptor; 14] = [
+    describe(Opcode::Add, RaRbRc, Immediate::None, 0b0000, 0, Alu),
+    describe(Opcode::Adi, RaRbImm, Signed(6), 0b0001, 0, Alu),
+    describe(Opcode::Sub, RaRbRc, Immediate::None, 0b0010, 0, Alu),
+    describe(Opcode::Mul, RaRbRc, Immediate::None, 0b0011, 0, Alu),
+    describe(Opcode::And, RaRbRc, Immediate::None, 0b0100, 0, Alu),
+    describe(Opcode::Ora, RaRbRc, Immediate::None, 0b0101, 0, Alu),
+    describe(Opcode::Imp, RaRbRc, Immediate::None, 0b0110, 0, Alu),
+    describe(Opcode::Lhi, RaImm, Unsigned(8), 0b1000, 0, Alu),
+    describe(Opcode::Lli, RaImm, Unsigned(8), 0b1001, 0, Alu),
+    describe(Opcode::Lw, RaRbImm, Signed(6), 0b1010, 0, Load),
+    describe(Opcode::Sw, RaRbImm, Signed(6), 0b1011, 0, Store),
+    describe(Opcode::Beq, RaRbImm, Offset(6), 0b1100, 0, Branch),
+    describe(Opcode::Jal, RaImm, Offset(9), 0b1101, 0, Jump),
+    describe(Opcode::Jlr, RaRb, Immediate::None, 0b1111, 0, Jump),
+];
+
+pub fn instruction_set(processor: Processor) -> &'static [InstructionDescriptor] {
+    match processor {
+        Processor::Pipelined => &PIPELINED,
+        Processor::SingleCycle => &SINGLE_CYCLE,
+    }
+}
+
+pub fn descriptor(processor: Processor, opcode: Opcode) -> Option<&'static InstructionDescriptor> {
+    instruction_set(processor)
+        .iter()
+        .find(|descriptor| descriptor.opcode == opcode)
+}
+
+// Case-insensitive lookup of a mnemonic in the instruction set of `processor`.
+pub fn lookup(processor: Processor, mnemonic: &str) -> Option<&'static InstructionDescriptor> {
+    instruction_set(processor)
+        .iter()
+        .find(|descriptor| descriptor.mnemonic.eq_ignore_ascii_case(mnemonic))
+}
@@ -0,0 +1,113 @@
+// Local labels, given their full names once the macros have been expanded.
+//
+// SUM:   LLI R3, 0
+// .loop: ADA R3, R3, R1      ; SUM.loop
+//        ADI R2, R2, -1
+//        BEQ R2, R0, 1f      ; the next 1:
+//        B .loop
+// 1:     RET                 ; SUM.1
+//
+// - A label that starts with a dot belongs to the global label before it, and is named
+//   GLOBAL.name. Elsewhere it can be written out in full, e.g. JAL R7, SUM.loop.
+// - A numeric label can be defined any number of times. 1f is the next 1: after the line,
+//   1b the last 1: up to and including the line. It is named GLOBAL.1, then GLOBAL.1#2, ...
+//   if the same number comes up again under that global label. With no N: anywhere, Nf and
+//   Nb are read as numbers, so a bare 0b is reported as a binary literal with no digits.
+// - The labels local to a macro expansion (LOOP@1) do not start a new scope. Numeric labels
+//   in a macro body are left as they are, so each expansion finds its own.
+
+use std::collections::BTreeMap;
+
+use crate::lexer::{number_token, SpannedToken, Token};
+
+// Renames the local labels in `lines`, and the references to them, to their full names.
+pub fn qualify(lines: &mut [Vec<SpannedToken>]) {
+    let mut scope = String::new();
+    let mut scopes = Vec::with_capacity(lines.len()); // the global label in effect on each line
+    let mut numeric: BTreeMap<String, Vec<(usize, String)>> = BTreeMap::new(); // 1 -> (line, name)
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+
+    for (line_index, line) in lines.iter_mut().enumerate() {
+        if let Some(SpannedToken {
+            token: Token::Label(label),
+            ..
+        }) = line.first_mut()
+        {
+            let name = label.trim_end_matches(':');
+            if name.starts_with('.') {
+                *label = format!("{}{}:", scope, name);
+            } else if is_numeric(name) {
+                let base = format!("{}.{}", scope, name);
+                let count = counts.entry(base.clone()).or_insert(0);
+                *count += 1;
+                let full_name = match *count {
+                    1 => base,
+                    count => format!("{}#{}", base, count),
+                };
+                numeric
+                    .entry(name.to_string())
+                    .or_default()
+                    .push((line_index, full_name.clone()));
+                *label = format!("{}:", full_name);
+            } else if !name.contains('@') {
+                scope = name.to_string();
+            }
+        }
+        scopes.push(scope.clone());
+    }
+
+    for (line_index, line) in lines.iter_mut().enumerate() {
+        let statement = match line.first().map(|token| &token.token) {
+            Some(Token::Label(_)) => 1,
+            _ => 0,
+        };
+        // the mnemonic of the statement is never a label reference
+        for token in line.iter_mut().skip(statement + 1) {
+            let Token::Identifier(name) = &mut token.token else {
+                continue;
+            };
+            if name.starts_with('.') {
+                *name = format!("{}{}", scopes[line_index], name);
+            } else if let Some(reference) = numeric_reference(name, line_index, &numeric) {
+                match reference {
+                    Some(full_name) => *name = full_name,
+                    None => token.token = number_token(name),
+                }
+            }
+        }
+    }
+}
+
+// The full name of the label that 1f or 1b on line `line_index` refers to, or Some(None)
+// when there is no 1: at all. An unresolved reference is left alone, to be reported as an
+// undefined label.
+fn numeric_reference(
+    name: &str,
+    line_index: usize,
+    numeric: &BTreeMap<String, Vec<(usize, String)>>,
+) -> Option<Option<String>> {
+    let (number, forward) = match name.strip_suffix('f') {
+        Some(number) => (number, true),
+        None => (name.strip_suffix('b')?, false),
+    };
+    if !is_numeric(number) {
+        return None;
+    }
+    let Some(definitions) = numeric.get(number) else {
+        return Some(None);
+    };
+    let definition = if forward {
+        definitions.iter().find(|(line, _)| *line > line_index)
+    } else {
+        definitions
+            .iter()
+            .rev()
+            .find(|(line, _)| *line <= line_index)
+    };
+    definition.map(|(_, full_name)| Some(full_name.clone()))
+}
+
+// Whether a label name is a number, as in 1:.
+pub fn is_numeric(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|ch| ch.is_ascii_digit())
+}
@@ -1,8 +1,12 @@
 // A lexer for the Assembly Language for the IITB RISC-V Processor.
 //
 // This lexer supports:
-// - 24 instructions for the Pipelined Architecture
-// - 14 instructions for the Single Cycle Architecture
+// - the instructions of the Pipelined and Single Cycle Architectures, see `isa`
+// - the directives .org, .word, .fill, .space, .equ, .text and .data
+// - .global and .extern, for objects that are linked together, see `crates::object`
+// - .macro and .endm, which are expanded by `macros` before parsing
+// - .include "FILE", which is resolved by `source` before macro expansion
+// - .if, .ifdef, .ifndef, .else and .endif, which are evaluated by `conditional`
 //
 // The ISA is developed by Prof. Virendra Singh, IIT Bombay.
 // For more information, see <https://www.ee.iitb.ac.in/~viren/>.
@@ -12,97 +16,162 @@
 #![allow(dead_code)]
 // TODO: Remove this line after implementing the code
 
+use std::ops::Range;
+
+use crate::expression::Operator;
+use crate::isa::{self, Opcode};
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Processor {
     SingleCycle,
     Pipelined,
 }
 
-pub const INSTRUCTION_SINGLE_CYCLE: [&'static str; 14] = [
-    "ADD", // 0x00
-    "SUB", //0x02
-    "MUL", //0x03
-    "ADI", // 0x01
-    "AND", // 0x04
-    "ORA", // 0x05
-    "IMP", // 0x06
-    "LHI", // 0x08
-    "LLI", // 0x09
-    "LW",  // 0x0A
-    "SW",  // 0x0B
-    "BEQ", // 0x0C
-    "JAL", // 0x0D
-    "JLR", // 0x0F
-];
-
-pub const INSTRUCTION_PIPELINED: [&'static str; 26] = [
-    "ADA", //00_01 RA RB RC 0 00
-    "ADC", //00_01 RA RB RC 0 10
-    "ADZ", //00_01 RA RB RC 0 01
-    "AWC", //00_01 RA RB RC 0 11
-    "ACA", //00_01 RA RB RC 1 00
-    "ACC", //00_01 RA RB RC 1 10
-    "ACZ", //00_01 RA RB RC 1 01
-    "ACW", //00_01 RA RB RC 1 11
-    "ADI", //00 RA RB IMM6
-    "NDU", //00_10 RA RB RC 0 00
-    "NDC", //00_10 RA RB RC 0 10
-    "NDZ", //00_10 RA RB RC 0 01
-    "NCU", //00_10 RA RB RC 1 00
-    "NCC", //00_10 RA RB RC 1 10
-    "NCZ", //00_10 RA RB RC 1 01
-    "LLI", //00_11 RA IMM9
-    "LW",  //01_00 RA RB IMM6
-    "SW",  //01_01 RA RB IMM6
-    "LM",  //01_10 RA 0 + 8 bits corresponding to R0 to R7
-    "SM",  //01_11 RA 0 + 8 bits corresponding to R0 to R7
-    "BEQ", //10_00 RA RB IMM6
-    "BLT", //10_01 RA RB IMM6
-    "BLE", //10_10 RA RB IMM6
-    "JAL", //11_00 RA IMM9
-    "JLR", //11_01 RA 0 0000
-    "JRI", //11_11 RA 0 0000
-];
-
-pub const OPCODES_WITH_THREE_REGISTERS_PIPELINED: [&str; 14] = [
-    "ADA", //00_01 RA RB RC 0 00
-    "ADC", //00_01 RA RB RC 0 10
-    "ADZ", //00_01 RA RB RC 0 01
-    "AWC", //00_01 RA RB RC 0 11
-    "ACA", //00_01 RA RB RC 1 00
-    "ACC", //00_01 RA RB RC 1 10
-    "ACZ", //00_01 RA RB RC 1 01
-    "ACW", //00_01 RA RB RC 1 11
-    "NDU", //00_10 RA RB RC 0 00
-    "NDC", //00_10 RA RB RC 0 10
-    "NDZ", //00_10 RA RB RC 0 01
-    "NCU", //00_10 RA RB RC 1 00
-    "NCC", //00_10 RA RB RC 1 10
-    "NCZ", //00_10 RA RB RC 1 01
-];
-
-pub const OPCODES_WITH_TWO_REGISTERS_PIPELINED: [&str; 7] = [
-    "ADI", //00_00 RA RB IMM6
-    "LLI", //00_11 RA IMM9
-    "LW",  //01_00 RA RB IMM6
-    "SW",  //01_01 RA RB IMM6
-    "BEQ", //10_00 RA RB IMM6
-    "BLT", //10_01 RA RB IMM6
-    "BLE", //10_10 RA RB IMM6
-];
-pub const OPCODES_WITH_SINGLE_REGISTER_PIPELINED: [&str; 6] = [
-    "LLI", //00_11 RA IMM9
-    "LM",  //01_10 RA 0 + 8 bits corresponding to R0 to R7
-    "SM",  //01_11 RA 0 + 8 bits corresponding to R0 to R7
-    "JAL", //11_00 RA IMM9
-    "JLR", //11_01 RA 0 0000
-    "JRI", //11_11 RA 0 0000
-];
+impl Processor {
+    pub const ALL: [Processor; 2] = [Processor::SingleCycle, Processor::Pipelined];
+
+    // The name given to --processor.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Processor::SingleCycle => "single-cycle",
+            Processor::Pipelined => "pipelined",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Processor> {
+        Processor::ALL
+            .iter()
+            .find(|processor| processor.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+}
+
+// Assembler directives: lines that lay out the program instead of encoding an instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Directive {
+    Org,     // .org ADDRESS: move the location counter of the current section
+    Word,    // .word VALUE, ...: one word per value
+    Fill,    // .fill COUNT, VALUE: COUNT copies of VALUE
+    Space,   // .space COUNT: COUNT zero words
+    Equ,     // .equ NAME, VALUE: a named constant
+    Text,    // .text: switch to the code section
+    Data,    // .data: switch to the data section
+    Macro,   // .macro NAME PARAMETER, ...: start a macro definition
+    Endm,    // .endm: end it
+    Include, // .include "FILE": the lines of another source file
+    If,      // .if CONDITION: assemble the lines up to .else or .endif if CONDITION is not 0
+    Ifdef,   // .ifdef NAME: ... if NAME is a constant
+    Ifndef,  // .ifndef NAME: ... if NAME is not a constant
+    Else,    // .else: the lines up to .endif, if the condition was false
+    Endif,   // .endif: end the conditional block
+    Global,  // .global NAME, ...: labels that other objects can use
+    Extern,  // .extern NAME, ...: labels that another object defines
+}
+
+impl Directive {
+    pub const ALL: [Directive; 17] = [
+        Directive::Org,
+        Directive::Word,
+        Directive::Fill,
+        Directive::Space,
+        Directive::Equ,
+        Directive::Text,
+        Directive::Data,
+        Directive::Macro,
+        Directive::Endm,
+        Directive::Include,
+        Directive::If,
+        Directive::Ifdef,
+        Directive::Ifndef,
+        Directive::Else,
+        Directive::Endif,
+        Directive::Global,
+        Directive::Extern,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Directive::Org => ".org",
+            Directive::Word => ".word",
+            Directive::Fill => ".fill",
+            Directive::Space => ".space",
+            Directive::Equ => ".equ",
+            Directive::Text => ".text",
+            Directive::Data => ".data",
+            Directive::Macro => ".macro",
+            Directive::Endm => ".endm",
+            Directive::Include => ".include",
+            Directive::If => ".if",
+            Directive::Ifdef => ".ifdef",
+            Directive::Ifndef => ".ifndef",
+            Directive::Else => ".else",
+            Directive::Endif => ".endif",
+            Directive::Global => ".global",
+            Directive::Extern => ".extern",
+        }
+    }
+
+    // The operands, as shown in error messages.
+    pub const fn syntax(self) -> &'static str {
+        match self {
+            Directive::Org => "ADDRESS",
+            Directive::Word => "VALUE, ...",
+            Directive::Fill => "COUNT, VALUE",
+            Directive::Space => "COUNT",
+            Directive::Equ => "NAME, VALUE",
+            Directive::Macro => "NAME PARAMETER, ...",
+            Directive::Include => "\"FILE\"",
+            Directive::If => "CONDITION",
+            Directive::Ifdef | Directive::Ifndef => "NAME",
+            Directive::Global | Directive::Extern => "NAME, ...",
+            Directive::Text
+            | Directive::Data
+            | Directive::Endm
+            | Directive::Else
+            | Directive::Endif => "no operands",
+        }
+    }
+
+    // The number of operands, or None for a list of one or more.
+    pub const fn arity(self) -> Option<usize> {
+        match self {
+            Directive::Org
+            | Directive::Space
+            | Directive::Include
+            | Directive::If
+            | Directive::Ifdef
+            | Directive::Ifndef => Some(1),
+            Directive::Fill | Directive::Equ => Some(2),
+            Directive::Word | Directive::Macro | Directive::Global | Directive::Extern => None,
+            Directive::Text
+            | Directive::Data
+            | Directive::Endm
+            | Directive::Else
+            | Directive::Endif => Some(0),
+        }
+    }
+
+    // Case-insensitive lookup of a directive, including its leading '.'.
+    pub fn from_name(name: &str) -> Option<Directive> {
+        Directive::ALL
+            .iter()
+            .find(|directive| directive.name().eq_ignore_ascii_case(name))
+            .copied()
+    }
+}
+
+impl std::fmt::Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Token {
-    Opcode(String), // a symbol(opcode)
+    Opcode(Opcode), // a symbol(opcode) of the processor being lexed
+    Directive(Directive),
     Label(String),
+    Identifier(String), // a symbol that is neither an opcode nor a register, e.g. a label reference
     Number(i32),
     Register(i32), // Only allow registers from 0 to 7
     Comment(String),
@@ -110,32 +179,107 @@ pub enum Token {
     Error(String), // for unknown tokens
     Comma,
     NewLine,
+    LeftBrace,  // opens a register list, e.g. {R0, R2-R4}
+    RightBrace, // closes a register list
+    LeftParen,
+    RightParen,
+    Operator(Operator), // in expressions, and the '-' of a register range
+    String(String),     // "text" without the quotes, e.g. the file of an .include
 }
 
 impl Token {
-    pub fn get_token_string(&self) -> String {
-        // only for tokens that are strings - opcode, label, comment, error
+    pub fn get_token_string(&self) -> Option<String> {
+        // only for tokens that are strings - opcode, directive, label, identifier, comment, error
         match self {
-            Token::Opcode(s) => s.clone(),
-            Token::Label(s) => s.clone(),
-            Token::Comment(s) => s.clone(),
-            Token::Error(s) => s.clone(),
-            _ => panic!("Token is not a string"),
+            Token::Opcode(opcode) => Some(opcode.mnemonic().to_string()),
+            Token::Directive(directive) => Some(directive.name().to_string()),
+            Token::Label(s) => Some(s.clone()),
+            Token::Identifier(s) => Some(s.clone()),
+            Token::Comment(s) => Some(s.clone()),
+            Token::Error(s) => Some(s.clone()),
+            Token::String(s) => Some(s.clone()),
+            _ => None,
         }
     }
 }
 
+// Where a token sits in the source: a byte range plus the 1-based line and column
+// (in characters) of its first character.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+    pub file: usize, // id of the source file, see `source::SourceMap`
+}
+
+impl Span {
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Lexer {
     pub input: Vec<char>,
     position: usize,
+    byte_offsets: Vec<usize>, // byte offset of every character, plus the end of the input
+    line_starts: Vec<usize>,  // character index where every line starts
+    file: usize,
+    base: usize, // added to every byte offset, so spans of different files do not overlap
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
+        Lexer::with_file(input, 0, 0)
+    }
+
+    // A lexer for source file `file`, whose byte offsets start at `base`.
+    pub fn with_file(input: &str, file: usize, base: usize) -> Self {
+        let input: Vec<char> = input.chars().collect();
+
+        let mut byte_offsets = Vec::with_capacity(input.len() + 1);
+        let mut line_starts = vec![0];
+        let mut offset = 0;
+        for (index, ch) in input.iter().enumerate() {
+            byte_offsets.push(offset);
+            offset += ch.len_utf8();
+            if *ch == '\n' {
+                line_starts.push(index + 1);
+            }
+        }
+        byte_offsets.push(offset);
+
         Lexer {
-            input: input.chars().collect(),
+            input,
             position: 0,
+            byte_offsets,
+            line_starts,
+            file,
+            base,
+        }
+    }
+
+    // The span covering the characters `start..end`.
+    fn span(&self, start: usize, end: usize) -> Span {
+        let end = end.min(self.input.len());
+        let start = start.min(end);
+        let line = self
+            .line_starts
+            .partition_point(|line_start| *line_start <= start);
+        Span {
+            start: self.base + self.byte_offsets[start],
+            end: self.base + self.byte_offsets[end],
+            line,
+            column: start - self.line_starts[line - 1] + 1,
+            file: self.file,
         }
     }
 
@@ -160,23 +304,42 @@ impl Lexer {
         }
     }
 
+    // An operator starting with `first_char`; '/' is only reached when it does not start a
+    // comment.
+    fn read_operator(&mut self, first_char: char) -> Option<Operator> {
+        let operator = match first_char {
+            '+' => Operator::Plus,
+            '-' => Operator::Minus,
+            '*' => Operator::Star,
+            '/' => Operator::Slash,
+            '%' => Operator::Percent,
+            '&' => Operator::And,
+            '|' => Operator::Or,
+            '^' => Operator::Xor,
+            '~' => Operator::Not,
+            '<' | '>' if self.peek_char() == Some(first_char) => {
+                self.next_char();
+                if first_char == '<' {
+                    Operator::ShiftLeft
+                } else {
+                    Operator::ShiftRight
+                }
+            }
+            _ => return None,
+        };
+        Some(operator)
+    }
+
+    // Reads the rest of a numeric literal; `parse_number` decides whether it is valid.
     fn read_number(&mut self, first_digit: char) -> String {
-        // later parse the number as an integer if failed return error
         let mut number_str = String::new();
-        let mut number = first_digit.to_digit(10).unwrap() as i32;
         number_str.push(first_digit);
         while let Some(ch) = self.peek_char() {
-            if ch.is_digit(10) {
-                number = number * 10 + ch.to_digit(10).unwrap() as i32;
+            if ch.is_ascii_alphanumeric() || ch == '_' {
                 number_str.push(ch);
                 self.next_char();
-            } else if ch.is_whitespace() || ch == ',' || ch == ';' || ch == '\n' {
-                // number parsed successfully
-                break;
             } else {
-                // error parsing number - not a number
-                number_str.push(ch);
-                self.next_char();
+                break;
             }
         }
         number_str
@@ -187,12 +350,17 @@ impl Lexer {
         identifier.push(first_char);
 
         while let Some(ch) = self.peek_char() {
-            if ch.is_alphanumeric() || ch == '_' {
+            if is_symbol_char(ch) {
                 // println!("{ch}");
                 identifier.push(ch);
                 self.next_char();
+            } else if ch == '.' && self.peek_next_char().is_some_and(is_symbol_char) {
+                // a qualified local label, e.g. SUM.loop
+                identifier.push(ch);
+                self.next_char();
             } else {
                 if ch.is_whitespace() {
+                    // a label may have spaces before its colon; otherwise leave them unread
                     let position = self.position;
                     self.skip_whitespace();
                     if let Some(ch) = self.peek_char() {
@@ -202,9 +370,8 @@ impl Lexer {
 
                             break;
                         }
-                    } else {
-                        break;
                     }
+                    self.position = position;
                 } else if ch == ':' {
                     identifier.push(ch);
                     self.next_char();
@@ -218,6 +385,35 @@ impl Lexer {
         identifier
     }
 
+    // Reads the rest of a string literal after its opening quote. Strings end on the same line.
+    fn read_string(&mut self) -> Token {
+        let mut text = String::new();
+        while let Some(ch) = self.peek_char() {
+            if ch == '\n' {
+                break;
+            }
+            self.next_char();
+            if ch == '"' {
+                return Token::String(text);
+            }
+            text.push(ch);
+        }
+        Token::Error("Unterminated string: expected a closing '\"'".to_string())
+    }
+
+    // Reads up to, but not including, the end of the line.
+    fn read_comment(&mut self) -> String {
+        let length = self.input[self.position..]
+            .iter()
+            .position(|&x| x == '\n')
+            .unwrap_or(self.input.len() - self.position);
+        let comment: String = self.input[self.position..self.position + length]
+            .iter()
+            .collect();
+        self.position += length;
+        comment.trim_end_matches('\r').to_string()
+    }
+
     fn peek_char(&self) -> Option<char> {
         if self.position < self.input.len() {
             Some(self.input[self.position])
@@ -226,21 +422,38 @@ impl Lexer {
         }
     }
 
-    pub fn next_token(&mut self, processor: Processor) -> Token {
+    fn peek_next_char(&self) -> Option<char> {
+        self.input.get(self.position + 1).copied()
+    }
+
+    pub fn next_token(&mut self, processor: Processor) -> SpannedToken {
         self.skip_whitespace();
+        let start = self.position;
+        let token = self.read_token(processor);
+        SpannedToken {
+            token,
+            span: self.span(start, self.position),
+        }
+    }
+
+    fn read_token(&mut self, processor: Processor) -> Token {
         match self.next_char() {
             Some(ch) => {
-                if ch.is_digit(10) {
+                if ch.is_ascii_digit() {
                     let number = self.read_number(ch);
-                    let token = if let Some(number_without_prefix) = number.strip_prefix("0x") {
-                        i32::from_str_radix(number_without_prefix, 16)
+                    let digits = number.trim_end_matches(['f', 'b']);
+                    if number.chars().all(|ch| ch.is_ascii_digit()) && self.peek_char() == Some(':')
+                    {
+                        // a numeric local label, e.g. 1:
+                        self.next_char();
+                        Token::Label(format!("{}:", number))
+                    } else if digits.len() + 1 == number.len()
+                        && digits.chars().all(|ch| ch.is_ascii_digit())
+                    {
+                        // a reference to the next or previous numeric label, e.g. 1f or 1b
+                        Token::Identifier(number)
                     } else {
-                        number.parse::<i32>()
-                    };
-
-                    match token {
-                        Ok(parsed_num) => Token::Number(parsed_num),
-                        Err(_) => Token::Error(format!("Invalid number: {}", number)),
+                        number_token(&number)
                     }
                 } else if ch.is_alphabetic() {
                     let identifier = self.read_identifier(ch);
@@ -249,75 +462,69 @@ impl Lexer {
                     } else {
                         match read_string_to_register(identifier.to_uppercase()) {
                             Some(token) => token,
-                            None => {
-                                if matches!(processor, Processor::SingleCycle) {
-                                    if INSTRUCTION_SINGLE_CYCLE
-                                        .contains(&identifier.to_uppercase().as_str())
-                                    {
-                                        Token::Opcode(identifier)
-                                    } else {
-                                        Token::Error(identifier)
-                                    }
-                                } else if matches!(processor, Processor::Pipelined) {
-                                    if INSTRUCTION_PIPELINED
-                                        .contains(&identifier.to_uppercase().as_str())
-                                    {
-                                        Token::Opcode(identifier)
-                                    } else {
-                                        Token::Error(identifier)
-                                    }
-                                } else {
-                                    Token::Error(format!("Unknown processor: {:?}", processor))
-                                }
-                            }
+                            None => match isa::lookup(processor, &identifier) {
+                                Some(descriptor) => Token::Opcode(descriptor.opcode),
+                                None => Token::Identifier(identifier),
+                            },
+                        }
+                    }
+                } else if ch == '.' && self.peek_char().is_some_and(|ch| ch.is_alphabetic()) {
+                    let mut name = String::from(".");
+                    while let Some(ch) = self.peek_char() {
+                        if is_symbol_char(ch) {
+                            name.push(ch);
+                            self.next_char();
+                        } else {
+                            break;
                         }
                     }
+                    if self.peek_char() == Some(':') {
+                        // a local label, e.g. .loop:
+                        self.next_char();
+                        name.push(':');
+                        return Token::Label(name);
+                    }
+                    match Directive::from_name(&name) {
+                        Some(directive) => Token::Directive(directive),
+                        // a local label reference, e.g. .loop; the parser reports an unknown
+                        // directive at the start of a statement
+                        None => Token::Identifier(name),
+                    }
                 } else if ch == '/' && self.peek_char() == Some('/') {
                     self.position += 1; // skip the second '/'
-                    let index = self.input[self.position..].iter().position(|&x| x == '\n');
-                    if let Some(_index) = index {
-                        let comment: String = self.input[self.position..self.position + _index - 1]
-                            .iter()
-                            .collect();
-                        self.position += _index - 1;
-                        Token::Comment(comment)
-                    } else {
-                        let comment: String = self.input[self.position..].iter().collect();
-                        self.position += self.input.len();
-                        Token::Comment(comment)
-                    }
-
-                    // Token::Comment(comment)
+                    Token::Comment(self.read_comment())
                 } else if ch == ';' {
-                    let index = self.input[self.position..].iter().position(|&x| x == '\n');
-                    if let Some(_index) = index {
-                        let comment: String = self.input[self.position..self.position + _index]
-                            .iter()
-                            .collect();
-                        self.position += _index;
-                        Token::Comment(comment)
-                    } else {
-                        let comment: String = self.input[self.position..].iter().collect();
-                        self.position += self.input.len();
-                        Token::Comment(comment)
-                    }
+                    Token::Comment(self.read_comment())
+                } else if ch == '"' {
+                    self.read_string()
                 } else if ch == ',' {
                     Token::Comma
+                } else if ch == '{' {
+                    Token::LeftBrace
+                } else if ch == '}' {
+                    Token::RightBrace
+                } else if ch == '(' {
+                    Token::LeftParen
+                } else if ch == ')' {
+                    Token::RightParen
+                } else if let Some(operator) = self.read_operator(ch) {
+                    Token::Operator(operator)
                 } else if ch == '\n' {
                     Token::NewLine
                 } else if ch == '#' {
-                    match self.next_char() {
-                        Some(num) => {
-                            let number = self.read_number(num);
-                            // TODO: remove the H from the number and assert that it was there
-                            let token = number.parse::<i32>();
-                            match token {
-                                Ok(parsed_num) => Token::Number(parsed_num),
-                                Err(_) => Token::Error(format!("Invalid number: {}", number)),
-                            }
+                    let mut number = String::from("#");
+                    if self.peek_char() == Some('-') {
+                        self.next_char();
+                        number.push('-');
+                    }
+                    match self.peek_char() {
+                        Some(first_digit) if first_digit.is_ascii_alphanumeric() => {
+                            self.next_char();
+                            number.push_str(&self.read_number(first_digit));
                         }
-                        None => Token::Error(format!("Invalid number: {}", ch)),
+                        _ => {}
                     }
+                    number_token(&number)
                 } else {
                     Token::Error(format!("Unknown token: {}", ch))
                 }
@@ -327,9 +534,9 @@ impl Lexer {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct TokenStream {
-    pub tokens_by_line: Vec<Vec<Token>>, // store tokens by line
+    pub tokens_by_line: Vec<Vec<SpannedToken>>, // store tokens by line
     pub position: usize,
     pub line: usize,
 }
@@ -343,7 +550,7 @@ impl TokenStream {
         }
     }
 
-    pub fn from(tokens: Vec<Token>) -> Self {
+    pub fn from(tokens: Vec<SpannedToken>) -> Self {
         TokenStream {
             tokens_by_line: vec![tokens], //TODO: write better way to parse all the tokens
             position: 0,
@@ -351,10 +558,10 @@ impl TokenStream {
         }
     }
 
-    pub fn add(&mut self, token: Token) {
+    pub fn add(&mut self, token: SpannedToken) {
         if let Some(tokens) = self.tokens_by_line.last_mut() {
             if let Some(last_token) = tokens.last_mut() {
-                if matches!(last_token, Token::NewLine) {
+                if matches!(last_token.token, Token::NewLine) {
                     self.tokens_by_line.push(vec![token]);
                 } else {
                     tokens.push(token);
@@ -367,7 +574,8 @@ impl TokenStream {
         }
     }
 
-    pub fn next(&mut self) -> Option<&Token> {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<&SpannedToken> {
         if self.line < self.tokens_by_line.len() {
             let tokens = &self.tokens_by_line[self.line];
             if self.position < tokens.len() {
@@ -377,7 +585,14 @@ impl TokenStream {
             } else {
                 self.position = 0;
                 self.line += 1;
-                Some(&self.tokens_by_line[self.line][self.position]) // This will most likely result in error.
+                let token = self
+                    .tokens_by_line
+                    .get(self.line)
+                    .and_then(|tokens| tokens.first());
+                if token.is_some() {
+                    self.position += 1;
+                }
+                token
             }
         } else {
             None
@@ -389,7 +604,7 @@ impl TokenStream {
         self.line = 0;
     }
 
-    pub fn peek(&self) -> Option<&Token> {
+    pub fn peek(&self) -> Option<&SpannedToken> {
         if self.line < self.tokens_by_line.len() {
             let tokens = &self.tokens_by_line[self.line];
             if self.position < tokens.len() {
@@ -403,6 +618,80 @@ impl TokenStream {
     }
 }
 
+fn is_symbol_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+pub(crate) fn number_token(literal: &str) -> Token {
+    match parse_number(literal) {
+        Ok(number) => Token::Number(number),
+        Err(message) => Token::Error(message),
+    }
+}
+
+// Parses an integer literal as written in the source:
+// - signed decimal: 42, -7
+// - prefixed: 0x1F, 0b1010, 0o17
+// - Intel-style hex after a #: #1FH (a plain #42 is decimal)
+// Underscores may separate digits, e.g. 0b0001_0010.
+pub fn parse_number(literal: &str) -> Result<i32, String> {
+    let (intel, rest) = match literal.strip_prefix('#') {
+        Some(rest) => (true, rest),
+        None => (false, literal),
+    };
+    let (negative, rest) = match rest.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+
+    // the H suffix comes first, so #0BEH is hexadecimal 0BE and not binary
+    let prefix = rest.get(..2).map(|prefix| prefix.to_ascii_lowercase());
+    let (radix, name, digits) = match prefix.as_deref() {
+        _ if intel && (rest.ends_with('H') || rest.ends_with('h')) => {
+            (16, "hexadecimal", &rest[..rest.len() - 1])
+        }
+        Some("0x") => (16, "hexadecimal", &rest[2..]),
+        Some("0b") => (2, "binary", &rest[2..]),
+        Some("0o") => (8, "octal", &rest[2..]),
+        _ => (10, "decimal", rest),
+    };
+
+    if digits.is_empty() {
+        return Err(format!("Invalid number {}: missing digits", literal));
+    }
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(format!(
+            "Invalid number {}: '_' may only separate two digits",
+            literal
+        ));
+    }
+    if let Some(ch) = digits.chars().find(|ch| *ch != '_' && !ch.is_digit(radix)) {
+        let hint = if radix == 10 && ch.is_ascii_hexdigit() {
+            if intel {
+                " (hexadecimal after # needs an H suffix, e.g. #1FH)"
+            } else {
+                " (write hexadecimal with a 0x prefix, e.g. 0x1F)"
+            }
+        } else {
+            ""
+        };
+        return Err(format!(
+            "Invalid number {}: '{}' is not a {} digit{}",
+            literal, ch, name, hint
+        ));
+    }
+
+    let digits: String = digits.chars().filter(|ch| *ch != '_').collect();
+    let value = i64::from_str_radix(&digits, radix)
+        .ok()
+        .map(|value| if negative { -value } else { value })
+        .and_then(|value| i32::try_from(value).ok());
+    match value {
+        Some(value) => Ok(value),
+        None => Err(format!("Invalid number {}: too large", literal)),
+    }
+}
+
 fn read_string_to_register(word: String) -> Option<Token> {
     match word.as_str() {
         "R1" => Some(Token::Register(1)),
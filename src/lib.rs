@@ -1,9 +1,25 @@
+pub mod conditional;
+pub mod diagnostic;
+pub mod expression;
+pub mod format;
+pub mod isa;
+pub mod labels;
 pub mod lexer;
+pub mod macros;
 pub mod parser;
+pub mod pseudo;
+pub mod source;
+pub mod suggest;
 pub mod texteditor;
 pub mod welcome;
 pub mod crates {
     pub mod assembler;
     pub mod custom_themes;
+    pub mod debuginfo;
+    pub mod disassembler;
     pub mod iitbcpu;
+    pub mod listing;
+    pub mod object;
+    pub mod output;
+    pub mod vhdl;
 }
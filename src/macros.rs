@@ -0,0 +1,409 @@
+// Macros, expanded on the token stream before the parser lays out the program.
+//
+// .macro PUSH reg
+//        SW reg, R6, 0
+//        ADI R6, R6, -1
+// .endm
+//
+//        PUSH R1
+//
+// - Parameters are replaced by the tokens of the matching argument. Arguments are separated
+//   by commas outside of {} and (), so a register list or an expression is one argument.
+// - Labels defined in the body are local to each expansion: LOOP becomes LOOP@1, LOOP@2, ...
+//   Numeric labels (1:) are kept, as 1f and 1b already find the one in the same expansion.
+// - A macro may call other macros, but not itself.
+// - Every expanded line remembers the calls it came from, so a diagnostic can point at both
+//   the body line and the call site.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::diagnostic::Diagnostic;
+use crate::labels;
+use crate::lexer::{Directive, SpannedToken, Token};
+
+// Deeper nesting than this is taken to be a macro that (indirectly) calls itself.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Macro {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub body: Vec<Vec<SpannedToken>>, // the lines between .macro and .endm
+    pub span: Range<usize>,           // the name in the .macro line
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MacroCall {
+    pub name: String,
+    pub span: Range<usize>, // the call, from the macro name to the last argument
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Expansion {
+    pub lines: Vec<Vec<SpannedToken>>,
+    pub calls: Vec<Vec<MacroCall>>, // per line: the calls it was expanded from, innermost first
+    pub macros: BTreeMap<String, Macro>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+// Removes the macro definitions from `lines` and expands every call.
+pub fn expand(lines: &[Vec<SpannedToken>]) -> Expansion {
+    let mut expansion = Expansion::default();
+    let source_lines = collect_definitions(lines, &mut expansion);
+    let mut counter = 0;
+    expand_lines(&source_lines, &[], &mut counter, &mut expansion);
+    expansion
+}
+
+// The note attached to a diagnostic raised on a line expanded from `calls`.
+pub fn expansion_notes(calls: &[MacroCall]) -> Vec<Diagnostic> {
+    calls
+        .iter()
+        .map(|call| {
+            Diagnostic::note(
+                format!("in expansion of macro {}", call.name),
+                call.span.clone(),
+            )
+        })
+        .collect()
+}
+
+// The first token after an optional label.
+fn statement(line: &[SpannedToken]) -> (Option<&SpannedToken>, usize) {
+    match line.first() {
+        Some(SpannedToken {
+            token: Token::Label(_),
+            ..
+        }) => (line.get(1), 1),
+        first => (first, 0),
+    }
+}
+
+fn end_of_line(token: &Token) -> bool {
+    matches!(token, Token::Comment(_) | Token::NewLine | Token::EOF)
+}
+
+// Takes out every .macro ... .endm block and returns the remaining lines.
+fn collect_definitions(
+    lines: &[Vec<SpannedToken>],
+    expansion: &mut Expansion,
+) -> Vec<Vec<SpannedToken>> {
+    let mut remaining = Vec::new();
+    let mut open: Option<Macro> = None;
+
+    for line in lines {
+        let (first, position) = statement(line);
+        let directive = match first.map(|token| &token.token) {
+            Some(Token::Directive(directive)) => Some(*directive),
+            _ => None,
+        };
+
+        match (directive, open.is_some()) {
+            (Some(Directive::Macro), true) => expansion.diagnostics.push(Diagnostic::error(
+                "A macro cannot be defined inside another macro",
+                line[position].span.range(),
+            )),
+            (Some(Directive::Macro), false) => {
+                if position == 1 {
+                    expansion.diagnostics.push(Diagnostic::error(
+                        "A .macro line cannot have a label",
+                        line[0].span.range(),
+                    ));
+                }
+                match definition(line, position) {
+                    Ok(definition) => open = Some(definition),
+                    Err(diagnostic) => {
+                        expansion.diagnostics.push(diagnostic);
+                        // still swallow the body, so it is not assembled as code
+                        open = Some(Macro {
+                            name: String::new(),
+                            parameters: Vec::new(),
+                            body: Vec::new(),
+                            span: line[position].span.range(),
+                        });
+                    }
+                }
+            }
+            (Some(Directive::Endm), true) => {
+                let definition = match open.take() {
+                    Some(definition) if !definition.name.is_empty() => definition,
+                    _ => continue,
+                };
+                match expansion.macros.get(&definition.name) {
+                    Some(first) => {
+                        expansion.diagnostics.push(Diagnostic::error(
+                            format!("Duplicate macro: {}", definition.name),
+                            definition.span.clone(),
+                        ));
+                        expansion.diagnostics.push(Diagnostic::note(
+                            format!("{} was first defined here", first.name),
+                            first.span.clone(),
+                        ));
+                    }
+                    None => {
+                        expansion.macros.insert(definition.name.clone(), definition);
+                    }
+                }
+            }
+            (Some(Directive::Endm), false) => expansion.diagnostics.push(Diagnostic::error(
+                ".endm without a .macro",
+                line[position].span.range(),
+            )),
+            _ => match &mut open {
+                Some(definition) => definition.body.push(line.clone()),
+                None => remaining.push(line.clone()),
+            },
+        }
+    }
+
+    if let Some(definition) = open {
+        expansion.diagnostics.push(Diagnostic::error(
+            format!("Macro {} has no .endm", definition.name),
+            definition.span,
+        ));
+    }
+    remaining
+}
+
+// .macro NAME PARAMETER, ...
+fn definition(line: &[SpannedToken], position: usize) -> Result<Macro, Diagnostic> {
+    let directive_span = line[position].span.range();
+    let (name, span) = match line.get(position + 1) {
+        Some(SpannedToken {
+            token: Token::Identifier(name),
+            span,
+        }) => (name.clone(), span.range()),
+        Some(token) if !end_of_line(&token.token) => {
+            return Err(Diagnostic::error(
+                "Expected a macro name that is not an instruction or register",
+                token.span.range(),
+            ))
+        }
+        _ => {
+            return Err(Diagnostic::error(
+                format!(".macro expects {}", Directive::Macro.syntax()),
+                directive_span,
+            ))
+        }
+    };
+
+    let mut parameters: Vec<String> = Vec::new();
+    let mut offset = position + 2;
+    while let Some(token) = line.get(offset).filter(|token| !end_of_line(&token.token)) {
+        match &token.token {
+            Token::Identifier(parameter) if parameters.contains(parameter) => {
+                return Err(Diagnostic::error(
+                    format!("Duplicate parameter: {}", parameter),
+                    token.span.range(),
+                ))
+            }
+            Token::Identifier(parameter) => parameters.push(parameter.clone()),
+            _ => {
+                return Err(Diagnostic::error(
+                    "Expected a parameter name that is not an instruction or register",
+                    token.span.range(),
+                ))
+            }
+        }
+        offset += 1;
+        match line.get(offset).map(|token| &token.token) {
+            Some(Token::Comma) => offset += 1,
+            Some(token) if !end_of_line(token) => {
+                return Err(Diagnostic::error(
+                    "Expected comma",
+                    line[offset].span.range(),
+                ))
+            }
+            _ => break,
+        }
+    }
+
+    Ok(Macro {
+        name,
+        parameters,
+        body: Vec::new(),
+        span,
+    })
+}
+
+fn expand_lines(
+    lines: &[Vec<SpannedToken>],
+    calls: &[MacroCall],
+    counter: &mut usize,
+    expansion: &mut Expansion,
+) {
+    for line in lines {
+        let (first, position) = statement(line);
+        let definition = match first.map(|token| &token.token) {
+            Some(Token::Identifier(name)) => expansion.macros.get(name).cloned(),
+            _ => None,
+        };
+        let definition = match definition {
+            Some(definition) => definition,
+            None => {
+                expansion.lines.push(line.clone());
+                expansion.calls.push(calls.to_vec());
+                continue;
+            }
+        };
+
+        // the label of the call marks the first line of the expansion
+        if position == 1 {
+            let label = line[0].clone();
+            let mut newline = label.clone();
+            newline.token = Token::NewLine;
+            newline.span.start = label.span.end;
+            expansion.lines.push(vec![label, newline]);
+            expansion.calls.push(calls.to_vec());
+        }
+
+        let arguments = arguments(line, position + 1);
+        let end = arguments
+            .last()
+            .and_then(|argument| argument.last())
+            .map_or(line[position].span.end, |token| token.span.end);
+        let call = MacroCall {
+            name: definition.name.clone(),
+            span: line[position].span.start..end,
+        };
+
+        let mut nested = vec![call.clone()];
+        nested.extend_from_slice(calls);
+        let fail = |expansion: &mut Expansion, diagnostic: Diagnostic| {
+            expansion.diagnostics.push(diagnostic);
+            expansion.diagnostics.extend(expansion_notes(calls));
+        };
+
+        if calls.len() >= MAX_DEPTH || calls.iter().any(|outer| outer.name == call.name) {
+            fail(
+                expansion,
+                Diagnostic::error(
+                    format!("Macro {} calls itself", call.name),
+                    call.span.clone(),
+                ),
+            );
+            continue;
+        }
+        if let Some(empty) = arguments.iter().position(|argument| argument.is_empty()) {
+            fail(
+                expansion,
+                Diagnostic::error(
+                    format!("Argument {} of {} is empty", empty + 1, call.name),
+                    call.span.clone(),
+                ),
+            );
+            continue;
+        }
+        if arguments.len() != definition.parameters.len() {
+            fail(
+                expansion,
+                Diagnostic::error(
+                    format!(
+                        "Macro {} expects {} argument(s) ({}), found {}",
+                        call.name,
+                        definition.parameters.len(),
+                        definition.parameters.join(", "),
+                        arguments.len()
+                    ),
+                    call.span.clone(),
+                ),
+            );
+            continue;
+        }
+
+        *counter += 1;
+        let body = substitute(&definition, &arguments, *counter);
+        expand_lines(&body, &nested, counter, expansion);
+
+        // keep the end of the file, if the call was on the last line
+        if let Some(eof) = line.last().filter(|token| token.token == Token::EOF) {
+            expansion.lines.push(vec![eof.clone()]);
+            expansion.calls.push(calls.to_vec());
+        }
+    }
+}
+
+// The arguments of a call starting at `start`, split at the commas outside {} and ().
+fn arguments(line: &[SpannedToken], start: usize) -> Vec<Vec<SpannedToken>> {
+    let mut arguments = Vec::new();
+    let mut argument = Vec::new();
+    let mut depth = 0;
+    let mut any = false;
+
+    for token in line.iter().skip(start) {
+        if end_of_line(&token.token) {
+            break;
+        }
+        any = true;
+        match token.token {
+            Token::LeftBrace | Token::LeftParen => depth += 1,
+            Token::RightBrace | Token::RightParen => depth -= 1,
+            Token::Comma if depth <= 0 => {
+                arguments.push(std::mem::take(&mut argument));
+                continue;
+            }
+            _ => {}
+        }
+        argument.push(token.clone());
+    }
+    if any {
+        arguments.push(argument);
+    }
+    arguments
+}
+
+// The body of `definition` with its parameters replaced and its labels made local to this
+// expansion.
+fn substitute(
+    definition: &Macro,
+    arguments: &[Vec<SpannedToken>],
+    counter: usize,
+) -> Vec<Vec<SpannedToken>> {
+    let local_labels: Vec<String> = definition
+        .body
+        .iter()
+        .filter_map(|line| match line.first().map(|token| &token.token) {
+            Some(Token::Label(label)) if !labels::is_numeric(label.trim_end_matches(':')) => {
+                Some(label.trim_end_matches(':').to_string())
+            }
+            _ => None,
+        })
+        .collect();
+    let local = |name: &str| format!("{}@{}", name, counter);
+
+    definition
+        .body
+        .iter()
+        .map(|line| {
+            let mut expanded = Vec::with_capacity(line.len());
+            for token in line {
+                match &token.token {
+                    Token::Identifier(name) => {
+                        if let Some(index) = definition.parameters.iter().position(|p| p == name) {
+                            expanded.extend(arguments[index].iter().cloned());
+                            continue;
+                        }
+                        if local_labels.contains(name) {
+                            expanded.push(SpannedToken {
+                                token: Token::Identifier(local(name)),
+                                span: token.span,
+                            });
+                            continue;
+                        }
+                        expanded.push(token.clone());
+                    }
+                    Token::Label(label) if !labels::is_numeric(label.trim_end_matches(':')) => {
+                        let name = label.trim_end_matches(':');
+                        expanded.push(SpannedToken {
+                            token: Token::Label(format!("{}:", local(name))),
+                            span: token.span,
+                        });
+                    }
+                    _ => expanded.push(token.clone()),
+                }
+            }
+            expanded
+        })
+        .collect()
+}
@@ -1,88 +1,407 @@
-use iitb_cpu::crates::assembler::dissasembler;
-use iitb_cpu::lexer::{Lexer, TokenStream};
-use iitb_cpu::parser::Parser;
+use iitb_cpu::conditional::parse_define;
+use iitb_cpu::crates::assembler::{assemble_sources, AssembleOptions};
+use iitb_cpu::crates::debuginfo::debug_info;
+use iitb_cpu::crates::disassembler::disassemble;
+use iitb_cpu::crates::listing::listing;
+use iitb_cpu::crates::object::{assemble_object, link, LinkOptions, Object};
+use iitb_cpu::crates::output::{self, Format};
+use iitb_cpu::crates::vhdl::{write_vhdl, VhdlOptions};
+use iitb_cpu::format::format;
+use iitb_cpu::lexer::{parse_number, Lexer, Processor, TokenStream};
+use iitb_cpu::source::SourceMap;
 use iitb_cpu::texteditor::tesh_editor;
 
 use std::env;
-use std::fs::File;
-use std::io::{self, Read};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
+// iitb_cpu [FILE] [-D NAME[=VALUE]]... [-I DIRECTORY]... [-o IMAGE [--format NAME]]
+//          [--vhdl FILE.vhd [--entity] [--width BITS] [--depth WORDS]] [--list FILE.lst]
+//          [--object FILE.o] [--debug FILE.dbg] [--processor single-cycle|pipelined]
+// iitb_cpu fmt [--check] [--processor single-cycle|pipelined] FILE...
+// iitb_cpu link [--text ADDRESS] [--data ADDRESS] -o IMAGE [--format NAME] OBJECT...
+// iitb_cpu disasm [--format NAME] [--processor single-cycle|pipelined] IMAGE
 fn main() -> io::Result<()> {
     env::set_var("RUST_BACKTRACE", "1");
-    let file_name = "./src/test/test.asm";
-    let mut file = File::open(file_name)?;
+    match env::args().nth(1).as_deref() {
+        Some("fmt") => {
+            let formatted = fmt(env::args().skip(2).collect())?;
+            std::process::exit(if formatted { 0 } else { 1 });
+        }
+        Some("link") => {
+            let linked = link_objects(env::args().skip(2).collect())?;
+            std::process::exit(if linked { 0 } else { 1 });
+        }
+        Some("disasm") => {
+            let read = disasm(env::args().skip(2).collect())?;
+            std::process::exit(if read { 0 } else { 1 });
+        }
+        _ => {}
+    }
+    let mut file_name = String::from("./src/test/test.asm");
+    let mut options = AssembleOptions::default();
+    let mut image = None;
+    let mut image_format = None;
+    let mut vhdl = None;
+    let mut vhdl_options = VhdlOptions::default();
+    let mut list = None;
+    let mut object = None;
+    let mut debug = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--entity" {
+            vhdl_options.entity = true;
+            continue;
+        }
+        // -D NAME=1 and -DNAME=1 are both accepted, as are -I DIR and -IDIR, -o FILE and -oFILE
+        let (flag, value) = match arg.get(..2) {
+            _ if matches!(
+                arg.as_str(),
+                "--format"
+                    | "--vhdl"
+                    | "--width"
+                    | "--depth"
+                    | "--list"
+                    | "--object"
+                    | "--debug"
+                    | "--processor"
+            ) =>
+            {
+                (arg, args.next())
+            }
+            Some(flag @ ("-D" | "-I" | "-o")) if arg.len() > 2 => {
+                (flag.to_string(), Some(arg[2..].to_string()))
+            }
+            Some(flag @ ("-D" | "-I" | "-o")) => (flag.to_string(), args.next()),
+            _ => {
+                file_name = arg;
+                continue;
+            }
+        };
+        let value = match value {
+            Some(value) => value,
+            None => {
+                eprintln!("{} expects a value", flag);
+                std::process::exit(2);
+            }
+        };
+        match flag.as_str() {
+            "-I" => {
+                options.include_paths.push(PathBuf::from(value));
+                continue;
+            }
+            "-o" => {
+                image = Some(value);
+                continue;
+            }
+            "--format" => {
+                image_format = Some(format_named(&value));
+                continue;
+            }
+            "--vhdl" => {
+                vhdl = Some(value);
+                continue;
+            }
+            "--list" => {
+                list = Some(value);
+                continue;
+            }
+            "--object" => {
+                object = Some(value);
+                continue;
+            }
+            "--debug" => {
+                debug = Some(value);
+                continue;
+            }
+            "--processor" => {
+                options.processor = processor_named(&value);
+                continue;
+            }
+            "--width" | "--depth" => {
+                let number = value.parse::<usize>().unwrap_or_else(|_| {
+                    eprintln!("{} expects a number", flag);
+                    std::process::exit(2);
+                });
+                if flag == "--width" {
+                    vhdl_options.width = number;
+                } else {
+                    vhdl_options.depth = Some(number);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        match parse_define(&value) {
+            Ok((name, value)) => {
+                options.defines.insert(name, value);
+            }
+            Err(message) => {
+                eprintln!("{}", message);
+                std::process::exit(2);
+            }
+        }
+    }
 
-    let mut sample = String::new();
-    file.read_to_string(&mut sample)?;
+    let mut sources = SourceMap::new();
+    sources.load(Path::new(&file_name))?;
+    let sample = sources.files[0].text.clone();
     println!("File content:\n{}", sample);
 
     let _token_stream = TokenStream::new();
-    let _lexer = Lexer::new(&sample.as_str());
+    let _lexer = Lexer::new(&sample);
 
-    let line_iter = sample.lines();
-
-    let mut parser = Parser::new(&sample.as_str());
-
-    match parser.parse() {
-        Ok(_) => println!("Parsed successfully"),
-        Err(e) => {
-            let line_number = e.line_number;
-            let column_number = e.column_number;
-
-            if let Some(line) = line_iter.clone().nth(line_number - 1) {
-                let column_count = count_char_columns(line, column_number);
-                println!("--> at {}:{}:{}", file_name, line_number, column_count);
-                println!("{}", line);
-                println!("{: <1$}^", "", column_count);
-                println!("[Parsing Error]: {:?}\n", e.message);
-            } else {
-                println!("Error: Could not fetch the line for the error.");
+    if let Some(object) = object {
+        match assemble_object(&mut sources, &options) {
+            Ok(assembled) => {
+                for warning in assembled.warnings.iter() {
+                    println!("{}", sources.render(warning));
+                }
+                fs::write(&object, assembled.write())?;
+                println!("Wrote {}", object);
+                return Ok(());
+            }
+            Err(diagnostics) => {
+                for diagnostic in diagnostics.iter() {
+                    println!("{}", sources.render(diagnostic));
+                }
+                std::process::exit(1);
             }
         }
     }
 
-    println!(
-        "\nInstructions: {:?}\nLabels: {:?}",
-        parser.instructions, parser.token_stream
-    );
+    match assemble_sources(&mut sources, &options) {
+        Ok(program) => {
+            for warning in program.warnings.iter() {
+                println!("{}", sources.render(warning));
+            }
+            println!("Assembled successfully");
+            if let Some(list) = list {
+                fs::write(&list, listing(&program, &sources))?;
+                println!("Wrote {}", list);
+            }
+            if let Some(debug) = debug {
+                fs::write(&debug, debug_info(&program).write())?;
+                println!("Wrote {}", debug);
+            }
+            if let Some(image) = image {
+                let image_format = image_format
+                    .or_else(|| Format::from_extension(&image))
+                    .unwrap_or_else(|| {
+                        eprintln!("Cannot tell the format of {}; use --format NAME", image);
+                        std::process::exit(2);
+                    });
+                fs::write(&image, output::write(&program.words, image_format))?;
+                println!("Wrote {} ({})", image, image_format);
+            }
+            if let Some(vhdl) = vhdl {
+                // the package or entity is named after the file
+                if let Some(stem) = Path::new(&vhdl).file_stem() {
+                    vhdl_options.name = stem.to_string_lossy().into_owned();
+                }
+                match write_vhdl(&program, &sources, &vhdl_options) {
+                    Ok(text) => {
+                        fs::write(&vhdl, text)?;
+                        println!("Wrote {}", vhdl);
+                    }
+                    Err(message) => {
+                        eprintln!("{}", message);
+                        std::process::exit(2);
+                    }
+                }
+            }
+        }
+        Err(diagnostics) => {
+            for diagnostic in diagnostics.iter() {
+                println!("{}", sources.render(diagnostic));
+            }
+            let error_count = diagnostics.iter().filter(|d| d.is_error()).count();
+            println!("[Parsing Error]: {} error(s) found\n", error_count);
+        }
+    }
 
-    dissasembler(parser);
     tesh_editor();
 
     Ok(())
 }
 
-fn count_char_columns(line: &str, column_count: usize) -> usize {
-    let mut count = 0;
-    let mut remaining_columns = column_count - 1;
-
-    // Skip initial spaces
-    while line.chars().nth(count) == Some(' ') {
-        count += 1;
+// Formats each file in place. With --check, only lists the files that are not formatted, and
+// returns whether there were none.
+fn fmt(args: Vec<String>) -> io::Result<bool> {
+    let mut check = false;
+    let mut processor = Processor::Pipelined;
+    let mut files = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--processor" => match args.next() {
+                Some(value) => processor = processor_named(&value),
+                None => {
+                    eprintln!("--processor expects a value");
+                    std::process::exit(2);
+                }
+            },
+            _ => files.push(arg),
+        }
     }
-    if count > 0 && remaining_columns > 0 {
-        remaining_columns -= 1;
+    if files.is_empty() {
+        eprintln!("fmt expects at least one FILE");
+        std::process::exit(2);
+    }
+    let mut formatted = true;
+    for file in files {
+        let source = fs::read_to_string(&file)?;
+        let output = format(&source, processor);
+        if output == source {
+            continue;
+        }
+        if check {
+            println!("Not formatted: {}", file);
+            formatted = false;
+        } else {
+            fs::write(file, output)?;
+        }
+    }
+    Ok(formatted)
+}
+
+fn processor_named(name: &str) -> Processor {
+    Processor::from_name(name).unwrap_or_else(|| {
+        let names: Vec<&str> = Processor::ALL
+            .iter()
+            .map(|processor| processor.name())
+            .collect();
+        eprintln!(
+            "Unknown processor {}; use one of {}",
+            name,
+            names.join(", ")
+        );
+        std::process::exit(2);
+    })
+}
+
+fn format_named(name: &str) -> Format {
+    Format::from_name(name).unwrap_or_else(|| {
+        let names: Vec<&str> = Format::ALL.iter().map(|format| format.name()).collect();
+        eprintln!("Unknown format {}; use one of {}", name, names.join(", "));
+        std::process::exit(2);
+    })
+}
+
+// Links objects into a memory image, and returns whether it succeeded.
+fn link_objects(args: Vec<String>) -> io::Result<bool> {
+    let mut options = LinkOptions::default();
+    let mut image = None;
+    let mut image_format = None;
+    let mut objects = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !matches!(arg.as_str(), "--text" | "--data" | "-o" | "--format") {
+            let text = fs::read_to_string(&arg)?;
+            match Object::read(&text) {
+                Ok(object) => objects.push((arg, object)),
+                Err(message) => {
+                    eprintln!("{}: {}", arg, message);
+                    return Ok(false);
+                }
+            }
+            continue;
+        }
+        let Some(value) = args.next() else {
+            eprintln!("{} expects a value", arg);
+            std::process::exit(2);
+        };
+        match arg.as_str() {
+            "-o" => image = Some(value),
+            "--format" => image_format = Some(format_named(&value)),
+            _ => {
+                let address = parse_number(&value)
+                    .ok()
+                    .and_then(|address| u16::try_from(address).ok())
+                    .unwrap_or_else(|| {
+                        eprintln!("{} expects an address from 0 to 0xFFFF", arg);
+                        std::process::exit(2);
+                    });
+                if arg == "--text" {
+                    options.text = address;
+                } else {
+                    options.data = Some(address);
+                }
+            }
+        }
     }
+    let (Some(image), false) = (image, objects.is_empty()) else {
+        eprintln!("link expects -o IMAGE and at least one OBJECT");
+        std::process::exit(2);
+    };
+    let image_format = image_format
+        .or_else(|| Format::from_extension(&image))
+        .unwrap_or_else(|| {
+            eprintln!("Cannot tell the format of {}; use --format NAME", image);
+            std::process::exit(2);
+        });
 
-    while let Some(c) = line.chars().nth(count) {
-        if remaining_columns == 0 {
-            break;
+    match link(&objects, &options) {
+        Ok(linked) => {
+            fs::write(&image, output::write(&linked.words, image_format))?;
+            println!("Wrote {} ({})", image, image_format);
+            Ok(true)
         }
-        count += 1;
-        if c == ':' || c == ',' {
-            remaining_columns -= 1;
-            count += 1;
-            while line.chars().nth(count) == Some(' ') {
-                count += 1;
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
             }
+            Ok(false)
         }
-        if c == ' ' {
-            remaining_columns -= 1;
-            // Skip the spaces
-            while line.chars().nth(count) == Some(' ') || line.chars().nth(count) == Some(',') {
-                count += 1;
+    }
+}
+
+// Prints the assembly for a memory image, e.g. one read back from a board, and returns
+// whether the image could be read.
+fn disasm(args: Vec<String>) -> io::Result<bool> {
+    let mut image = None;
+    let mut image_format = None;
+    let mut processor = Processor::Pipelined;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if !matches!(arg.as_str(), "--format" | "--processor") {
+            if image.replace(arg).is_some() {
+                eprintln!("disasm expects a single IMAGE");
+                std::process::exit(2);
             }
+            continue;
+        }
+        let Some(value) = args.next() else {
+            eprintln!("{} expects a value", arg);
+            std::process::exit(2);
+        };
+        if arg == "--format" {
+            image_format = Some(format_named(&value));
+        } else {
+            processor = processor_named(&value);
+        }
+    }
+    let Some(image) = image else {
+        eprintln!("disasm expects an IMAGE");
+        std::process::exit(2);
+    };
+    let image_format = image_format
+        .or_else(|| Format::from_extension(&image))
+        .unwrap_or_else(|| {
+            eprintln!("Cannot tell the format of {}; use --format NAME", image);
+            std::process::exit(2);
+        });
+
+    match output::read(&fs::read(&image)?, image_format) {
+        Ok(words) => {
+            print!("{}", disassemble(&words, processor));
+            Ok(true)
+        }
+        Err(message) => {
+            eprintln!("{}: {}", image, message);
+            Ok(false)
         }
     }
-    count
 }
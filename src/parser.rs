@@ -1,19 +1,39 @@
 // A parser for the Assembly Language for the IITB RISC-V Processor.
 //
 // This parser supports:
-// - 24 instructions for the Pipelined Architecture
-// - 14 instructions for the Single Cycle Architecture
+// - the instructions of the Pipelined and Single Cycle Architectures, see `isa`
+// - the directives .org, .word, .fill, .space, .equ, .text and .data
+// - macros, see `macros`
+// - .include, see `source`
+// - .if, .ifdef, .ifndef, .else and .endif, and constants defined from outside, see
+//   `conditional`
+// - the pseudo-instructions NOP, MOV, LI, B, CALL, RET and CLR, see `pseudo`
+// - local labels (.loop) and numeric labels (1:, referenced as 1f and 1b), see `labels`
+// - .global and .extern, and relocations for an object that is linked later, see
+//   `crates::object`
 //
 // The ISA was developed by Prof. Virendra Singh, IIT Bombay.
 // For more information, see <https://www.ee.iitb.ac.in/~viren/>.
 // The ISA is based on the RISC-V ISA and has been modified to suit the needs of the EE309 and EE224 courses at IIT Bombay.
 //
 
-use crate::lexer::{Lexer, Processor, Token, TokenStream};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::diagnostic::Diagnostic;
+use crate::expression::{Expression, ExpressionKind, Operator, Value};
+use crate::isa::{self, Immediate, InstructionClass, InstructionDescriptor, Opcode, Operands};
+use crate::labels;
+use crate::lexer::{Directive, Lexer, Processor, SpannedToken, Token, TokenStream};
+use crate::macros::{self, Macro, MacroCall};
+use crate::pseudo::{self, PseudoOp, PseudoOperands};
+use crate::source::{self, SourceMap};
+use crate::suggest;
 
 #[derive(Debug, Clone)]
 pub struct Instruction {
-    pub opcode: String,
+    pub opcode: Opcode,
     pub reg_a: i32,
     pub reg_b: Option<i32>, // optional
     pub reg_c: Option<i32>,
@@ -21,11 +41,16 @@ pub struct Instruction {
     pub line_number: usize,
     pub column_number: usize,
     pub processor: Processor, //use this to determine the type of instruction
+    pub span: Range<usize>,   // bytes from the opcode to the last operand
+    pub address: u16,         // word address in the memory image
+    pub pseudo: Option<PseudoOp>, // the pseudo-instruction it was lowered from
+    pub relocation: Option<Relocation>, // for an immediate the linker fills in
 }
 
 impl Instruction {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        opcode: String,
+        opcode: Opcode,
         reg_a: i32,
         reg_b: Option<i32>,
         reg_c: Option<i32>,
@@ -43,17 +68,69 @@ impl Instruction {
             line_number,
             column_number,
             processor,
+            span: 0..0,
+            address: 0,
+            pseudo: None,
+            relocation: None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Section {
+    Text,
+    Data,
+}
+
+impl Section {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Section::Text => ".text",
+            Section::Data => ".data",
         }
     }
 }
 
+// A word of an object that the linker fills in once it has placed the sections: the address
+// of `symbol` plus `addend`, in the field given by `kind`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: Section, // of the word
+    pub offset: u16,      // of the word, from the start of its section
+    pub kind: RelocationKind,
+    pub symbol: String, // an .extern, or .text or .data for an address in the same object
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationKind {
+    Field(Immediate), // the immediate of an instruction; an Offset field holds the distance
+    Word,             // a whole data word
+}
+
+// Words emitted by .word, .fill or .space, starting at `address`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Data {
+    pub address: u16,
+    pub words: Vec<u16>,
+    pub line_number: usize,
+    pub span: Range<usize>,           // the directive and its operands
+    pub relocations: Vec<Relocation>, // for the words the linker fills in
+}
+
+// A location counter. Until .data gets an .org of its own, its addresses are counted from
+// the end of .text, which is only known once the whole program has been laid out.
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    address: u32,
+    relative: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct Parser {
     pub token_stream: TokenStream,
     pub lexer: Lexer,                   // contains the original sample
     pub instructions: Vec<Instruction>, // the final program - contains labels separated instructions
-    pub labels: Vec<String>,
-    pub label_line_numbers: Vec<usize>,
     // contains the labels
     // Example:
     // MAIN: ADI R1, R2, 10 // I1
@@ -65,38 +142,92 @@ pub struct Parser {
     // The above program will be stored as:
     // instructions = [[I1, I2, I3], [I4, I5, I6]]
     // labels = [MAIN, NEXT]
-}
-
-#[derive(Debug, Clone)]
-pub struct ParserError {
-    pub message: String,
-    pub line_number: usize,
-    pub column_number: usize,
+    pub labels: Vec<String>,
+    pub label_line_numbers: Vec<usize>,
+    pub symbol_table: BTreeMap<String, u16>, // label -> address of the instruction it marks
+    pub constants: BTreeMap<String, Value>,  // .equ name -> value
+    pub data: Vec<Data>,
+    pub line_addresses: Vec<Option<u16>>, // of the statement on each line; None past memory
+    pub line_sizes: Vec<u32>,             // words emitted by each line
+    pub macros: BTreeMap<String, Macro>,
+    pub macro_calls: Vec<Vec<MacroCall>>, // per line: the macro calls it was expanded from
+    expansion_diagnostics: Vec<Diagnostic>,
+    pub sources: SourceMap, // the top-level file and every file it includes
+    pub defines: BTreeMap<String, i64>, // constants defined from outside the program
+    pub processor: Processor,
+    pub relocatable: bool, // assembling an object; see `crates::object`
+    pub globals: BTreeMap<String, Range<usize>>, // .global name -> where it is declared
+    pub externs: BTreeMap<String, Range<usize>>, // .extern name -> where it is declared
+    pub label_sections: BTreeMap<String, Section>,
+    pub text_end: u32, // where .data starts, unless it is moved with .org
 }
 
 impl Parser {
     pub fn new(sample: &str) -> Parser {
+        Parser::with_processor(sample, Processor::Pipelined)
+    }
+
+    pub fn with_processor(sample: &str, processor: Processor) -> Parser {
+        let mut sources = SourceMap::new();
+        sources.add("<input>", sample);
+        Parser::with_sources(sources, processor, &[], &BTreeMap::new())
+    }
+
+    // A parser for file 0 of `sources`. The files it includes are added to `sources`, and
+    // spans run across all of them; see `source`. `defines` are constants for the whole
+    // program, such as -D FORWARDING=1.
+    pub fn with_sources(
+        mut sources: SourceMap,
+        processor: Processor,
+        include_paths: &[PathBuf],
+        defines: &BTreeMap<String, i64>,
+    ) -> Parser {
+        if sources.files.is_empty() {
+            sources.add("<input>", "");
+        }
         let mut token_stream = TokenStream::new();
-        let mut lexer = Lexer::new(sample);
+        let lexer = Lexer::new(&sources.files[0].text);
         let instructions = Vec::new();
-        let mut labels = Vec::new();
         let label_line_numbers = Vec::new();
 
-        loop {
-            let token = lexer.next_token(Processor::Pipelined);
-            token_stream.add(token.clone());
-            if let Token::Label(label) = token {
-                labels.push(label);
-            } else if token == Token::EOF {
-                break;
-            }
-        }
+        let inclusion = source::include(&mut sources, 0, processor, include_paths, defines);
+        let expansion = macros::expand(&inclusion.lines);
+        let mut diagnostics = inclusion.diagnostics;
+        diagnostics.extend(expansion.diagnostics);
+        token_stream.tokens_by_line = expansion.lines;
+        labels::qualify(&mut token_stream.tokens_by_line);
+        let labels = token_stream
+            .tokens_by_line
+            .iter()
+            .flatten()
+            .filter_map(|token| match &token.token {
+                Token::Label(label) => Some(label.clone()),
+                _ => None,
+            })
+            .collect();
+
         Parser {
             token_stream,
             lexer,
             instructions,
             labels,
             label_line_numbers,
+            symbol_table: BTreeMap::new(),
+            constants: BTreeMap::new(),
+            data: Vec::new(),
+            line_addresses: Vec::new(),
+            line_sizes: Vec::new(),
+            macros: expansion.macros,
+            macro_calls: expansion.calls,
+            expansion_diagnostics: diagnostics,
+            sources,
+            defines: defines.clone(),
+            processor,
+            relocatable: false,
+            globals: BTreeMap::new(),
+            externs: BTreeMap::new(),
+            label_sections: BTreeMap::new(),
+            text_end: 0,
         }
     }
 
@@ -104,290 +235,1203 @@ impl Parser {
         self.instructions.push(instruction);
     }
 
-    pub fn parse(&mut self) -> Result<(Self), ParserError> {
-        // fields for instruction
-
-        //let  mut instructioin:Instruction = Instruction::new(opcode, reg_a, reg_b, reg_c, imm, line_number, processor);
-
-        // fields for parser
-        let processor = Processor::Pipelined;
-
-        // Data for the instruction
-        let opcodes_with_three_register_pipelined = vec![
-            "ADA", //00_01 RA RB RC 0 00
-            "ADC", //00_01 RA RB RC 0 10
-            "ADZ", //00_01 RA RB RC 0 01
-            "AWC", //00_01 RA RB RC 0 11
-            "ACA", //00_01 RA RB RC 1 00
-            "ACC", //00_01 RA RB RC 1 10
-            "ACZ", //00_01 RA RB RC 1 01
-            "ACW", //00_01 RA RB RC 1 11
-            "NDU", //00_10 RA RB RC 0 00
-            "NDC", //00_10 RA RB RC 0 10
-            "NDZ", //00_10 RA RB RC 0 01
-            "NCU", //00_10 RA RB RC 1 00
-            "NCC", //00_10 RA RB RC 1 10
-            "NCZ", //00_10 RA RB RC 1 01
-        ];
+    // First pass: lay out every line, and assign labels and .equ constants their values.
+    // Addresses count 16-bit words from 0. .text starts at address 0 and .data follows the end
+    // of .text, unless either is moved with .org.
+    fn build_symbol_table(&mut self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut definitions: BTreeMap<String, Range<usize>> = BTreeMap::new();
+        let mut constants: BTreeMap<String, Value> = self
+            .defines
+            .iter()
+            .map(|(name, value)| (name.clone(), Value::number(*value)))
+            .collect();
+        let mut deferred = Vec::new(); // .equ constants that name a label: (name, expression)
+        let mut labels = Vec::new(); // (name, location, section)
+        self.globals.clear();
+        self.externs.clear();
+        let mut lines = Vec::new(); // (location, size, span) of each line
 
-        let opcodes_with_two_register_pipelined = vec![
-            "ADI", //00_00 RA RB IMM6
-            "LW",  //01_00 RA RB IMM6
-            "SW",  //01_01 RA RB IMM6
-            "BEQ", //10_00 RA RB IMM6
-            "BLT", //10_01 RA RB IMM6
-            "BLE", //10_10 RA RB IMM6
+        let mut section = Section::Text;
+        let mut counters = [
+            Location {
+                address: 0,
+                relative: false,
+            },
+            Location {
+                address: 0,
+                relative: true,
+            },
         ];
+        let mut text_end = 0;
 
-        let opcodes_with_single_register_pipelined = vec![
-            "LLI", //00_11 RA IMM9
-            "LM",  //01_10 RA 0 + 8 bits corresponding to R0 to R7
-            "SM",  //01_11 RA 0 + 8 bits corresponding to R0 to R7
-            "JAL", //11_00 RA IMM9
-            "JLR", //11_01 RA 0 0000
-            "JRI", //11_11 RA 0 0000
-        ];
+        for (line_index, tokens) in self.token_stream.tokens_by_line.iter().enumerate() {
+            let diagnostics_before = diagnostics.len();
+            let (label, position) = match tokens.first() {
+                Some(SpannedToken {
+                    token: Token::Label(label),
+                    span,
+                }) => (Some((label.trim_end_matches(':'), span.range())), 1),
+                _ => (None, 0),
+            };
+            let span = match tokens.get(position) {
+                Some(token) => token.span.start..tokens[tokens.len() - 1].span.end,
+                None => 0..0,
+            };
 
-        let mut instructions_to_add = Vec::new();
-        let mut label_count = 0;
+            let size = match tokens.get(position).map(|token| &token.token) {
+                Some(Token::Opcode(_)) => 1,
+                Some(Token::Identifier(name)) => match PseudoOp::from_mnemonic(name) {
+                    // LI is shorter when its value is a constant that is already known
+                    Some(op) => {
+                        let value = match pseudo_operands(op, tokens, position) {
+                            Ok((_, _, Some(value), _))
+                                if value
+                                    .symbols()
+                                    .iter()
+                                    .all(|(symbol, _)| constants.contains_key(*symbol)) =>
+                            {
+                                constant_value(&constants, &value)
+                                    .ok()
+                                    .filter(|value| (-0x8000..=0xFFFF).contains(&value.value))
+                                    .map(|value| value.value as u16)
+                            }
+                            _ => None,
+                        };
+                        op.length(self.processor, value) as u32
+                    }
+                    None => 0,
+                },
+                Some(Token::Directive(directive)) => {
+                    let mut lay_out = || -> Result<u32, Diagnostic> {
+                        let mut operands = directive_operands(*directive, tokens, position)?;
+                        match directive {
+                            Directive::Org if self.relocatable => Err(Diagnostic::error(
+                                "An object cannot use .org: the linker places its sections",
+                                tokens[position].span.range(),
+                            )),
+                            Directive::Org => {
+                                let address = constant(&constants, &operands[0])?;
+                                if !(0..=0xFFFF).contains(&address) {
+                                    return Err(Diagnostic::error(
+                                        format!(
+                                            "Address {} is outside memory (0 to 0xFFFF)",
+                                            address
+                                        ),
+                                        operands[0].span.clone(),
+                                    ));
+                                }
+                                counters[section as usize] = Location {
+                                    address: address as u32,
+                                    relative: false,
+                                };
+                                Ok(0)
+                            }
+                            Directive::Word => Ok(operands.len() as u32),
+                            Directive::Fill | Directive::Space => {
+                                let count = constant(&constants, &operands[0])?;
+                                if !(0..=0xFFFF).contains(&count) {
+                                    return Err(Diagnostic::error(
+                                        format!(
+                                            "Count {} is out of range for {} (0 to 65535)",
+                                            count, directive
+                                        ),
+                                        operands[0].span.clone(),
+                                    ));
+                                }
+                                Ok(count as u32)
+                            }
+                            Directive::Equ => {
+                                let name = operands[0].to_string();
+                                if let Some(first_definition) = definitions.get(&name) {
+                                    diagnostics.extend(duplicate(
+                                        "constant",
+                                        &name,
+                                        operands[0].span.clone(),
+                                        first_definition,
+                                    ));
+                                    return Ok(0);
+                                }
+                                if self.defines.contains_key(&name) {
+                                    return Err(predefined(
+                                        "constant",
+                                        &name,
+                                        operands[0].span.clone(),
+                                    ));
+                                }
+                                definitions.insert(name.clone(), operands[0].span.clone());
+                                // a constant that names a label gets its value once the
+                                // labels have addresses, and cannot be used for the layout
+                                let value = operands.swap_remove(1);
+                                if value
+                                    .symbols()
+                                    .iter()
+                                    .all(|(symbol, _)| constants.contains_key(*symbol))
+                                {
+                                    constants.insert(name, constant_value(&constants, &value)?);
+                                } else {
+                                    deferred.push((name, value));
+                                }
+                                Ok(0)
+                            }
+                            Directive::Text => {
+                                section = Section::Text;
+                                Ok(0)
+                            }
+                            Directive::Data => {
+                                section = Section::Data;
+                                Ok(0)
+                            }
+                            Directive::Global | Directive::Extern => {
+                                let names = match directive {
+                                    Directive::Global => &mut self.globals,
+                                    _ => &mut self.externs,
+                                };
+                                for operand in operands.iter() {
+                                    match &operand.kind {
+                                        ExpressionKind::Symbol(name) => {
+                                            names
+                                                .entry(name.clone())
+                                                .or_insert(operand.span.clone());
+                                        }
+                                        _ => {
+                                            return Err(Diagnostic::error(
+                                                format!("Expected a label name, found {}", operand),
+                                                operand.span.clone(),
+                                            ))
+                                        }
+                                    }
+                                }
+                                Ok(0)
+                            }
+                            // taken out before parsing
+                            Directive::Macro
+                            | Directive::Endm
+                            | Directive::Include
+                            | Directive::If
+                            | Directive::Ifdef
+                            | Directive::Ifndef
+                            | Directive::Else
+                            | Directive::Endif => Ok(0),
+                        }
+                    };
+                    match lay_out() {
+                        Ok(size) => size,
+                        Err(diagnostic) => {
+                            diagnostics.push(diagnostic);
+                            0
+                        }
+                    }
+                }
+                _ => 0,
+            };
+
+            // a label marks the first word of its line, after any .org or section switch
+            let location = counters[section as usize];
+            if let Some((name, label_span)) = label {
+                if let Some(first_definition) = definitions.get(name) {
+                    diagnostics.extend(duplicate("label", name, label_span, first_definition));
+                } else if self.defines.contains_key(name) {
+                    diagnostics.push(predefined("label", name, label_span));
+                } else {
+                    definitions.insert(name.to_string(), label_span);
+                    labels.push((name.to_string(), location, section));
+                }
+            }
+            counters[section as usize].address += size;
+            if section == Section::Text {
+                text_end = text_end.max(counters[0].address);
+            }
+            lines.push((location, size, span));
+            self.note_expansion(line_index, &mut diagnostics, diagnostics_before);
+        }
 
-        for (line_number, token_by_lines) in self.token_stream.tokens_by_line.iter().enumerate() {
-            let mut token_position: usize = 0;
+        let absolute = |location: Location| {
+            if location.relative {
+                location.address + text_end
+            } else {
+                location.address
+            }
+        };
+        self.line_addresses.clear();
+        self.line_sizes.clear();
+        for (location, size, span) in lines {
+            let address = absolute(location);
+            // a line that does not fit has no address, so nothing is placed for it
+            if size > 0 && address + size > 0x10000 {
+                diagnostics.push(Diagnostic::error(
+                    format!(
+                        "Out of memory: this line ends at address 0x{:X}, past 0xFFFF",
+                        address + size - 1
+                    ),
+                    span,
+                ));
+                self.line_addresses.push(None);
+            } else {
+                self.line_addresses.push(u16::try_from(address).ok());
+            }
+            self.line_sizes.push(size);
+        }
+        self.symbol_table.clear();
+        self.label_sections.clear();
+        for (name, location, section) in labels {
+            let Ok(address) = u16::try_from(absolute(location)) else {
+                diagnostics.push(Diagnostic::error(
+                    format!(
+                        "Out of memory: {} is at address 0x{:X}, past 0xFFFF",
+                        name,
+                        absolute(location)
+                    ),
+                    definitions[&name].clone(),
+                ));
+                continue;
+            };
+            self.symbol_table.insert(name.clone(), address);
+            self.label_sections.insert(name, section);
+        }
+        self.text_end = text_end;
+        for (name, expression) in deferred {
+            let lookup = |symbol: &str| lookup_symbol(&constants, &self.symbol_table, symbol);
+            match expression.evaluate(&lookup) {
+                Ok(value) => {
+                    constants.insert(name, value);
+                }
+                Err(diagnostic) => diagnostics.push(diagnostic),
+            }
+        }
+        self.constants = constants;
 
-            for (position, token) in token_by_lines.iter().enumerate() {
-                token_position = position;
+        for (name, span) in self.externs.iter() {
+            if self.symbol_table.contains_key(name) || self.constants.contains_key(name) {
+                diagnostics.push(Diagnostic::error(
+                    format!("{} is declared .extern but is defined in this file", name),
+                    span.clone(),
+                ));
+            }
+        }
+        for (name, span) in self.globals.iter() {
+            if self.symbol_table.contains_key(name) {
+                continue;
+            }
+            let diagnostic = match self.constants.contains_key(name) {
+                true => Diagnostic::error(
+                    format!("{} is a constant; only labels can be .global", name),
+                    span.clone(),
+                ),
+                false => Diagnostic::error(
+                    format!("{} is declared .global but is not defined", name),
+                    span.clone(),
+                ),
+            };
+            diagnostics.push(diagnostic);
+        }
+        diagnostics
+    }
 
-                match token {
-                    Token::Label(_) => {
-                        if position != 0 {
-                            return Err(ParserError {
-                                message: "Label must be at the beginning of the line".to_string(),
-                                line_number: line_number + 1,
-                                column_number: position + 1,
-                            });
-                        }
-                        label_count += 1;
-                        self.label_line_numbers.push(line_number);
+    // Points the diagnostics raised on an expanded line since `before` back at the macro calls.
+    fn note_expansion(&self, line_index: usize, diagnostics: &mut Vec<Diagnostic>, before: usize) {
+        if diagnostics.len() > before {
+            diagnostics.extend(macros::expansion_notes(&self.macro_calls[line_index]));
+        }
+    }
+
+    // The value of a constant, or the address of a label. In an object, an .extern is at
+    // address 0 until the linker relocates the fields that use it.
+    fn symbol_value(&self, name: &str) -> Option<Value> {
+        lookup_symbol(&self.constants, &self.symbol_table, name).or_else(|| {
+            (self.relocatable && self.externs.contains_key(name)).then(|| Value::address(0))
+        })
+    }
+
+    // Evaluates an expression over constants and labels. An undefined name gets the closest
+    // defined one as help.
+    fn evaluate(&self, expression: &Expression) -> Result<Value, Diagnostic> {
+        expression
+            .evaluate(&|name| self.symbol_value(name))
+            .map_err(|diagnostic| {
+                let undefined = expression.symbols().into_iter().find(|(name, span)| {
+                    *span == diagnostic.span && self.symbol_value(name).is_none()
+                });
+                let help = undefined.and_then(|(name, _)| {
+                    if self.externs.contains_key(name) {
+                        return Some(format!(
+                            "{} is .extern: assemble this file as an object and link it",
+                            name
+                        ));
                     }
-                    Token::Opcode(opcode) => {
-                        let mut reg_a = 0;
-                        let mut reg_b = 0;
-                        let mut reg_c = 0;
-                        let mut imm = 0;
-
-                        if opcodes_with_three_register_pipelined.contains(&opcode.as_str()) {
-                            if let Some(Token::Register(reg)) =
-                                token_by_lines.get(token_position + 1)
-                            {
-                                reg_a = *reg;
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected register".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 2,
-                                });
-                            }
+                    suggest::register_help(name).or_else(|| {
+                        let symbols = self.symbol_table.keys().chain(self.constants.keys());
+                        suggest::did_you_mean(name, symbols.map(String::as_str))
+                    })
+                });
+                match help {
+                    Some(help) => diagnostic.with_help(help),
+                    None => diagnostic,
+                }
+            })
+    }
 
-                            if let Some(Token::Comma) = token_by_lines.get(token_position + 2) {
-                                // Expected comma, continue
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected comma".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 3,
-                                });
-                            }
+    // The diagnostic for a statement that starts with a name that is not an instruction,
+    // pseudo-instruction, macro or directive.
+    fn unknown_mnemonic(&self, name: &str, span: Range<usize>) -> Diagnostic {
+        if let Some(directive) = name.strip_prefix('.') {
+            let diagnostic = Diagnostic::error(format!("Unknown directive: {}", name), span);
+            let names = Directive::ALL
+                .iter()
+                .map(|directive| &directive.name()[1..]);
+            return match suggest::closest(directive, names) {
+                Some(closest) => diagnostic.with_help(format!("did you mean .{}?", closest)),
+                None => diagnostic,
+            };
+        }
 
-                            if let Some(Token::Register(reg)) =
-                                token_by_lines.get(token_position + 3)
-                            {
-                                reg_b = *reg;
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected register".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 4,
-                                });
-                            }
+        let diagnostic = Diagnostic::error(format!("Unknown instruction: {}", name), span);
+        let other = match self.processor {
+            Processor::Pipelined => Processor::SingleCycle,
+            Processor::SingleCycle => Processor::Pipelined,
+        };
+        if let Some(descriptor) = isa::lookup(other, name) {
+            return diagnostic.with_help(format!(
+                "{} is an instruction of the {:?} processor, not the {:?} one",
+                descriptor.opcode, other, self.processor
+            ));
+        }
+        let mnemonics = isa::instruction_set(self.processor)
+            .iter()
+            .map(|descriptor| descriptor.mnemonic)
+            .chain(PseudoOp::ALL.iter().map(|op| op.mnemonic()))
+            .chain(self.macros.keys().map(String::as_str));
+        match suggest::did_you_mean(name, mnemonics) {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        }
+    }
 
-                            if let Some(Token::Comma) = token_by_lines.get(token_position + 4) {
-                                // Expected comma, continue
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected comma".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 5,
-                                });
-                            }
+    // Turns an immediate expression into the value stored in the instruction.
+    // A branch or jump target that is an address, such as LOOP or TABLE+2, becomes the signed
+    // offset from `address`, counted in instructions from the branch itself, as PC + IMM * 2
+    // on the hardware. A plain number is the offset itself. Any other field takes the value of
+    // the expression, so LLI R1, TABLE loads the address of TABLE.
+    // In an object, a field that depends on where the linker puts the sections is 0, and comes
+    // with its relocation.
+    fn resolve_immediate(
+        &self,
+        descriptor: &InstructionDescriptor,
+        expression: &Expression,
+        address: usize,
+    ) -> Result<(i32, Option<Relocation>), Diagnostic> {
+        let kind = RelocationKind::Field(descriptor.immediate);
+        if let Some(relocation) = self.relocation(expression, kind, address as u16)? {
+            return Ok((0, Some(relocation)));
+        }
+        self.immediate_value(descriptor, expression, address)
+            .map(|value| (value, None))
+    }
 
-                            if let Some(Token::Register(reg)) =
-                                token_by_lines.get(token_position + 5)
-                            {
-                                reg_c = *reg;
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected register".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 6,
-                                });
-                            }
-                            if reg_b == reg_c {
-                                return Err(ParserError {
-                                    message: "Register B and Register C must be different"
-                                        .to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 4,
-                                });
-                            }
+    fn immediate_value(
+        &self,
+        descriptor: &InstructionDescriptor,
+        expression: &Expression,
+        address: usize,
+    ) -> Result<i32, Diagnostic> {
+        let span = expression.span.clone();
+        let value = self.evaluate(expression)?;
+        match (descriptor.immediate, value.labels) {
+            (_, 0) => self.check_immediate(descriptor, value.value, span),
+            (Immediate::Offset(bits), 1) => {
+                let offset = value.value - address as i64;
+                let range = descriptor.immediate.range();
+                if !(*range.start() as i64..=*range.end() as i64).contains(&offset) {
+                    return Err(Diagnostic::error(
+                        format!(
+                            "Target {} is out of range: offset {} does not fit in IMM{} ({} to {})",
+                            expression,
+                            offset,
+                            bits,
+                            range.start(),
+                            range.end()
+                        ),
+                        span,
+                    ));
+                }
+                Ok(offset as i32)
+            }
+            (_, 1) => self.check_immediate(descriptor, value.value, span),
+            (_, labels) if labels < 0 => Err(Diagnostic::error(
+                format!(
+                    "{} is not a number or an address: it subtracts a label address from a number",
+                    expression
+                ),
+                span,
+            )),
+            _ => Err(Diagnostic::error(
+                format!(
+                    "{} is not a number or an address: it adds label addresses together",
+                    expression
+                ),
+                span,
+            )),
+        }
+    }
 
-                            let instruction = Instruction::new(
-                                opcode.clone(),
-                                reg_a,
-                                Some(reg_b),
-                                Some(reg_c),
-                                imm,
-                                line_number + 1,
-                                position + 1,
-                                Processor::Pipelined,
-                            );
-                            instructions_to_add.push((instruction, label_count));
-                        } else if opcodes_with_two_register_pipelined.contains(&opcode.as_str()) {
-                            if let Some(Token::Register(reg)) =
-                                token_by_lines.get(token_position + 1)
-                            {
-                                reg_a = *reg;
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected register".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 2,
-                                });
-                            }
+    // In an object, the relocation of the word at `address` that holds `expression`, or None
+    // if its value does not depend on where the linker puts the sections. A relocatable value
+    // is one label or .extern plus or minus a constant; the distance between two labels of the
+    // same section is a plain number.
+    fn relocation(
+        &self,
+        expression: &Expression,
+        kind: RelocationKind,
+        address: u16,
+    ) -> Result<Option<Relocation>, Diagnostic> {
+        if !self.relocatable {
+            return Ok(None);
+        }
+        let symbols: Vec<&str> = expression
+            .symbols()
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| {
+                self.symbol_table.contains_key(*name) || self.externs.contains_key(*name)
+            })
+            .collect();
+        if symbols.is_empty() {
+            return Ok(None);
+        }
+        let value = self.evaluate(expression)?;
+        let base = |section: Section| match section {
+            Section::Text => 0,
+            Section::Data => self.text_end as i64,
+        };
+        let section = match u32::from(address) < self.text_end {
+            true => Section::Text,
+            false => Section::Data,
+        };
 
-                            if let Some(Token::Comma) = token_by_lines.get(token_position + 2) {
-                                // Expected comma, continue
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected comma".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 3,
-                                });
-                            }
+        if value.labels == 1 && symbols.len() == 1 {
+            let target = self.label_sections.get(symbols[0]).copied(); // None for an .extern
 
-                            if let Some(Token::Register(reg)) =
-                                token_by_lines.get(token_position + 3)
-                            {
-                                reg_b = *reg;
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected register".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 4,
-                                });
-                            }
-                            if let Some(Token::Comma) = token_by_lines.get(token_position + 4) {
-                                // Expected comma, continue
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected comma".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 3,
-                                });
-                            }
-                            if let Some(Token::Number(num)) = token_by_lines.get(token_position + 5)
-                            {
-                                imm = *num;
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected immediate here".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 5,
-                                });
-                            }
+            // a branch within its own section keeps its offset
+            if matches!(kind, RelocationKind::Field(Immediate::Offset(_)))
+                && target == Some(section)
+            {
+                return Ok(None);
+            }
+            if kind != RelocationKind::Field(Immediate::RegisterMask) {
+                let (symbol, addend) = match target {
+                    Some(target) => (target.name(), value.value - base(target)),
+                    None => (symbols[0], value.value),
+                };
+                return Ok(Some(Relocation {
+                    section,
+                    offset: (address as i64 - base(section)) as u16,
+                    kind,
+                    symbol: symbol.to_string(),
+                    addend,
+                }));
+            }
+        }
 
-                            let instruction = Instruction::new(
-                                opcode.clone(),
-                                reg_a,
-                                Some(reg_b),
-                                None,
-                                imm,
-                                line_number + 1,
-                                position + 1,
-                                Processor::Pipelined,
-                            );
-                            instructions_to_add.push((instruction, label_count));
-                        } else if opcodes_with_single_register_pipelined.contains(&opcode.as_str())
-                        {
-                            if let Some(Token::Register(reg)) =
-                                token_by_lines.get(token_position + 1)
-                            {
-                                reg_a = *reg;
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected register".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 2,
-                                });
-                            }
+        // anything else must come out the same wherever the linker puts the sections and
+        // whatever the .extern labels turn out to be, as END-START does
+        let moved = |moved: &dyn Fn(&str) -> bool| {
+            expression.evaluate(&|name| {
+                self.symbol_value(name).map(|symbol| match moved(name) {
+                    true => Value {
+                        value: symbol.value + 0x1235,
+                        ..symbol
+                    },
+                    false => symbol,
+                })
+            })
+        };
+        let in_section =
+            |section: Section| move |name: &str| self.label_sections.get(name) == Some(&section);
+        let fixed = [Section::Text, Section::Data]
+            .into_iter()
+            .all(|section| moved(&in_section(section)).ok() == Some(value))
+            && self
+                .externs
+                .keys()
+                .all(|external| moved(&|name| name == external).ok() == Some(value));
+        match fixed {
+            true => Ok(None),
+            false => Err(Diagnostic::error(
+                format!("{} cannot be relocated by the linker", expression),
+                expression.span.clone(),
+            )
+            .with_help("an object can only hold one label or .extern plus or minus a constant")),
+        }
+    }
 
-                            if let Some(Token::Comma) = token_by_lines.get(token_position + 2) {
-                                // Expected comma, continue
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected comma".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 3,
-                                });
-                            }
+    // Checks a literal immediate against the width and signedness of its field.
+    fn check_immediate(
+        &self,
+        descriptor: &InstructionDescriptor,
+        value: i64,
+        span: Range<usize>,
+    ) -> Result<i32, Diagnostic> {
+        let immediate = descriptor.immediate;
+        let range = immediate.range();
+        if (*range.start() as i64..=*range.end() as i64).contains(&value) {
+            return Ok(value as i32);
+        }
 
-                            if let Some(Token::Number(num)) = token_by_lines.get(token_position + 3)
-                            {
-                                imm = *num;
-                            } else {
-                                return Err(ParserError {
-                                    message: "Expected immediate".to_string(),
-                                    line_number: line_number + 1,
-                                    column_number: position + 3,
-                                });
-                            }
+        let bits = immediate.bits();
+        let field = match immediate {
+            Immediate::Signed(_) if descriptor.class == InstructionClass::Alu => {
+                "a signed constant"
+            }
+            Immediate::Signed(_) => "a signed displacement",
+            Immediate::Offset(_) => "a signed PC-relative offset",
+            Immediate::Unsigned(_) => "an unsigned constant",
+            _ => "a register mask",
+        };
+        let mut diagnostic = Diagnostic::error(
+            format!(
+                "Immediate {} is out of range for {}: IMM{} is {} ({} to {})",
+                value,
+                descriptor.mnemonic,
+                bits,
+                field,
+                range.start(),
+                range.end()
+            ),
+            span,
+        );
+
+        // Suggest the value with the same bit pattern if the user wrote the other signedness.
+        let modulus = 1i64 << bits;
+        if immediate.is_signed() && value > *range.end() as i64 && value < modulus {
+            diagnostic = diagnostic.with_help(format!(
+                "{} sign-extends its immediate; write {} for the bit pattern {:0width$b}",
+                descriptor.mnemonic,
+                value - modulus,
+                value,
+                width = bits as usize
+            ));
+        } else if !immediate.is_signed() && value < 0 && value >= -modulus {
+            diagnostic = diagnostic.with_help(format!(
+                "{} zero-extends its immediate; write {} for the bit pattern {:0width$b}",
+                descriptor.mnemonic,
+                value + modulus,
+                value + modulus,
+                width = bits as usize
+            ));
+        }
+
+        Err(diagnostic)
+    }
+
+    // Parses the operands following the opcode at `position` according to its descriptor.
+    fn parse_operands(
+        &self,
+        descriptor: &InstructionDescriptor,
+        tokens: &[SpannedToken],
+        position: usize,
+        address: usize,
+        warnings: &mut Vec<Diagnostic>,
+    ) -> Result<Instruction, Diagnostic> {
+        // A missing operand is reported at the end of the line.
+        let span_at = |offset: usize| match tokens.get(position + offset) {
+            Some(token) => token.span.range(),
+            None => {
+                let end = tokens.last().map_or(0, |token| token.span.end);
+                end..end
+            }
+        };
+        let token_at = |offset: usize| tokens.get(position + offset).map(|token| &token.token);
+        // the expected form of the operands is the help for anything out of shape
+        let expects = format!("{} expects {}", descriptor.opcode, descriptor.syntax());
+        let error = |message: &str, offset: usize| {
+            Diagnostic::error(message, span_at(offset)).with_help(expects.clone())
+        };
+        let register = |offset: usize| match token_at(offset) {
+            Some(Token::Register(reg)) => Ok(*reg),
+            token => Err(expected_register(token, error("Expected register", offset))),
+        };
+        let comma = |offset: usize| match token_at(offset) {
+            Some(Token::Comma) => Ok(()),
+            _ => Err(error("Expected comma", offset)),
+        };
+        // the immediate is the last operand; returns it and its relocation with the number of
+        // tokens it took
+        let immediate = |offset: usize| match token_at(offset) {
+            Some(
+                Token::Number(_)
+                | Token::Identifier(_)
+                | Token::LeftParen
+                | Token::Operator(Operator::Minus | Operator::Plus | Operator::Not),
+            ) => {
+                let (expression, length) = Expression::parse(&tokens[position + offset..])?;
+                let (value, relocation) =
+                    self.resolve_immediate(descriptor, &expression, address)?;
+                Ok((value, relocation, length))
+            }
+            _ => Err(error("Expected immediate", offset)),
+        };
+
+        let invalid = tokens[position..]
+            .iter()
+            .find_map(|token| match &token.token {
+                Token::Error(message) => Some((message, token.span.range())),
+                _ => None,
+            });
+        if let Some((message, span)) = invalid {
+            return Err(Diagnostic::error(message.clone(), span));
+        }
+
+        let reg_a = register(1)?;
+        comma(2)?;
+        let mut relocation = None;
+        let (reg_b, reg_c, imm, end) = match descriptor.operands {
+            Operands::RaRbRc => {
+                let reg_b = register(3)?;
+                comma(4)?;
+                let reg_c = register(5)?;
+                // adding a register to itself doubles it, which is how LI shifts
+                if reg_b == reg_c && !matches!(descriptor.opcode, Opcode::Ada | Opcode::Add) {
+                    return Err(error("Register B and Register C must be different", 5));
+                }
+                (Some(reg_b), Some(reg_c), 0, 6)
+            }
+            Operands::RaRbImm => {
+                let reg_b = register(3)?;
+                comma(4)?;
+                let (imm, field, length) = immediate(5)?;
+                relocation = field;
+                (Some(reg_b), None, imm, 5 + length)
+            }
+            Operands::RaRb => (Some(register(3)?), None, 0, 4),
+            Operands::RaImm
+                if descriptor.immediate == Immediate::RegisterMask
+                    && token_at(3) == Some(&Token::LeftBrace) =>
+            {
+                // {R0, R2-R4, R7}: registers and ranges separated by commas; bit 7 of the
+                // mask is R0
+                if token_at(4) == Some(&Token::RightBrace) {
+                    return Err(Diagnostic::error(
+                        "Register list is empty",
+                        span_at(3).start..span_at(4).end,
+                    )
+                    .with_help(format!("{} needs at least one register", descriptor.opcode)));
+                }
+                let mut mask = 0;
+                let mut offset = 4;
+                loop {
+                    let first = register(offset)?;
+                    let mut last = first;
+                    let start = span_at(offset).start;
+                    if token_at(offset + 1) == Some(&Token::Operator(Operator::Minus)) {
+                        last = register(offset + 2)?;
+                        offset += 2;
+                        if last < first {
+                            return Err(Diagnostic::error(
+                                format!("Register range R{}-R{} runs backwards", first, last),
+                                start..span_at(offset).end,
+                            )
+                            .with_help(format!("write R{}-R{}", last, first)));
+                        }
+                    }
+                    if (first..=last).contains(&reg_a) {
+                        warnings.push(Diagnostic::warning(
+                            format!("Base register R{} is also in the register list", reg_a),
+                            start..span_at(offset).end,
+                        ));
+                    }
+                    for reg in first..=last {
+                        mask |= 1 << (7 - reg);
+                    }
+                    offset += 1;
+                    match token_at(offset) {
+                        Some(Token::Comma) => offset += 1,
+                        Some(Token::RightBrace) => break,
+                        _ => return Err(error("Expected ',' or '}' in register list", offset)),
+                    }
+                }
+                (None, None, mask, offset + 1)
+            }
+            Operands::RaImm => {
+                let (imm, field, length) = immediate(3)?;
+                relocation = field;
+                (None, None, imm, 3 + length)
+            }
+        };
+
+        match token_at(end) {
+            None | Some(Token::Comment(_)) | Some(Token::NewLine) | Some(Token::EOF) => {}
+            Some(token) => {
+                return Err(error(
+                    &format!("Unexpected token after operands: {:?}", token),
+                    end,
+                ))
+            }
+        }
+
+        let span = tokens[position].span;
+        let mut instruction = Instruction::new(
+            descriptor.opcode,
+            reg_a,
+            reg_b,
+            reg_c,
+            imm,
+            span.line,
+            span.column,
+            self.processor,
+        );
+        instruction.span = span.start..tokens[position + end - 1].span.end;
+        instruction.address = address as u16;
+        instruction.relocation = relocation;
+        Ok(instruction)
+    }
+
+    // Second pass of a pseudo-instruction: the real instructions it stands for, at the
+    // addresses the first pass gave its line.
+    fn parse_pseudo(
+        &self,
+        op: PseudoOp,
+        tokens: &[SpannedToken],
+        position: usize,
+        line_index: usize,
+        address: u16,
+    ) -> Result<Vec<Instruction>, Diagnostic> {
+        let (reg_a, reg_b, value, end) = pseudo_operands(op, tokens, position)?;
+        let span = tokens[position].span;
+        let span = span.start..tokens[position + end - 1].span.end;
+
+        let mut relocation = None;
+        let imm = match (op, value) {
+            (PseudoOp::B | PseudoOp::Call, Some(target)) => {
+                let opcode = match op {
+                    PseudoOp::B => Opcode::Beq,
+                    _ => Opcode::Jal,
+                };
+                let descriptor = isa::descriptor(self.processor, opcode).ok_or_else(|| {
+                    Diagnostic::error(format!("Invalid opcode: {}", opcode), span.clone())
+                })?;
+                let (imm, field) = self.resolve_immediate(descriptor, &target, address as usize)?;
+                relocation = field;
+                imm
+            }
+            (PseudoOp::Li, Some(value)) => {
+                // LI splits its value across instructions, which no relocation can patch
+                if self
+                    .relocation(&value, RelocationKind::Word, address)?
+                    .is_some()
+                {
+                    return Err(Diagnostic::error(
+                        format!("LI cannot load {} in an object", value),
+                        value.span.clone(),
+                    )
+                    .with_help("put the address in a .word and load it with LW"));
+                }
+                self.data_word(&value)? as i32
+            }
+            _ => 0,
+        };
+        let length = self.line_sizes[line_index] as usize;
+        let mut instructions = pseudo::lower(op, self.processor, reg_a, reg_b, imm, length);
+        for (index, instruction) in instructions.iter_mut().enumerate() {
+            instruction.line_number = tokens[position].span.line;
+            instruction.column_number = tokens[position].span.column;
+            instruction.span = span.clone();
+            instruction.address = address.wrapping_add(index as u16);
+        }
+        if let Some(first) = instructions.first_mut() {
+            first.relocation = relocation;
+        }
+        Ok(instructions)
+    }
+
+    // Second pass of .word, .fill and .space: the words they emit. The operands were checked
+    // and the size of the line fixed by the first pass.
+    fn parse_data(
+        &self,
+        directive: Directive,
+        tokens: &[SpannedToken],
+        position: usize,
+        line_index: usize,
+        address: u16,
+    ) -> Result<Option<Data>, Diagnostic> {
+        let size = self.line_sizes[line_index] as usize;
+        let operands = match directive_operands(directive, tokens, position) {
+            Ok(operands) if size > 0 => operands,
+            _ => return Ok(None),
+        };
+        // the value of each word, and the relocation of the words the linker fills in
+        let values: Vec<&Expression> = match directive {
+            Directive::Word => operands.iter().collect(),
+            Directive::Fill => vec![&operands[1]; size],
+            Directive::Space => Vec::new(),
+            _ => return Ok(None),
+        };
+        let mut words = vec![0; size];
+        let mut relocations = Vec::new();
+        for (index, value) in values.into_iter().enumerate() {
+            let word_address = address.wrapping_add(index as u16);
+            match self.relocation(value, RelocationKind::Word, word_address)? {
+                Some(relocation) => relocations.push(relocation),
+                None => words[index] = self.data_word(value)?,
+            }
+        }
+
+        Ok(Some(Data {
+            address,
+            words,
+            relocations,
+            line_number: tokens[position].span.line,
+            span: tokens[position].span.start..tokens[tokens.len() - 1].span.end,
+        }))
+    }
+
+    // A data word: the value of an expression over numbers, constants and label addresses,
+    // as a signed or unsigned 16-bit value.
+    fn data_word(&self, expression: &Expression) -> Result<u16, Diagnostic> {
+        let value = self.evaluate(expression)?.value;
+        if !(-0x8000..=0xFFFF).contains(&value) {
+            return Err(Diagnostic::error(
+                format!(
+                    "Value {} does not fit in a 16-bit word (-32768 to 65535)",
+                    value
+                ),
+                expression.span.clone(),
+            ));
+        }
+        Ok(value as u16)
+    }
+
+    // Parses the whole program. A line with an error is skipped and parsing carries on with
+    // the next one, so every problem in the file is reported at once. `instructions` holds the
+    // lines that parsed; the program is only usable if none of the diagnostics is an error.
+    pub fn parse(&mut self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.expansion_diagnostics.clone();
+        diagnostics.extend(self.build_symbol_table());
+
+        let mut instructions_to_add = Vec::new();
+        let mut data_to_add = Vec::new();
 
-                            let instruction = Instruction::new(
-                                opcode.clone(),
-                                reg_a,
-                                None,
-                                None,
-                                imm,
-                                line_number + 1,
-                                position + 1,
-                                Processor::Pipelined,
-                            );
-                            instructions_to_add.push((instruction, label_count));
-                        } else {
-                            return Err(ParserError {
-                                message: format!("Invalid opcode: {}", opcode),
-                                line_number: line_number + 1,
-                                column_number: position + 1,
-                            });
+        for (line_index, token_by_lines) in self.token_stream.tokens_by_line.iter().enumerate() {
+            let diagnostics_before = diagnostics.len();
+            // a line past the end of memory was reported by the first pass, and places nothing
+            let address = self.line_addresses[line_index];
+            for (position, token) in token_by_lines.iter().enumerate() {
+                match &token.token {
+                    Token::Label(_) => {
+                        if position != 0 {
+                            diagnostics.push(Diagnostic::error(
+                                "Label must be at the beginning of the line",
+                                token.span.range(),
+                            ));
+                            break;
                         }
+                        self.label_line_numbers.push(line_index);
+                    }
+                    Token::Opcode(opcode) => {
+                        let Some(address) = address else { break };
+                        match isa::descriptor(self.processor, *opcode) {
+                            Some(descriptor) => match self.parse_operands(
+                                descriptor,
+                                token_by_lines,
+                                position,
+                                address as usize,
+                                &mut diagnostics,
+                            ) {
+                                Ok(instruction) => instructions_to_add.push(instruction),
+                                Err(diagnostic) => diagnostics.push(diagnostic),
+                            },
+                            None => diagnostics.push(Diagnostic::error(
+                                format!("Invalid opcode: {}", opcode),
+                                token.span.range(),
+                            )),
+                        }
+                        break;
+                    }
+                    Token::Directive(directive) => {
+                        let Some(address) = address else { break };
+                        match self.parse_data(
+                            *directive,
+                            token_by_lines,
+                            position,
+                            line_index,
+                            address,
+                        ) {
+                            Ok(Some(data)) => data_to_add.push(data),
+                            Ok(None) => {}
+                            Err(diagnostic) => diagnostics.push(diagnostic),
+                        }
+                        break;
                     }
                     Token::EOF => break,
+                    Token::Identifier(identifier) => {
+                        match (PseudoOp::from_mnemonic(identifier), address) {
+                            (Some(_), None) => {}
+                            (Some(op), Some(address)) => {
+                                match self.parse_pseudo(
+                                    op,
+                                    token_by_lines,
+                                    position,
+                                    line_index,
+                                    address,
+                                ) {
+                                    Ok(instructions) => instructions_to_add.extend(instructions),
+                                    Err(diagnostic) => diagnostics.push(diagnostic),
+                                }
+                            }
+                            (None, _) => diagnostics
+                                .push(self.unknown_mnemonic(identifier, token.span.range())),
+                        }
+                        break;
+                    }
                     Token::Error(error) => {
-                        return Err(ParserError {
-                            message: format!("Invalid Token: {}", error),
-                            line_number: line_number + 1,
-                            column_number: position + 1,
-                        })
+                        diagnostics.push(Diagnostic::error(error.clone(), token.span.range()));
+                        break;
+                    }
+                    Token::Comment(_) | Token::NewLine => continue,
+                    _ => {
+                        diagnostics.push(Diagnostic::error(
+                            "Expected an opcode or a label at the start of the line",
+                            token.span.range(),
+                        ));
+                        break;
                     }
-                    _ => continue,
                 }
             }
+            self.note_expansion(line_index, &mut diagnostics, diagnostics_before);
         }
 
         // Add instructions after processing all tokens
-        for (instruction, label_count) in instructions_to_add {
+        for instruction in instructions_to_add {
             self.add_instruction(instruction);
         }
+        self.data = data_to_add;
+
+        diagnostics
+    }
+}
+
+// Labels and constants share one namespace; `kind` is what the second definition is.
+fn duplicate(
+    kind: &str,
+    name: &str,
+    span: Range<usize>,
+    first_definition: &Range<usize>,
+) -> [Diagnostic; 2] {
+    [
+        Diagnostic::error(format!("Duplicate {}: {}", kind, name), span),
+        Diagnostic::note(
+            format!("{} was first defined here", name),
+            first_definition.clone(),
+        ),
+    ]
+}
+
+// A label or constant with the name of a define.
+fn predefined(kind: &str, name: &str, span: Range<usize>) -> Diagnostic {
+    Diagnostic::error(format!("Duplicate {}: {}", kind, name), span)
+        .with_help(format!("{} is already defined with -D", name))
+}
+
+// Splits the operands of a directive at its commas and checks how many there are. The name
+// of an .equ is returned as a symbol.
+fn directive_operands(
+    directive: Directive,
+    tokens: &[SpannedToken],
+    position: usize,
+) -> Result<Vec<Expression>, Diagnostic> {
+    let mut operands = Vec::new();
+    let mut offset = position + 1;
+    let end_of_line = |token: Option<&SpannedToken>| {
+        matches!(
+            token.map(|token| &token.token),
+            None | Some(Token::Comment(_)) | Some(Token::NewLine) | Some(Token::EOF)
+        )
+    };
+
+    if !end_of_line(tokens.get(offset)) {
+        loop {
+            let token = match tokens.get(offset) {
+                Some(token) if !end_of_line(Some(token)) => token,
+                _ => {
+                    let end = tokens[offset - 1].span.end;
+                    return Err(Diagnostic::error("Expected a value", end..end));
+                }
+            };
+            if directive == Directive::Equ && operands.is_empty() {
+                match &token.token {
+                    Token::Identifier(name) => operands.push(Expression {
+                        kind: ExpressionKind::Symbol(name.clone()),
+                        span: token.span.range(),
+                    }),
+                    _ => {
+                        return Err(Diagnostic::error(
+                            "Expected a constant name",
+                            token.span.range(),
+                        ))
+                    }
+                }
+                offset += 1;
+            } else {
+                let (expression, length) = Expression::parse(&tokens[offset..])?;
+                operands.push(expression);
+                offset += length;
+            }
+            match tokens.get(offset) {
+                Some(SpannedToken {
+                    token: Token::Comma,
+                    ..
+                }) => offset += 1,
+                token if end_of_line(token) => break,
+                Some(token) => return Err(Diagnostic::error("Expected comma", token.span.range())),
+                None => break,
+            }
+        }
+    }
+
+    let count_ok = match directive.arity() {
+        Some(arity) => operands.len() == arity,
+        None => !operands.is_empty(),
+    };
+    if !count_ok {
+        let directive_span = tokens[position].span;
+        let end = tokens[offset.min(tokens.len()) - 1].span.end;
+        return Err(Diagnostic::error(
+            format!(
+                "{} expects {}, found {} operand(s)",
+                directive,
+                directive.syntax(),
+                operands.len()
+            ),
+            directive_span.start..end,
+        ));
+    }
+    Ok(operands)
+}
+
+// The operands of a pseudo-instruction at `position`: RA, RB, the value or target, and the
+// number of tokens from the mnemonic to the last operand. Missing registers are 0.
+fn pseudo_operands(
+    op: PseudoOp,
+    tokens: &[SpannedToken],
+    position: usize,
+) -> Result<(i32, i32, Option<Expression>, usize), Diagnostic> {
+    let span_at = |offset: usize| match tokens.get(position + offset) {
+        Some(token) => token.span.range(),
+        None => {
+            let end = tokens.last().map_or(0, |token| token.span.end);
+            end..end
+        }
+    };
+    let token_at = |offset: usize| tokens.get(position + offset).map(|token| &token.token);
+    let expects = format!("{} expects {}", op, op.syntax());
+    let error = |message: &str, offset: usize| {
+        Diagnostic::error(message, span_at(offset)).with_help(expects.clone())
+    };
+    let register = |offset: usize| match token_at(offset) {
+        Some(Token::Register(reg)) => Ok(*reg),
+        token => Err(expected_register(token, error("Expected register", offset))),
+    };
+    let comma = |offset: usize| match token_at(offset) {
+        Some(Token::Comma) => Ok(()),
+        _ => Err(error("Expected comma", offset)),
+    };
+    let value = |offset: usize| match token_at(offset) {
+        Some(
+            Token::Number(_)
+            | Token::Identifier(_)
+            | Token::LeftParen
+            | Token::Operator(Operator::Minus | Operator::Plus | Operator::Not),
+        ) => Expression::parse(&tokens[position + offset..]),
+        _ => Err(error("Expected immediate", offset)),
+    };
+
+    let invalid = tokens[position..]
+        .iter()
+        .find_map(|token| match &token.token {
+            Token::Error(message) => Some(Diagnostic::error(message.clone(), token.span.range())),
+            _ => None,
+        });
+    if let Some(diagnostic) = invalid {
+        return Err(diagnostic);
+    }
+
+    let (reg_a, reg_b, expression, end) = match op.operands() {
+        PseudoOperands::None => (0, 0, None, 1),
+        PseudoOperands::Ra => (register(1)?, 0, None, 2),
+        PseudoOperands::RaRb => {
+            let reg_a = register(1)?;
+            comma(2)?;
+            (reg_a, register(3)?, None, 4)
+        }
+        PseudoOperands::RaValue => {
+            let reg_a = register(1)?;
+            comma(2)?;
+            let (expression, length) = value(3)?;
+            (reg_a, 0, Some(expression), 3 + length)
+        }
+        PseudoOperands::Target => {
+            let (expression, length) = value(1)?;
+            (0, 0, Some(expression), 1 + length)
+        }
+    };
+
+    match token_at(end) {
+        None | Some(Token::Comment(_)) | Some(Token::NewLine) | Some(Token::EOF) => {}
+        Some(token) => {
+            return Err(error(
+                &format!("Unexpected token after operands: {:?}", token),
+                end,
+            ))
+        }
+    }
+    Ok((reg_a, reg_b, expression, end))
+}
+
+// A name in place of a register, such as R8, gets help about the registers instead.
+fn expected_register(token: Option<&Token>, diagnostic: Diagnostic) -> Diagnostic {
+    match token {
+        Some(Token::Identifier(name)) => match suggest::register_help(name) {
+            Some(help) => diagnostic.with_help(help),
+            None => diagnostic,
+        },
+        _ => diagnostic,
+    }
+}
+
+// A value the first pass needs: an expression over numbers and constants defined on earlier
+// lines.
+fn constant(
+    constants: &BTreeMap<String, Value>,
+    expression: &Expression,
+) -> Result<i64, Diagnostic> {
+    let undefined = expression
+        .symbols()
+        .into_iter()
+        .find(|(name, _)| !constants.contains_key(*name));
+    if let Some((name, span)) = undefined {
+        return Err(Diagnostic::error(
+            format!("{} must be a constant defined before it is used", name),
+            span,
+        ));
+    }
+    Ok(constant_value(constants, expression)?.value)
+}
+
+fn constant_value(
+    constants: &BTreeMap<String, Value>,
+    expression: &Expression,
+) -> Result<Value, Diagnostic> {
+    expression.evaluate(&|name| constants.get(name).copied())
+}
 
-        Ok(self.clone())
+fn lookup_symbol(
+    constants: &BTreeMap<String, Value>,
+    symbol_table: &BTreeMap<String, u16>,
+    name: &str,
+) -> Option<Value> {
+    match constants.get(name) {
+        Some(value) => Some(*value),
+        None => symbol_table
+            .get(name)
+            .map(|address| Value::address(*address)),
     }
 }
@@ -0,0 +1,230 @@
+// Pseudo-instructions: mnemonics outside the ISA that the parser lowers to real instructions.
+//
+//   NOP             BEQ R0, R0, 1          falls through without touching registers or flags
+//   MOV RA, RB      ADI RB, RA, 0          ADI writes its second register: RA = RB
+//   LI RA, VALUE    LLI RA, ... (see below)
+//   B TARGET        BEQ R0, R0, TARGET
+//   CALL TARGET     JAL R7, TARGET         R7 holds the return address
+//   RET             JRI R7, 0              JLR R7, R7 on the single cycle processor
+//   CLR RA          LLI RA, 0
+//
+// LI loads any 16-bit value. Values that fit LLI take one instruction; otherwise the top bits
+// are loaded with LLI and the rest are shifted in by doubling (ADA/ADD RA, RA, RA) and adding
+// (ADI) five bits at a time. The length of LI has to be known when the program is laid out, so
+// a value that depends on a label always takes the full-length sequence.
+
+use crate::isa::Opcode;
+use crate::lexer::Processor;
+use crate::parser::Instruction;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PseudoOp {
+    Nop,
+    Mov,
+    Li,
+    B,
+    Call,
+    Ret,
+    Clr,
+}
+
+// The operands written after a pseudo mnemonic.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PseudoOperands {
+    None,
+    Ra,
+    RaRb,
+    RaValue,
+    Target,
+}
+
+impl PseudoOp {
+    pub const ALL: [PseudoOp; 7] = [
+        PseudoOp::Nop,
+        PseudoOp::Mov,
+        PseudoOp::Li,
+        PseudoOp::B,
+        PseudoOp::Call,
+        PseudoOp::Ret,
+        PseudoOp::Clr,
+    ];
+
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            PseudoOp::Nop => "NOP",
+            PseudoOp::Mov => "MOV",
+            PseudoOp::Li => "LI",
+            PseudoOp::B => "B",
+            PseudoOp::Call => "CALL",
+            PseudoOp::Ret => "RET",
+            PseudoOp::Clr => "CLR",
+        }
+    }
+
+    pub const fn operands(self) -> PseudoOperands {
+        match self {
+            PseudoOp::Nop | PseudoOp::Ret => PseudoOperands::None,
+            PseudoOp::Clr => PseudoOperands::Ra,
+            PseudoOp::Mov => PseudoOperands::RaRb,
+            PseudoOp::Li => PseudoOperands::RaValue,
+            PseudoOp::B | PseudoOp::Call => PseudoOperands::Target,
+        }
+    }
+
+    // The operands as written, for help text: MOV expects RA, RB.
+    pub const fn syntax(self) -> &'static str {
+        match self.operands() {
+            PseudoOperands::None => "no operands",
+            PseudoOperands::Ra => "RA",
+            PseudoOperands::RaRb => "RA, RB",
+            PseudoOperands::RaValue => "RA, VALUE",
+            PseudoOperands::Target => "TARGET",
+        }
+    }
+
+    // Case-insensitive lookup of a pseudo mnemonic.
+    pub fn from_mnemonic(mnemonic: &str) -> Option<PseudoOp> {
+        PseudoOp::ALL
+            .iter()
+            .find(|op| op.mnemonic().eq_ignore_ascii_case(mnemonic))
+            .copied()
+    }
+
+    // The number of instructions it lowers to. For LI, `value` is None when it is not known
+    // yet, which gives the full-length sequence.
+    pub fn length(self, processor: Processor, value: Option<u16>) -> usize {
+        match (self, value) {
+            (PseudoOp::Li, Some(value)) => li_sequence(processor, 0, value, false).len(),
+            (PseudoOp::Li, None) => 1 + (16 - lli_bits(processor)) as usize + 2,
+            _ => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for PseudoOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.mnemonic())
+    }
+}
+
+// The real instructions for `op`. `imm` is the branch offset for B and CALL and the value for
+// LI; `length` is the number of instructions the first pass laid out for it.
+pub fn lower(
+    op: PseudoOp,
+    processor: Processor,
+    reg_a: i32,
+    reg_b: i32,
+    imm: i32,
+    length: usize,
+) -> Vec<Instruction> {
+    let instruction = |opcode, reg_a, reg_b, reg_c, imm| {
+        let mut instruction = Instruction::new(opcode, reg_a, reg_b, reg_c, imm, 0, 0, processor);
+        instruction.pseudo = Some(op);
+        instruction
+    };
+
+    match op {
+        PseudoOp::Nop => vec![instruction(Opcode::Beq, 0, Some(0), None, 1)],
+        PseudoOp::Mov => vec![instruction(Opcode::Adi, reg_b, Some(reg_a), None, 0)],
+        PseudoOp::Li => load_immediate(processor, reg_a, imm as u16, length)
+            .into_iter()
+            .map(|mut lowered| {
+                lowered.pseudo = Some(op);
+                lowered
+            })
+            .collect(),
+        PseudoOp::B => vec![instruction(Opcode::Beq, 0, Some(0), None, imm)],
+        PseudoOp::Call => vec![instruction(Opcode::Jal, 7, None, None, imm)],
+        PseudoOp::Ret => match processor {
+            Processor::Pipelined => vec![instruction(Opcode::Jri, 7, None, None, 0)],
+            Processor::SingleCycle => vec![instruction(Opcode::Jlr, 7, Some(7), None, 0)],
+        },
+        PseudoOp::Clr => vec![instruction(Opcode::Lli, reg_a, None, None, 0)],
+    }
+}
+
+// The pseudo-instruction a single real instruction reads as, if any. Used to annotate
+// disassembly; LI sequences are not recognised.
+pub fn recognise(instruction: &Instruction) -> Option<String> {
+    let reg_b = instruction.reg_b.unwrap_or(0);
+    match (
+        instruction.opcode,
+        instruction.reg_a,
+        reg_b,
+        instruction.imm,
+    ) {
+        (Opcode::Beq, 0, 0, 1) => Some("NOP".to_string()),
+        (Opcode::Beq, 0, 0, imm) => Some(format!("B {}", imm)),
+        (Opcode::Adi, reg_a, reg_b, 0) => Some(format!("MOV R{}, R{}", reg_b, reg_a)),
+        (Opcode::Lli, reg_a, _, 0) => Some(format!("CLR R{}", reg_a)),
+        (Opcode::Jal, 7, _, imm) => Some(format!("CALL {}", imm)),
+        (Opcode::Jri, 7, _, 0) if instruction.processor == Processor::Pipelined => {
+            Some("RET".to_string())
+        }
+        (Opcode::Jlr, 7, 7, _) if instruction.processor == Processor::SingleCycle => {
+            Some("RET".to_string())
+        }
+        _ => None,
+    }
+}
+
+// Width of the LLI immediate.
+fn lli_bits(processor: Processor) -> u32 {
+    match processor {
+        Processor::Pipelined => 9,
+        Processor::SingleCycle => 8,
+    }
+}
+
+// LI as `length` instructions: the shortest sequence for `value` when the first pass knew the
+// value and laid it out, and otherwise the full-length sequence, which any value fits.
+fn load_immediate(processor: Processor, reg: i32, value: u16, length: usize) -> Vec<Instruction> {
+    let shortest = li_sequence(processor, reg, value, false);
+    match shortest.len() == length {
+        true => shortest,
+        false => li_sequence(processor, reg, value, true),
+    }
+}
+
+// The instructions that load `value`: LLI alone, LLI and ADI for small negative numbers, or LLI
+// of the top bits and the rest shifted in by doubling the register with ADA (ADD on the single
+// cycle processor). `full_length` always takes the last form and keeps every ADI.
+fn li_sequence(processor: Processor, reg: i32, value: u16, full_length: bool) -> Vec<Instruction> {
+    let instruction = |opcode, reg_b, reg_c, imm| {
+        Instruction::new(opcode, reg, reg_b, reg_c, imm, 0, 0, processor)
+    };
+    let lli_bits = lli_bits(processor);
+
+    if !full_length {
+        if (value as u32) < 1 << lli_bits {
+            return vec![instruction(Opcode::Lli, None, None, value as i32)];
+        }
+        // small negative numbers: 0 - n
+        if (-32..0).contains(&(value as i16)) {
+            return vec![
+                instruction(Opcode::Lli, None, None, 0),
+                instruction(Opcode::Adi, Some(reg), None, value as i16 as i32),
+            ];
+        }
+    }
+
+    let double = match processor {
+        Processor::Pipelined => Opcode::Ada,
+        Processor::SingleCycle => Opcode::Add,
+    };
+    let rest = 16 - lli_bits;
+    let mut sequence = vec![instruction(Opcode::Lli, None, None, (value >> rest) as i32)];
+    // shift in the remaining bits, at most five at a time so every chunk fits ADI
+    let mut remaining = rest;
+    for chunk in [rest - 5, 5] {
+        remaining -= chunk;
+        for _ in 0..chunk {
+            sequence.push(instruction(double, Some(reg), Some(reg), 0));
+        }
+        let bits = ((value >> remaining) & ((1 << chunk) - 1)) as i32;
+        if bits != 0 || full_length {
+            sequence.push(instruction(Opcode::Adi, Some(reg), None, bits));
+        }
+    }
+    sequence
+}
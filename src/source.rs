@@ -0,0 +1,374 @@
+// Source files of a program, and the .include directives that bring them together.
+//
+// .include "lib/stack.asm"
+//
+// - The file is searched for next to the file that includes it, then in each include path,
+//   in order. The top-level buffer of `assemble` is taken to live in the current directory.
+// - A file that includes itself, directly or through other files, is an error.
+// - Conditional blocks are decided as the lines are read, see `conditional`, so an .include
+//   in a block that is not assembled is ignored.
+// - Every file gets an id and a base offset. The spans of its tokens are offset by the base,
+//   so byte ranges stay unique across the program and the later passes can keep using plain
+//   ranges. `localize` turns such a range back into a file id and an offset into that file.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::conditional::Conditions;
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Directive, Lexer, Processor, SpannedToken, Token, TokenStream};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub name: String,          // as shown in diagnostics
+    pub path: Option<PathBuf>, // None for a buffer that did not come from disk
+    pub text: String,
+    pub base: usize, // offset of the first byte in program-wide spans
+}
+
+impl SourceFile {
+    // The text of a 1-based line, without its line break.
+    pub fn line(&self, number: usize) -> Option<&str> {
+        let line = self.text.split('\n').nth(number.checked_sub(1)?)?;
+        Some(line.trim_end_matches('\r'))
+    }
+}
+
+// A 1-based line and column (in characters) in file `file`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    pub files: Vec<SourceFile>, // indexed by file id; the top-level file is 0
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    // Adds a buffer and returns its file id.
+    pub fn add(&mut self, name: impl Into<String>, text: impl Into<String>) -> usize {
+        self.push(name.into(), None, text.into())
+    }
+
+    // Reads a file from disk and returns its file id.
+    pub fn load(&mut self, path: &Path) -> io::Result<usize> {
+        let text = fs::read_to_string(path)?;
+        Ok(self.push(path.display().to_string(), Some(path.to_path_buf()), text))
+    }
+
+    fn push(&mut self, name: String, path: Option<PathBuf>, text: String) -> usize {
+        // one byte of room after each file for the span of its end
+        let base = self
+            .files
+            .last()
+            .map_or(0, |file| file.base + file.text.len() + 1);
+        self.files.push(SourceFile {
+            name,
+            path,
+            text,
+            base,
+        });
+        self.files.len() - 1
+    }
+
+    pub fn file(&self, id: usize) -> Option<&SourceFile> {
+        self.files.get(id)
+    }
+
+    // The id of the file a program-wide offset falls in.
+    pub fn file_at(&self, offset: usize) -> usize {
+        self.files
+            .partition_point(|file| file.base <= offset)
+            .saturating_sub(1)
+    }
+
+    // The position of a program-wide offset.
+    pub fn position(&self, offset: usize) -> Position {
+        let id = self.file_at(offset);
+        let Some(file) = self.files.get(id) else {
+            return Position {
+                file: id,
+                line: 1,
+                column: 1,
+            };
+        };
+        let before = &file.text[..offset.saturating_sub(file.base).min(file.text.len())];
+        let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+        let line = before.matches('\n').count() + 1;
+        Position {
+            file: id,
+            line,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    // Points a diagnostic with a program-wide span at its file.
+    pub fn localize(&self, diagnostic: &mut Diagnostic) {
+        let id = self.file_at(diagnostic.span.start);
+        if let Some(file) = self.files.get(id) {
+            diagnostic.file = id;
+            diagnostic.span =
+                diagnostic.span.start - file.base..diagnostic.span.end.saturating_sub(file.base);
+        }
+    }
+
+    // Renders a localized diagnostic against the file it points at.
+    pub fn render(&self, diagnostic: &Diagnostic) -> String {
+        match self.files.get(diagnostic.file) {
+            Some(file) => diagnostic.render(&file.name, &file.text),
+            None => diagnostic.render("<unknown>", ""),
+        }
+    }
+
+    // The tokens of a file, by line. Each line ends with a NewLine, the last one with EOF.
+    pub fn lex(&self, id: usize, processor: Processor) -> Vec<Vec<SpannedToken>> {
+        let file = &self.files[id];
+        let mut lexer = Lexer::with_file(&file.text, id, file.base);
+        let mut token_stream = TokenStream::new();
+        loop {
+            let token = lexer.next_token(processor);
+            token_stream.add(token.clone());
+            if token.token == Token::EOF {
+                break;
+            }
+        }
+        token_stream.tokens_by_line
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Inclusion {
+    pub lines: Vec<Vec<SpannedToken>>,
+    pub diagnostics: Vec<Diagnostic>, // for the .include and conditional lines
+}
+
+// The lines of file `root` with every .include replaced by the lines of the file it names,
+// and the lines of conditional blocks that are not assembled taken out.
+pub fn include(
+    sources: &mut SourceMap,
+    root: usize,
+    processor: Processor,
+    include_paths: &[PathBuf],
+    defines: &BTreeMap<String, i64>,
+) -> Inclusion {
+    let mut stack = Vec::new();
+    if let Some(path) = sources.files.get(root).and_then(|file| file.path.as_ref()) {
+        stack.push(fs::canonicalize(path).unwrap_or_else(|_| path.clone()));
+    }
+    let mut includer = Includer {
+        sources,
+        processor,
+        include_paths,
+        stack,
+        conditions: Conditions::new(defines),
+        inclusion: Inclusion::default(),
+    };
+    includer.include_file(root, true);
+    let mut inclusion = includer.inclusion;
+    inclusion.diagnostics.extend(includer.conditions.finish());
+    inclusion
+}
+
+// The state of one walk through the included files.
+struct Includer<'a> {
+    sources: &'a mut SourceMap,
+    processor: Processor,
+    include_paths: &'a [PathBuf],
+    stack: Vec<PathBuf>, // canonical paths of the files being included, outermost first
+    conditions: Conditions,
+    inclusion: Inclusion,
+}
+
+impl Includer<'_> {
+    fn include_file(&mut self, id: usize, top_level: bool) {
+        let mut file_lines = self.sources.lex(id, self.processor);
+        if !top_level {
+            // only the end of the top-level file ends the program
+            for token in file_lines.iter_mut().flatten() {
+                if token.token == Token::EOF {
+                    token.token = Token::NewLine;
+                }
+            }
+        }
+
+        for line in file_lines {
+            if !self.conditions.line(&line) {
+                // keep the end of the file
+                if let Some(eof) = line.last().filter(|token| token.token == Token::EOF) {
+                    self.inclusion.lines.push(vec![eof.clone()]);
+                }
+                continue;
+            }
+
+            let position = match line.first().map(|token| &token.token) {
+                Some(Token::Label(_)) => 1,
+                _ => 0,
+            };
+            if line.get(position).map(|token| &token.token)
+                != Some(&Token::Directive(Directive::Include))
+            {
+                self.inclusion.lines.push(line);
+                continue;
+            }
+
+            // the label of the .include marks the first line of the file
+            if position == 1 {
+                let label = line[0].clone();
+                let mut newline = label.clone();
+                newline.token = Token::NewLine;
+                newline.span.start = label.span.end;
+                self.inclusion.lines.push(vec![label, newline]);
+            }
+
+            match resolve(
+                self.sources,
+                id,
+                &line,
+                position,
+                self.include_paths,
+                &self.stack,
+            ) {
+                Ok((included, canonical)) => {
+                    self.stack.push(canonical);
+                    self.include_file(included, false);
+                    self.stack.pop();
+                }
+                Err(diagnostic) => self.inclusion.diagnostics.push(diagnostic),
+            }
+
+            // keep the end of the file, if the .include was on the last line
+            if let Some(eof) = line.last().filter(|token| token.token == Token::EOF) {
+                self.inclusion.lines.push(vec![eof.clone()]);
+            }
+        }
+    }
+}
+
+// Finds and loads the file named by the .include at `position` of `line`, in file `id`.
+// Returns its file id and canonical path.
+fn resolve(
+    sources: &mut SourceMap,
+    id: usize,
+    line: &[SpannedToken],
+    position: usize,
+    include_paths: &[PathBuf],
+    stack: &[PathBuf],
+) -> Result<(usize, PathBuf), Diagnostic> {
+    let end_of_line = |token: &SpannedToken| {
+        matches!(token.token, Token::Comment(_) | Token::NewLine | Token::EOF)
+    };
+    let directive = line[position].span;
+    let (name, span) = match (line.get(position + 1), line.get(position + 2)) {
+        (
+            Some(SpannedToken {
+                token: Token::String(name),
+                span,
+            }),
+            next,
+        ) if next.is_none_or(end_of_line) => (name.clone(), span.range()),
+        (
+            Some(SpannedToken {
+                token: Token::Error(message),
+                span,
+            }),
+            _,
+        ) => return Err(Diagnostic::error(message.clone(), span.range())),
+        _ => {
+            let end = line
+                .iter()
+                .rev()
+                .find(|token| !end_of_line(token))
+                .map_or(directive.end, |token| token.span.end);
+            return Err(Diagnostic::error(
+                format!(
+                    "{} expects {}",
+                    Directive::Include,
+                    Directive::Include.syntax()
+                ),
+                directive.start..end,
+            ));
+        }
+    };
+
+    let directory = match sources.files[id]
+        .path
+        .as_ref()
+        .and_then(|path| path.parent())
+    {
+        Some(directory) => directory.to_path_buf(),
+        None => PathBuf::from("."),
+    };
+    let found = search(&name, &directory, include_paths).map_err(|searched| {
+        let diagnostic = Diagnostic::error(format!("Cannot find {}", name), span.clone());
+        if searched.is_empty() {
+            return diagnostic;
+        }
+        let searched: Vec<String> = searched
+            .iter()
+            .map(|directory| directory.display().to_string())
+            .collect();
+        diagnostic.with_help(format!("searched in {}", searched.join(", ")))
+    })?;
+
+    let canonical = fs::canonicalize(&found).unwrap_or_else(|_| found.clone());
+    if let Some(first) = stack.iter().position(|path| *path == canonical) {
+        let cycle: Vec<String> = stack[first..]
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|path| file_name(path))
+            .collect();
+        return Err(Diagnostic::error(
+            format!("Include cycle: {}", cycle.join(" -> ")),
+            span,
+        ));
+    }
+
+    let included = sources.load(&found).map_err(|error| {
+        Diagnostic::error(
+            format!("Cannot read {}: {}", found.display(), error),
+            span.clone(),
+        )
+    })?;
+    Ok((included, canonical))
+}
+
+// The first existing file called `name`, next to the including file or in an include path.
+// Fails with the directories that were searched.
+fn search(
+    name: &str,
+    directory: &Path,
+    include_paths: &[PathBuf],
+) -> Result<PathBuf, Vec<PathBuf>> {
+    if Path::new(name).is_absolute() {
+        let path = PathBuf::from(name);
+        return if path.is_file() {
+            Ok(path)
+        } else {
+            Err(Vec::new())
+        };
+    }
+    let directories: Vec<PathBuf> = std::iter::once(directory.to_path_buf())
+        .chain(include_paths.iter().cloned())
+        .collect();
+    directories
+        .iter()
+        .map(|directory| directory.join(name))
+        .find(|path| path.is_file())
+        .ok_or(directories)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
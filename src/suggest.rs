@@ -0,0 +1,59 @@
+// Suggestions for misspelled names, for the help line of a diagnostic.
+//
+// ADDI R1, R2, 3      help: did you mean ADI?
+// LW R8, R1, 0        help: registers are R0 to R7
+
+// The number of single-character insertions, deletions, substitutions and swaps of two
+// neighbouring characters that turn `a` into `b`, ignoring case.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().map(|ch| ch.to_ascii_uppercase()).collect();
+    let b: Vec<char> = b.chars().map(|ch| ch.to_ascii_uppercase()).collect();
+    // distances[i][j] is the distance between a[..i] and b[..j]
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+// The candidate closest to `name`, if it is close enough to be a typo: one edit for short
+// names, and one more for every three characters after that.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = 1 + name.chars().count().saturating_sub(3) / 3;
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| (1..=limit).contains(distance))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+// "did you mean X?", for the closest candidate.
+pub fn did_you_mean<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    closest(name, candidates).map(|candidate| format!("did you mean {}?", candidate))
+}
+
+// Help for a name that looks like a register but is not one, such as R8.
+pub fn register_help(name: &str) -> Option<String> {
+    let number = name.strip_prefix(['R', 'r'])?;
+    if number.is_empty() || !number.chars().all(|ch| ch.is_ascii_digit()) {
+        return None;
+    }
+    Some("registers are R0 to R7".to_string())
+}
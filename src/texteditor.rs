@@ -3,7 +3,9 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use crate::crates::custom_themes;
-use crate::lexer::Lexer;
+use crate::crates::debuginfo::DebugInfo;
+use crate::format::format;
+use crate::lexer::{Lexer, Processor};
 use crate::welcome::welcome_screen;
 
 use iced::widget::horizontal_space;
@@ -11,7 +13,7 @@ use iced::widget::{button, column, container, row, text, text_editor};
 
 use iced::Settings;
 use iced::{executor, Application, Command, Element, Length, Theme};
-use iced::{window, Font, Size};
+use iced::{window, Size};
 
 use iced_core::text::LineHeight;
 
@@ -30,7 +32,9 @@ pub fn tesh_editor() {
         ..Settings::default()
     };
 
-    Editor::run(settings);
+    if let Err(error) = Editor::run(settings) {
+        println!("[ERROR] {}", error);
+    }
 }
 
 struct Editor {
@@ -40,6 +44,8 @@ struct Editor {
     error: Option<Error>,
     state: State,
     line_nume: usize,
+    debug_info: Option<DebugInfo>, // from the .dbg next to the file, if there is one
+    processor: Processor,          // that the file is written for, e.g. by Format
 }
 
 #[derive(Debug, Clone)]
@@ -49,6 +55,8 @@ pub enum Message {
     OpenFile,
     CloseFile,
     NewFile,
+    Format,
+    SwitchProcessor,
 }
 
 pub enum State {
@@ -70,11 +78,13 @@ impl Application for Editor {
             Self {
                 /*path: Some(default_path()),*/
                 path: None,
-                lexer: lexer,
+                lexer,
                 content: text_editor::Content::with_text(lexer_input.as_str()),
                 error: None,
                 state: State::Welcome,
                 line_nume: 1,
+                debug_info: None,
+                processor: Processor::Pipelined,
             },
             //Command::perform(load_file(self.path), Message::FileOpened),
             Command::none(),
@@ -97,6 +107,9 @@ impl Application for Editor {
                 self.lexer = Lexer::new(result.as_str());
                 let lexer_input = self.lexer.input.iter().collect::<String>();
                 self.content = text_editor::Content::with_text(lexer_input.as_str());
+                self.debug_info = std::fs::read_to_string(path.with_extension("dbg"))
+                    .ok()
+                    .and_then(|text| DebugInfo::read(&text).ok());
                 self.path = Some(path);
                 Command::none()
             }
@@ -111,12 +124,27 @@ impl Application for Editor {
             }
             Message::CloseFile => {
                 self.path = None;
+                self.debug_info = None;
                 self.content = text_editor::Content::default();
                 self.state = State::Welcome;
                 Command::none()
             }
+            Message::Format => {
+                let formatted = format(&self.content.text(), self.processor);
+                self.content = text_editor::Content::with_text(&formatted);
+                self.line_nume = self.content.line_count() + 1;
+                Command::none()
+            }
+            Message::SwitchProcessor => {
+                self.processor = match self.processor {
+                    Processor::Pipelined => Processor::SingleCycle,
+                    Processor::SingleCycle => Processor::Pipelined,
+                };
+                Command::none()
+            }
             Message::NewFile => {
                 self.path = None;
+                self.debug_info = None;
                 self.content = text_editor::Content::default();
                 self.state = State::Editing;
                 Command::none()
@@ -131,6 +159,8 @@ impl Application for Editor {
         let controls = row![button("Open").on_press(Message::OpenFile)];
         let controls = match self.path {
             Some(_) => controls
+                .push(button("Format").on_press(Message::Format))
+                .push(button(self.processor.name()).on_press(Message::SwitchProcessor))
                 .push(button("Close").on_press(Message::CloseFile))
                 .spacing(10)
                 .padding(10),
@@ -144,7 +174,21 @@ impl Application for Editor {
         };
         let position = {
             let (line, column) = self.content.cursor_position();
-            text(format!("{}: {}", line + 1, column + 1))
+            // the addresses the line was assembled to, when the debug file is loaded
+            let addresses = match (&self.debug_info, &self.path) {
+                (Some(info), Some(path)) => info
+                    .addresses(path, line + 1)
+                    .iter()
+                    .map(|address| format!("0x{:04X}", address))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                _ => String::new(),
+            };
+            text(
+                format!("{}  {}: {}", addresses, line + 1, column + 1)
+                    .trim_start()
+                    .to_string(),
+            )
         };
 
         let status_bar = row![path, horizontal_space(), position];
@@ -203,7 +247,7 @@ async fn load_file(path: PathBuf) -> Result<(PathBuf, Arc<String>), Error> {
 }
 
 #[derive(Debug, Clone)]
-enum Error {
+pub enum Error {
     DialogClosed,
     IO(io::ErrorKind),
 }
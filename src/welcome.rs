@@ -2,11 +2,10 @@
 
 use iced::alignment::Horizontal;
 use iced::widget::{Button, Text};
-use iced::{theme, Length, Theme};
-use std::ops::Range;
+use iced::{theme, Length};
 
 use crate::texteditor::Message;
-use iced::widget::{button, column, container, row, text};
+use iced::widget::{column, container, row, text};
 use iced::Element;
 
 pub fn welcome_screen() -> Element<'static, Message> {
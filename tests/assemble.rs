@@ -0,0 +1,73 @@
+// The assembler API: whole programs in, machine words or diagnostics out, never a panic.
+
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions};
+use iitb_cpu::diagnostic::Diagnostic;
+use iitb_cpu::lexer::Processor;
+
+mod common;
+use common::assemble_pipelined;
+
+#[test]
+fn program_carries_words_lines_and_symbols() {
+    let program = assemble_pipelined(
+        "; count to ten\n\
+         START: LLI R1, 10\n\
+         \n\
+         LOOP:  ADI R1, R1, -1\n\
+                BEQ R1, R0, START\n",
+    )
+    .unwrap();
+
+    assert_eq!(program.words, vec![0x320A, 0x027F, 0x823E]);
+    assert_eq!(
+        program.line_numbers.into_iter().collect::<Vec<_>>(),
+        vec![(0, 2), (1, 4), (2, 5)]
+    );
+    assert_eq!(program.symbol_table["START"], 0);
+    assert_eq!(program.symbol_table["LOOP"], 1);
+    assert!(program.warnings.is_empty());
+}
+
+#[test]
+fn malformed_sources_return_diagnostics() {
+    let sources = [
+        "",
+        ":",
+        "ADD",
+        "LW R1",
+        "LW R1,",
+        "LW R1, R2, 99999999999",
+        "R1, R2, R3",
+        "LM R1, 0x1FF",
+        "BEQ R1, R2, NOWHERE",
+        "ADA R1 R2 R3 R4",
+        "JLR R9, R1",
+        "é: ADI R1, R1, ü",
+        "\u{0}\t\r\n,,,",
+        "0x",
+        "#",
+        "-",
+    ];
+    for source in sources {
+        // only the empty program is valid; the point is that none of these panic
+        let result = assemble_pipelined(source);
+        if !source.is_empty() {
+            let diagnostics = result.expect_err(source);
+            assert!(diagnostics.iter().any(Diagnostic::is_error), "{:?}", source);
+            for diagnostic in diagnostics.iter() {
+                diagnostic.render("test.asm", source);
+            }
+        }
+    }
+}
+
+#[test]
+fn instructions_of_the_other_processor_are_rejected() {
+    let options = AssembleOptions {
+        processor: Processor::SingleCycle,
+        ..AssembleOptions::default()
+    };
+    let diagnostics = assemble("ADA R1, R2, R3\n", &options).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].span, 0..3);
+}
@@ -0,0 +1,17 @@
+// Helpers shared by the integration tests. Each test file uses only some of them.
+#![allow(dead_code)]
+
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions, Program};
+use iitb_cpu::diagnostic::Diagnostic;
+
+pub fn assemble_pipelined(source: &str) -> Result<Program, Vec<Diagnostic>> {
+    assemble(source, &AssembleOptions::default())
+}
+
+pub fn words(source: &str) -> Vec<u16> {
+    assemble_pipelined(source).unwrap().words
+}
+
+pub fn first_error(source: &str) -> Diagnostic {
+    assemble_pipelined(source).unwrap_err().remove(0)
+}
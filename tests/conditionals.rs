@@ -0,0 +1,171 @@
+// .if/.ifdef/.ifndef/.else/.endif and defines given from outside the program.
+
+use std::collections::BTreeMap;
+
+use iitb_cpu::conditional::parse_define;
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions, Program};
+use iitb_cpu::diagnostic::Diagnostic;
+
+const VARIANTS: &str = "\
+.ifdef FORWARDING
+       ADA R3, R1, R2
+       ADA R4, R3, R1
+.else
+       ADA R3, R1, R2
+       NOP
+       NOP
+       ADA R4, R3, R1
+.endif
+";
+
+fn assemble_with(source: &str, defines: &[(&str, i64)]) -> Result<Program, Vec<Diagnostic>> {
+    let options = AssembleOptions {
+        defines: defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect::<BTreeMap<_, _>>(),
+        ..AssembleOptions::default()
+    };
+    assemble(source, &options)
+}
+
+fn words(source: &str, defines: &[(&str, i64)]) -> Vec<u16> {
+    assemble_with(source, defines).unwrap().words
+}
+
+fn first_error(source: &str) -> Diagnostic {
+    assemble_with(source, &[]).unwrap_err().remove(0)
+}
+
+#[test]
+fn defines_pick_a_variant() {
+    assert_eq!(
+        words(VARIANTS, &[("FORWARDING", 1)]),
+        words("ADA R3, R1, R2\nADA R4, R3, R1\n", &[])
+    );
+    assert_eq!(
+        words(VARIANTS, &[]),
+        words("ADA R3, R1, R2\nNOP\nNOP\nADA R4, R3, R1\n", &[])
+    );
+}
+
+#[test]
+fn if_evaluates_constant_expressions() {
+    let source = "\
+.equ STAGES, 5
+.if STAGES - 5
+       LLI R1, 1
+.else
+       LLI R1, 2
+.endif
+.if (LEVEL & 2) | 0
+       LLI R2, 3
+.endif
+";
+    assert_eq!(
+        words(source, &[("LEVEL", 2)]),
+        words("LLI R1, 2\nLLI R2, 3\n", &[])
+    );
+    assert_eq!(words(source, &[("LEVEL", 1)]), words("LLI R1, 2\n", &[]));
+}
+
+#[test]
+fn defines_are_constants_in_the_program() {
+    let program = assemble_with("LLI R1, DEPTH * 2\n.space DEPTH\n", &[("DEPTH", 3)]).unwrap();
+    assert_eq!(program.words, vec![0x3206, 0, 0, 0]);
+}
+
+#[test]
+fn blocks_nest_and_skipped_blocks_are_not_evaluated() {
+    let source = "\
+.if 0
+  .if UNDEFINED
+       LLI R1, 1
+  .else
+       LLI R1, 2
+  .endif
+.else
+  .ifndef OTHER
+       LLI R1, 3
+  .endif
+.endif
+";
+    assert_eq!(words(source, &[]), words("LLI R1, 3\n", &[]));
+}
+
+#[test]
+fn defaults_can_be_overridden() {
+    let source = "\
+.ifndef COUNT
+.equ COUNT, 2
+.endif
+.fill COUNT, 7
+";
+    assert_eq!(words(source, &[]), vec![7, 7]);
+    assert_eq!(words(source, &[("COUNT", 3)]), vec![7, 7, 7]);
+}
+
+#[test]
+fn skipped_lines_are_not_checked() {
+    let source = ".if 0\nMACRO_THAT_DOES_NOT_EXIST R9\n.macro M\nNOP\n.endm\n.endif\nM: NOP\n";
+    assert_eq!(words(source, &[]), words("NOP\n", &[]));
+}
+
+#[test]
+fn unbalanced_blocks_are_errors() {
+    assert_eq!(first_error(".endif\n").message, ".endif without an .if");
+    assert_eq!(first_error("NOP\n.else\n").message, ".else without an .if");
+    let unclosed = first_error(".if 1\nNOP\n");
+    assert_eq!(unclosed.message, "This conditional block has no .endif");
+    assert_eq!(unclosed.span, 0..3);
+
+    let diagnostics = assemble_with(".if 1\n.else\n.else\n.endif\n", &[]).unwrap_err();
+    assert_eq!(diagnostics[0].message, "Duplicate .else");
+    assert_eq!(diagnostics[1].message, "the first .else is here");
+    assert_eq!(diagnostics[1].span, 6..11);
+}
+
+#[test]
+fn conditions_must_be_constants() {
+    assert_eq!(
+        first_error(".if LOOP\n.endif\nLOOP: NOP\n").message,
+        "LOOP must be a constant defined before it is used"
+    );
+    assert_eq!(
+        first_error(".if\n.endif\n").message,
+        ".if expects CONDITION"
+    );
+    assert_eq!(
+        first_error(".ifdef 3\n.endif\n").message,
+        ".ifdef expects NAME"
+    );
+    assert_eq!(
+        first_error("X: .if 1\n.endif\n").message,
+        ".if cannot have a label"
+    );
+}
+
+#[test]
+fn names_of_defines_cannot_be_reused() {
+    let diagnostics = assemble_with(".equ MODE, 2\nMODE: NOP\n", &[("MODE", 1)]).unwrap_err();
+    assert_eq!(diagnostics[0].message, "Duplicate constant: MODE");
+    assert_eq!(diagnostics[1].message, "Duplicate label: MODE");
+    assert_eq!(
+        diagnostics[0].help.as_deref(),
+        Some("MODE is already defined with -D")
+    );
+}
+
+#[test]
+fn command_line_defines_are_parsed() {
+    assert_eq!(
+        parse_define("FORWARDING=1"),
+        Ok(("FORWARDING".to_string(), 1))
+    );
+    assert_eq!(parse_define("NOPS"), Ok(("NOPS".to_string(), 1)));
+    assert_eq!(parse_define("BASE=0x40"), Ok(("BASE".to_string(), 64)));
+    assert_eq!(parse_define("OFFSET=-3"), Ok(("OFFSET".to_string(), -3)));
+    assert!(parse_define("1ST=2").is_err());
+    assert!(parse_define("=2").is_err());
+    assert!(parse_define("X=abc").is_err());
+}
@@ -0,0 +1,121 @@
+// The debug sidecar: where each word of the image comes from, labels, and the round trip
+// through the .dbg file.
+
+use std::path::Path;
+
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions, Expansion, Program};
+use iitb_cpu::crates::debuginfo::{debug_info, DebugInfo};
+use iitb_cpu::pseudo::PseudoOp;
+use iitb_cpu::source::Position;
+
+const SOURCE: &str = "\
+.macro PUSH reg
+       SW reg, R6, 0
+       ADI R6, R6, -1
+.endm
+START: LI R1, 300
+LOOP:  PUSH R1
+       JAL R0, LOOP
+";
+
+fn debug(source: &str) -> (Program, DebugInfo) {
+    let program = assemble(source, &AssembleOptions::default()).unwrap();
+    let info = debug_info(&program);
+    (program, info)
+}
+
+fn position(line: usize, column: usize) -> Position {
+    Position {
+        file: 0,
+        line,
+        column,
+    }
+}
+
+#[test]
+fn words_point_at_their_statement() {
+    let (program, info) = debug(SOURCE);
+    assert_eq!(info.files, ["<input>"]);
+    assert_eq!(info.origins.len(), program.words.len());
+    let last = program.words.len() as u16 - 1;
+    let jal = info.origin(last).unwrap();
+    assert_eq!(jal.position, position(7, 8));
+    assert_eq!(jal.pseudo, None);
+    assert!(jal.macros.is_empty());
+    assert_eq!(info.labels["START"], 0);
+    assert_eq!(info.labels["LOOP"], info.origins.len() as u16 - 3);
+}
+
+#[test]
+fn pseudo_and_macro_words_record_their_expansion() {
+    let (_, info) = debug(SOURCE);
+    let li = info.origin(0).unwrap();
+    assert_eq!(li.position, position(5, 8));
+    assert_eq!(li.pseudo, Some(PseudoOp::Li));
+    let push = info.labels["LOOP"];
+    for (address, line) in [(push, 2), (push + 1, 3)] {
+        let origin = info.origin(address).unwrap();
+        assert_eq!(origin.position, position(line, 8));
+        assert_eq!(
+            origin.macros,
+            [Expansion {
+                name: "PUSH".to_string(),
+                call: position(6, 8),
+            }]
+        );
+    }
+    assert_eq!(info.describe(0).unwrap(), "<input>:5:8 (LI)");
+    assert_eq!(
+        info.describe(push + 1).unwrap(),
+        "<input>:3:8 (in PUSH at <input>:6:8)"
+    );
+    assert_eq!(info.describe(0x100), None);
+}
+
+#[test]
+fn the_debug_file_round_trips_and_matches_its_image() {
+    let (program, info) = debug(SOURCE);
+    let text = info.write();
+    assert!(text.starts_with(&format!("iitb-debug {} ", program.words.len())));
+    assert!(text.contains("file 0 <input>\n"));
+    assert!(text.contains("addr 0000 0 5 8 pseudo LI\n"));
+    let read = DebugInfo::read(&text).unwrap();
+    assert_eq!(read, info);
+    assert!(read.matches(&program.words));
+
+    let mut changed = program.words.clone();
+    changed[0] ^= 1;
+    assert!(!read.matches(&changed));
+}
+
+#[test]
+fn an_editor_finds_the_addresses_of_a_line() {
+    let (_, mut info) = debug(SOURCE);
+    info.files[0] = "./main.asm".to_string();
+    let push = info.labels["LOOP"];
+    assert_eq!(info.addresses(Path::new("/home/lab/main.asm"), 2), [push]);
+    assert_eq!(info.addresses(Path::new("main.asm"), 3), [push + 1]);
+    assert!(info
+        .addresses(Path::new("/home/lab/other.asm"), 2)
+        .is_empty());
+    assert!(info
+        .addresses(Path::new("/home/lab/main.asm"), 6)
+        .is_empty());
+}
+
+#[test]
+fn malformed_debug_files_are_rejected() {
+    assert_eq!(DebugInfo::read("").unwrap_err(), "the debug file is empty");
+    assert_eq!(
+        DebugInfo::read("iitb-object Pipelined\n").unwrap_err(),
+        "line 1: not an iitb-debug file"
+    );
+    assert_eq!(
+        DebugInfo::read("iitb-debug 1 0000\naddr 0000 0 1 x\n").unwrap_err(),
+        "line 2: invalid number \"x\""
+    );
+    assert_eq!(
+        DebugInfo::read("iitb-debug 1 0000\naddr 0000 0 1 1 pseudo FOO\n").unwrap_err(),
+        "line 2: unknown pseudo-instruction \"FOO\""
+    );
+}
@@ -0,0 +1,163 @@
+// Parsing recovers at the next line and reports every problem in the file.
+
+use iitb_cpu::diagnostic::Severity;
+use iitb_cpu::lexer::{parse_number, Lexer, Processor, Span, Token};
+use iitb_cpu::parser::Parser;
+
+#[test]
+fn every_bad_line_is_reported() {
+    let source = "ADI R1, R2, 1\nLW R1, 5, 5\nADA R1, R2\nA: ADI R1, R1, 1\nA: ADI R1, R1, 1\n";
+    let mut parser = Parser::new(source);
+    let diagnostics = parser.parse();
+
+    let summary: Vec<(Severity, &str)> = diagnostics
+        .iter()
+        .map(|d| (d.severity, d.message.as_str()))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (Severity::Error, "Duplicate label: A"),
+            (Severity::Note, "A was first defined here"),
+            (Severity::Error, "Expected register"),
+            (Severity::Error, "Expected comma"),
+        ]
+    );
+    assert_eq!(parser.instructions.len(), 3);
+}
+
+#[test]
+fn diagnostics_render_with_a_caret_line() {
+    let source = "ADI R1, R2, 1\n  LW R1, 5, 5\n";
+    let mut parser = Parser::new(source);
+    let diagnostics = parser.parse();
+
+    assert_eq!(
+        diagnostics[0].render("test.asm", source),
+        "error: Expected register\n --> test.asm:2:10\n  |\n2 |   LW R1, 5, 5\n  |          ^\n  = help: LW expects RA, RB, IMM6\n"
+    );
+}
+
+#[test]
+fn tokens_carry_byte_spans() {
+    // the comment holds a multi-byte character, so byte and character offsets differ
+    let mut lexer = Lexer::new("; ü\nLOOP: BEQ R1, R2, LOOP // back\n");
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token(Processor::Pipelined);
+        if token.token == Token::EOF {
+            break;
+        }
+        tokens.push((token.token, token.span));
+    }
+
+    assert_eq!(
+        tokens[0].1,
+        Span {
+            start: 0,
+            end: 4,
+            line: 1,
+            column: 1,
+            file: 0
+        }
+    );
+    assert_eq!(tokens[2].0, Token::Label("LOOP:".to_string()));
+    assert_eq!(
+        tokens[2].1,
+        Span {
+            start: 5,
+            end: 10,
+            line: 2,
+            column: 1,
+            file: 0
+        }
+    );
+    assert_eq!(tokens[8].0, Token::Identifier("LOOP".to_string()));
+    assert_eq!(
+        tokens[8].1,
+        Span {
+            start: 23,
+            end: 27,
+            line: 2,
+            column: 19,
+            file: 0
+        }
+    );
+    assert_eq!(tokens[9].0, Token::Comment(" back".to_string()));
+    assert_eq!(tokens[10].0, Token::NewLine);
+}
+
+#[test]
+fn diagnostics_point_at_the_offending_token() {
+    let source = "BEQ R1, R2, NOWHERE\nADA R1, R2, R3 R4\n";
+    let mut parser = Parser::new(source);
+    let diagnostics = parser.parse();
+
+    assert_eq!(&source[diagnostics[0].span.clone()], "NOWHERE");
+    assert_eq!(&source[diagnostics[1].span.clone()], "R4");
+}
+
+#[test]
+fn immediate_literals() {
+    let source = "ADI R1, R2, -5\nADI R1, R2, 0x1F\nADI R1, R2, 0b10_10\nADI R1, R2, 0o17\nADI R1, R2, #1FH\nADI R1, R2, #10\nBEQ R1, R2, -32\n";
+    let mut parser = Parser::new(source);
+    assert!(parser.parse().is_empty());
+
+    let immediates: Vec<i32> = parser.instructions.iter().map(|i| i.imm).collect();
+    assert_eq!(immediates, vec![-5, 31, 10, 15, 31, 10, -32]);
+
+    // an H suffix wins over a 0b/0o prefix
+    assert_eq!(parse_number("#0BEH"), Ok(0xBE));
+    assert_eq!(parse_number("#0B1H"), Ok(0xB1));
+    assert_eq!(
+        parse_number("#0O7H"),
+        Err("Invalid number #0O7H: 'O' is not a hexadecimal digit".to_string())
+    );
+}
+
+#[test]
+fn malformed_literals_are_explained() {
+    let cases = [
+        ("0x", "Invalid number 0x: missing digits"),
+        ("0x1G", "Invalid number 0x1G: 'G' is not a hexadecimal digit"),
+        ("0b102", "Invalid number 0b102: '2' is not a binary digit"),
+        ("1_", "Invalid number 1_: '_' may only separate two digits"),
+        ("#1F", "Invalid number #1F: 'F' is not a decimal digit (hexadecimal after # needs an H suffix, e.g. #1FH)"),
+        ("99999999999", "Invalid number 99999999999: too large"),
+    ];
+    for (literal, message) in cases {
+        let source = format!("ADI R1, R2, {}\n", literal);
+        let mut parser = Parser::new(&source);
+        let diagnostics = parser.parse();
+        assert_eq!(diagnostics[0].message, message);
+        assert_eq!(&source[diagnostics[0].span.clone()], literal);
+    }
+}
+
+#[test]
+fn immediates_are_checked_against_their_field() {
+    let source = "ADI R1, R2, 62\nLW R1, R2, -33\nBEQ R1, R2, 32\nLLI R1, -1\nLLI R1, 511\nJAL R7, -256\nLM R1, 256\n";
+    let mut parser = Parser::new(source);
+    let diagnostics = parser.parse();
+
+    let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "Immediate 62 is out of range for ADI: IMM6 is a signed constant (-32 to 31)",
+            "Immediate -33 is out of range for LW: IMM6 is a signed displacement (-32 to 31)",
+            "Immediate 32 is out of range for BEQ: IMM6 is a signed PC-relative offset (-32 to 31)",
+            "Immediate -1 is out of range for LLI: IMM9 is an unsigned constant (0 to 511)",
+            "Immediate 256 is out of range for LM: IMM8 is a register mask (0 to 255)",
+        ]
+    );
+    assert_eq!(
+        diagnostics[0].help.as_deref(),
+        Some("ADI sign-extends its immediate; write -2 for the bit pattern 111110")
+    );
+    assert_eq!(
+        diagnostics[3].help.as_deref(),
+        Some("LLI zero-extends its immediate; write 511 for the bit pattern 111111111")
+    );
+    assert_eq!(&source[diagnostics[1].span.clone()], "-33");
+}
@@ -0,0 +1,141 @@
+// Directives: .org, .word, .fill, .space, .equ and the .text/.data sections.
+
+mod common;
+use common::{assemble_pipelined, first_error};
+
+#[test]
+fn data_follows_the_code() {
+    let program = assemble_pipelined(
+        ".equ COUNT, 3\n\
+         .data\n\
+         TABLE: .word 0x1234, -1, COUNT, TABLE\n\
+         ZEROS: .space 2\n\
+         ONES:  .fill COUNT, 0xFFFF\n\
+         .text\n\
+         START: LLI R1, COUNT\n\
+                ADI R1, R1, -1\n",
+    )
+    .unwrap();
+
+    assert_eq!(program.symbol_table["START"], 0);
+    assert_eq!(program.symbol_table["TABLE"], 2);
+    assert_eq!(program.symbol_table["ZEROS"], 6);
+    assert_eq!(program.symbol_table["ONES"], 8);
+    assert_eq!(
+        program.words,
+        vec![0x3203, 0x027F, 0x1234, 0xFFFF, 0x0003, 0x0002, 0, 0, 0xFFFF, 0xFFFF, 0xFFFF]
+    );
+    assert_eq!(program.line_numbers[&0], 7);
+    assert_eq!(program.line_numbers[&2], 3);
+    assert_eq!(program.line_numbers[&10], 5);
+}
+
+#[test]
+fn org_moves_the_location_counter() {
+    let program = assemble_pipelined(
+        "      JAL R0, MAIN\n\
+         .org 4\n\
+         MAIN: BEQ R0, R0, MAIN\n\
+         .data\n\
+         .org 0x10\n\
+         BUF:  .word 7\n",
+    )
+    .unwrap();
+
+    assert_eq!(program.symbol_table["MAIN"], 4);
+    assert_eq!(program.symbol_table["BUF"], 0x10);
+    assert_eq!(program.words.len(), 0x11);
+    assert_eq!(program.words[0], 0xC004);
+    assert_eq!(&program.words[1..4], &[0, 0, 0]);
+    assert_eq!(program.words[4], 0x8000);
+    assert_eq!(program.words[0x10], 7);
+    assert!(!program.line_numbers.contains_key(&1));
+}
+
+#[test]
+fn constants_can_be_immediates() {
+    let program = assemble_pipelined(
+        ".equ STEP, -2\n\
+         .equ MASK, 0b10000001\n\
+         ADI R1, R1, STEP\n\
+         LM R2, MASK\n",
+    )
+    .unwrap();
+    assert_eq!(program.words, vec![0x027E, 0x6481]);
+
+    assert_eq!(
+        first_error(".equ BIG, 100\nADI R1, R1, BIG\n").message,
+        "Immediate 100 is out of range for ADI: IMM6 is a signed constant (-32 to 31)"
+    );
+}
+
+#[test]
+fn overlapping_regions_are_errors() {
+    let diagnostics = assemble_pipelined(
+        "ADI R1, R1, 1\n\
+         ADI R1, R1, 1\n\
+         .org 1\n\
+         .word 5, 6\n",
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].message,
+        "Address 0x0001 is already used by line 2"
+    );
+}
+
+#[test]
+fn lines_past_the_end_of_memory_are_only_out_of_memory() {
+    let diagnostics = assemble_pipelined(
+        ".org 0xFFFF\n\
+         ADI R1, R1, 1\n\
+         ADI R1, R1, 1\n\
+         END:\n",
+    )
+    .unwrap_err();
+    let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+    assert_eq!(
+        messages,
+        [
+            "Out of memory: this line ends at address 0x10000, past 0xFFFF",
+            "Out of memory: END is at address 0x10001, past 0xFFFF",
+        ]
+    );
+}
+
+#[test]
+fn malformed_directives_are_errors() {
+    for (source, message) in [
+        (".bogus 1", "Unknown directive: .bogus"),
+        (".org", ".org expects ADDRESS, found 0 operand(s)"),
+        (".fill 1", ".fill expects COUNT, VALUE, found 1 operand(s)"),
+        (".word", ".word expects VALUE, ..., found 0 operand(s)"),
+        (".text 5", ".text expects no operands, found 1 operand(s)"),
+        (".word 1,", "Expected a value"),
+        (".word 1 2", "Expected comma"),
+        (".word R1", "Expected a value"),
+        (".equ R1, 5", "Expected a constant name"),
+        (
+            ".space N\n.equ N, 2",
+            "N must be a constant defined before it is used",
+        ),
+        (
+            ".org 0x10000",
+            "Address 65536 is outside memory (0 to 0xFFFF)",
+        ),
+        (
+            ".word 70000",
+            "Value 70000 does not fit in a 16-bit word (-32768 to 65535)",
+        ),
+        (".word NOWHERE", "Undefined label: NOWHERE"),
+        (".equ A, 1\nA: ADI R1, R1, 1", "Duplicate label: A"),
+        ("A: .word 1\n.equ A, 1", "Duplicate constant: A"),
+        (
+            ".org 0xFFFF\n.word 1, 2",
+            "Out of memory: this line ends at address 0x10000, past 0xFFFF",
+        ),
+    ] {
+        assert_eq!(first_error(source).message, message, "{}", source);
+    }
+}
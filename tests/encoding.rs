@@ -0,0 +1,186 @@
+// Golden encodings for every instruction of the pipelined ISA.
+//
+// Each row is (opcode, RA, RB, RC, IMM, expected word). The expected words are written
+// with the fields grouped so they can be checked against the ISA sheet by eye.
+
+#![allow(clippy::unusual_byte_groupings)]
+
+use iitb_cpu::crates::assembler::{assemble, instruction_to_binary, AssembleOptions};
+use iitb_cpu::crates::disassembler::{decode_instruction, disassemble};
+use iitb_cpu::isa::{self, Opcode};
+use iitb_cpu::lexer::Processor;
+use iitb_cpu::parser::Instruction;
+
+type GoldenRow = (&'static str, i32, Option<i32>, Option<i32>, i32, u16);
+
+const GOLDEN: [GoldenRow; 26] = [
+    ("ADA", 1, Some(2), Some(3), 0, 0b0001_001_010_011_0_00),
+    ("ADC", 1, Some(2), Some(3), 0, 0b0001_001_010_011_0_10),
+    ("ADZ", 1, Some(2), Some(3), 0, 0b0001_001_010_011_0_01),
+    ("AWC", 1, Some(2), Some(3), 0, 0b0001_001_010_011_0_11),
+    ("ACA", 1, Some(2), Some(3), 0, 0b0001_001_010_011_1_00),
+    ("ACC", 1, Some(2), Some(3), 0, 0b0001_001_010_011_1_10),
+    ("ACZ", 1, Some(2), Some(3), 0, 0b0001_001_010_011_1_01),
+    ("ACW", 1, Some(2), Some(3), 0, 0b0001_001_010_011_1_11),
+    ("ADI", 4, Some(5), None, 10, 0b0000_100_101_001010),
+    ("NDU", 7, Some(6), Some(5), 0, 0b0010_111_110_101_0_00),
+    ("NDC", 7, Some(6), Some(5), 0, 0b0010_111_110_101_0_10),
+    ("NDZ", 7, Some(6), Some(5), 0, 0b0010_111_110_101_0_01),
+    ("NCU", 7, Some(6), Some(5), 0, 0b0010_111_110_101_1_00),
+    ("NCC", 7, Some(6), Some(5), 0, 0b0010_111_110_101_1_10),
+    ("NCZ", 7, Some(6), Some(5), 0, 0b0010_111_110_101_1_01),
+    ("LLI", 3, None, None, 0x1FF, 0b0011_011_111111111),
+    ("LW", 2, Some(1), None, 15, 0b0100_010_001_001111),
+    ("SW", 2, Some(1), None, 63, 0b0101_010_001_111111),
+    ("LM", 6, None, None, 0b1010_0001, 0b0110_110_0_10100001),
+    ("SM", 6, None, None, 0b0101_1110, 0b0111_110_0_01011110),
+    ("BEQ", 1, Some(2), None, -1, 0b1000_001_010_111111),
+    ("BLT", 1, Some(2), None, 5, 0b1001_001_010_000101),
+    ("BLE", 1, Some(2), None, -32, 0b1010_001_010_100000),
+    ("JAL", 7, None, None, -2, 0b1100_111_111111110),
+    ("JLR", 7, Some(3), None, 0, 0b1101_111_011_000000),
+    ("JRI", 5, None, None, 100, 0b1111_101_001100100),
+];
+
+const GOLDEN_SINGLE_CYCLE: [GoldenRow; 14] = [
+    ("ADD", 1, Some(2), Some(3), 0, 0b0000_001_010_011_000),
+    ("SUB", 1, Some(2), Some(3), 0, 0b0010_001_010_011_000),
+    ("MUL", 1, Some(2), Some(3), 0, 0b0011_001_010_011_000),
+    ("ADI", 4, Some(5), None, -3, 0b0001_100_101_111101),
+    ("AND", 7, Some(6), Some(5), 0, 0b0100_111_110_101_000),
+    ("ORA", 7, Some(6), Some(5), 0, 0b0101_111_110_101_000),
+    ("IMP", 7, Some(6), Some(5), 0, 0b0110_111_110_101_000),
+    ("LHI", 3, None, None, 0xAB, 0b1000_011_0_10101011),
+    ("LLI", 3, None, None, 0xFF, 0b1001_011_0_11111111),
+    ("LW", 2, Some(1), None, 15, 0b1010_010_001_001111),
+    ("SW", 2, Some(1), None, 31, 0b1011_010_001_011111),
+    ("BEQ", 1, Some(2), None, -1, 0b1100_001_010_111111),
+    ("JAL", 7, None, None, 200, 0b1101_111_011001000),
+    ("JLR", 7, Some(3), None, 0, 0b1111_111_011_000000),
+];
+
+#[test]
+fn golden_encodings_cover_every_pipelined_opcode() {
+    for descriptor in isa::PIPELINED.iter() {
+        assert!(
+            GOLDEN.iter().any(|row| row.0 == descriptor.mnemonic),
+            "no golden encoding for {}",
+            descriptor.mnemonic
+        );
+    }
+}
+
+#[test]
+fn golden_encodings_match() {
+    for (opcode, reg_a, reg_b, reg_c, imm, expected) in GOLDEN {
+        let instruction = Instruction::new(
+            Opcode::from_mnemonic(opcode).unwrap(),
+            reg_a,
+            reg_b,
+            reg_c,
+            imm,
+            1,
+            1,
+            Processor::Pipelined,
+        );
+        let word = instruction_to_binary(&instruction).unwrap();
+        assert_eq!(
+            word, expected,
+            "{}: got {:016b}, expected {:016b}",
+            opcode, word, expected
+        );
+    }
+}
+
+#[test]
+fn golden_encodings_decode_back() {
+    for (opcode, reg_a, reg_b, reg_c, _, expected) in GOLDEN {
+        let instruction = decode_instruction(expected, Processor::Pipelined)
+            .unwrap_or_else(|| panic!("{}: {:016b} did not decode", opcode, expected));
+        assert_eq!(instruction.opcode.mnemonic(), opcode);
+        assert_eq!(instruction.reg_a, reg_a);
+        assert_eq!(instruction.reg_b, reg_b);
+        assert_eq!(instruction.reg_c, reg_c);
+        assert_eq!(instruction_to_binary(&instruction), Ok(expected));
+    }
+}
+
+#[test]
+fn undecodable_words_are_printed_as_data() {
+    let image = [
+        0b0000_100_101_111111,
+        0b1011_000_000_000000,
+        0b0010_001_010_011_0_11,
+        0b1101_111_011_000001,
+    ];
+    assert_eq!(
+        disassemble(&image, Processor::Pipelined),
+        "ADI R4, R5, -1\n.word 0xB000\n.word 0x229B\n.word 0xDEC1\n"
+    );
+}
+
+#[test]
+fn single_cycle_golden_encodings_match() {
+    for descriptor in isa::SINGLE_CYCLE.iter() {
+        assert!(
+            GOLDEN_SINGLE_CYCLE
+                .iter()
+                .any(|row| row.0 == descriptor.mnemonic),
+            "no golden encoding for {}",
+            descriptor.mnemonic
+        );
+    }
+
+    for (opcode, reg_a, reg_b, reg_c, imm, expected) in GOLDEN_SINGLE_CYCLE {
+        let instruction = Instruction::new(
+            Opcode::from_mnemonic(opcode).unwrap(),
+            reg_a,
+            reg_b,
+            reg_c,
+            imm,
+            1,
+            1,
+            Processor::SingleCycle,
+        );
+        let word = instruction_to_binary(&instruction).unwrap();
+        assert_eq!(
+            word, expected,
+            "{}: got {:016b}, expected {:016b}",
+            opcode, word, expected
+        );
+    }
+}
+
+#[test]
+fn single_cycle_source_assembles() {
+    let options = AssembleOptions {
+        processor: Processor::SingleCycle,
+        ..AssembleOptions::default()
+    };
+    let program = assemble(
+        "ADD R1, R2, R3\nLHI R4, 171\nLOOP: BEQ R1, R2, LOOP\nJLR R7, R3\n",
+        &options,
+    )
+    .unwrap();
+    assert_eq!(
+        program.words,
+        vec![
+            0b0000_001_010_011_000,
+            0b1000_100_0_10101011,
+            0b1100_001_010_000000,
+            0b1111_111_011_000000,
+        ]
+    );
+}
+
+#[test]
+fn processors_are_named_as_on_the_command_line() {
+    for processor in Processor::ALL {
+        assert_eq!(Processor::from_name(processor.name()), Some(processor));
+    }
+    assert_eq!(
+        Processor::from_name("Single-Cycle"),
+        Some(Processor::SingleCycle)
+    );
+    assert_eq!(Processor::from_name("multicycle"), None);
+}
@@ -0,0 +1,164 @@
+// Constant expressions in immediates and directives.
+
+#![allow(clippy::unusual_byte_groupings)]
+
+use iitb_cpu::expression::{Expression, Value};
+use iitb_cpu::lexer::{Lexer, Processor, Token};
+
+mod common;
+use common::{assemble_pipelined, first_error};
+
+fn parse(source: &str) -> Expression {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer.next_token(Processor::Pipelined);
+        if token.token == Token::EOF {
+            break;
+        }
+        tokens.push(token);
+    }
+    let (expression, length) = Expression::parse(&tokens).unwrap();
+    assert_eq!(length, tokens.len(), "{}", source);
+    expression
+}
+
+fn evaluate(source: &str) -> i64 {
+    parse(source)
+        .evaluate(&|name| match name {
+            "A" => Some(Value::address(0x1234)),
+            "B" => Some(Value::address(0x1200)),
+            _ => None,
+        })
+        .unwrap()
+        .value
+}
+
+#[test]
+fn operators_follow_c_precedence() {
+    assert_eq!(evaluate("(1<<5)-1"), 31);
+    assert_eq!(evaluate("1+2*3"), 7);
+    assert_eq!(evaluate("(1+2)*3"), 9);
+    assert_eq!(evaluate("10-4-3"), 3);
+    assert_eq!(evaluate("1|6&3"), 3);
+    assert_eq!(evaluate("-7/2"), -3);
+    assert_eq!(evaluate("-7%2"), -1);
+    assert_eq!(evaluate("~0^0xF"), -16);
+    assert_eq!(evaluate("1 << 2 + 1"), 8);
+    assert_eq!(evaluate("A-B"), 0x34);
+    assert_eq!(evaluate("lo(A)"), 0x34);
+    assert_eq!(evaluate("hi(A) + 1"), 0x13);
+}
+
+#[test]
+fn expressions_print_back_with_needed_parentheses() {
+    for (source, printed) in [
+        ("(1<<5)-1", "(1<<5)-1"),
+        ("BUF + 3", "BUF+3"),
+        ("A-(B-C)", "A-(B-C)"),
+        ("(A-B)-C", "A-B-C"),
+        ("A*(B+C)", "A*(B+C)"),
+        ("A-(-1)", "A-(-1)"),
+        ("-(A+1)", "-(A+1)"),
+        ("LO(END - START)", "lo(END-START)"),
+    ] {
+        assert_eq!(parse(source).to_string(), printed);
+        assert_eq!(parse(printed).to_string(), printed);
+    }
+}
+
+#[test]
+fn operands_take_expressions() {
+    let program = assemble_pipelined(
+        ".equ SIZE, END-START\n\
+         .equ ENTRY, LOOP\n\
+         START: LLI R1, (1<<5)-1\n\
+                ADI R2, R0, SIZE\n\
+         LOOP:  LW R3, R2, BUF-START-8\n\
+                BEQ R3, R0, LOOP+2\n\
+                JAL R7, ENTRY\n\
+                LLI R4, lo(BUF)\n\
+         END:   .word BUF+3, hi(0x1234), -(2*3)\n\
+         BUF:   .space 6 / 2\n",
+    )
+    .unwrap();
+
+    assert_eq!(program.symbol_table["END"], 6);
+    assert_eq!(program.symbol_table["BUF"], 9);
+    assert_eq!(
+        program.words,
+        vec![
+            0b0011_001_000011111,
+            0b0000_010_000_000110,
+            0b0100_011_010_000001,
+            0b1000_011_000_000001,
+            0b1100_111_111111110,
+            0b0011_100_000001001,
+            0x000C,
+            0x0012,
+            0xFFFA,
+            0,
+            0,
+            0,
+        ]
+    );
+}
+
+#[test]
+fn results_are_checked_against_the_field() {
+    assert_eq!(
+        first_error("ADI R1, R1, (1<<5)+1\n").message,
+        "Immediate 33 is out of range for ADI: IMM6 is a signed constant (-32 to 31)"
+    );
+    assert_eq!(
+        first_error("LLI R1, 1<<40\n").message,
+        "Immediate 1099511627776 is out of range for LLI: IMM9 is an unsigned constant (0 to 511)"
+    );
+    assert_eq!(
+        first_error("BEQ R1, R2, HERE+40\nHERE: ADI R1, R1, 1\n").message,
+        "Target HERE+40 is out of range: offset 41 does not fit in IMM6 (-32 to 31)"
+    );
+    assert_eq!(
+        first_error(".word 0x7FFF * 4\n").message,
+        "Value 131068 does not fit in a 16-bit word (-32768 to 65535)"
+    );
+}
+
+#[test]
+fn malformed_expressions_are_errors() {
+    for (source, message) in [
+        ("ADI R1, R1, (1+2\n", "Expected ')'"),
+        ("ADI R1, R1, 1+\n", "Expected a value"),
+        ("ADI R1, R1, 4/(2-2)\n", "Division by zero in 4/(2-2)"),
+        (
+            "ADI R1, R1, 1<<64\n",
+            "Shift amount 64 is out of range (0 to 63)",
+        ),
+        (
+            ".word 0x7FFFFFFF << 40\n",
+            "Expression 2147483647<<40 overflows 64 bits",
+        ),
+        (
+            "ADI R1, R1, 1 2\n",
+            "Unexpected token after operands: Number(2)",
+        ),
+        (
+            "A: BEQ R1, R2, A+A\n",
+            "A+A is not a number or an address: it adds label addresses together",
+        ),
+        (
+            "A: BEQ R1, R2, -A\n",
+            "-A is not a number or an address: it subtracts a label address from a number",
+        ),
+        (
+            "A: ADI R1, R1, 1\nB: LLI R1, A-B-A\n",
+            "A-B-A is not a number or an address: it subtracts a label address from a number",
+        ),
+        (
+            ".org LATE\nLATE: .equ X, 1\n",
+            "LATE must be a constant defined before it is used",
+        ),
+    ] {
+        assert_eq!(first_error(source).message, message, "{}", source);
+    }
+}
@@ -0,0 +1,96 @@
+// The source formatter: columns, case, spacing, comments, and the same binary afterwards.
+
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions};
+use iitb_cpu::format::{format, is_formatted};
+use iitb_cpu::lexer::Processor;
+
+const MESSY: &str = "\
+; sum R1 + ... down to 1
+.equ  COUNT,5
+sum:   lli r2 ,COUNT
+  lli r3,0   // running total
+.loop:  ada R3,r3 , r2
+   ADI r2,r2,-1
+ beq R2 ,r0,1f
+  b .loop
+1: ret ; done
+   .word (COUNT<<2)|1 , -COUNT , ~0
+\t    ; trailing notes
+LM r0 , {R1,  r3-R5}
+
+
+";
+
+fn words(source: &str) -> Vec<u16> {
+    assemble(source, &AssembleOptions::default()).unwrap().words
+}
+
+#[test]
+fn lines_are_laid_out_in_columns() {
+    assert_eq!(
+        format(MESSY, Processor::Pipelined),
+        "\
+; sum R1 + ... down to 1
+       .equ COUNT, 5
+sum:   LLI R2, COUNT
+       LLI R3, 0 // running total
+.loop: ADA R3, R3, R2
+       ADI R2, R2, -1
+       BEQ R2, R0, 1f
+       B   .loop
+1:     RET       ; done
+       .word (COUNT << 2) | 1, -COUNT, ~0
+       ; trailing notes
+       LM  R0, {R1, R3-R5}
+"
+    );
+}
+
+#[test]
+fn formatting_keeps_the_binary() {
+    let formatted = format(MESSY, Processor::Pipelined);
+    assert_eq!(words(&formatted), words(MESSY));
+}
+
+#[test]
+fn formatting_is_idempotent() {
+    let formatted = format(MESSY, Processor::Pipelined);
+    assert!(!is_formatted(MESSY, Processor::Pipelined));
+    assert!(is_formatted(&formatted, Processor::Pipelined));
+}
+
+#[test]
+fn lines_the_lexer_cannot_read_are_kept() {
+    let source = "LLI R1, 0x1G   \n.include \"open\n  nop\n";
+    assert_eq!(
+        format(source, Processor::Pipelined),
+        "LLI R1, 0x1G\n.include \"open\nNOP\n"
+    );
+}
+
+#[test]
+fn macros_and_conditionals_keep_their_names() {
+    let source = ".macro push reg\nsw reg,r6,0\n.endm\n.IFDEF FAST\npush r1\n.ENDIF\n";
+    assert_eq!(
+        format(source, Processor::Pipelined),
+        ".macro push reg\nSW   reg, R6, 0\n.endm\n.ifdef FAST\npush R1\n.endif\n"
+    );
+}
+
+#[test]
+fn calls_keep_their_parenthesis() {
+    let source = "lli r1,lo( TABLE )\nlli r2 , hi (TABLE+1)\n";
+    assert_eq!(
+        format(source, Processor::Pipelined),
+        "LLI R1, lo(TABLE)\nLLI R2, hi(TABLE + 1)\n"
+    );
+}
+
+#[test]
+fn single_cycle_sources_are_formatted_for_their_processor() {
+    let source = "add r1,r2,r3\nlhi r4 ,171\n";
+    assert_eq!(
+        format(source, Processor::SingleCycle),
+        "ADD R1, R2, R3\nLHI R4, 171\n"
+    );
+}
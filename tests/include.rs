@@ -0,0 +1,177 @@
+// .include: search order, cycles, and diagnostics that point into the included file.
+
+#![allow(clippy::unusual_byte_groupings)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use iitb_cpu::crates::assembler::{assemble, assemble_sources, AssembleOptions};
+use iitb_cpu::source::SourceMap;
+
+// A fresh directory holding `files`, as (relative path, text).
+fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root =
+        std::env::temp_dir().join(format!("iitb_cpu_include_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for (path, text) in files {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    root
+}
+
+fn load(root: &Path, main: &str) -> SourceMap {
+    let mut sources = SourceMap::new();
+    sources.load(&root.join(main)).unwrap();
+    sources
+}
+
+#[test]
+fn included_lines_are_assembled_in_place() {
+    let root = project(
+        "in_place",
+        &[
+            (
+                "main.asm",
+                "LLI R1, 1\n.include \"lib/stack.asm\"\nJAL R0, PUSH\n",
+            ),
+            ("lib/stack.asm", "PUSH: SW R1, R6, 0\n      ADI R6, R6, -1"),
+        ],
+    );
+    let mut sources = load(&root, "main.asm");
+    let program = assemble_sources(&mut sources, &AssembleOptions::default()).unwrap();
+    let expected = assemble(
+        "LLI R1, 1\nPUSH: SW R1, R6, 0\nADI R6, R6, -1\nJAL R0, PUSH\n",
+        &AssembleOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(program.words, expected.words);
+    assert_eq!(program.symbol_table["PUSH"], 1);
+    assert_eq!(sources.files.len(), 2);
+    assert_eq!(program.files[&0], 0);
+    assert_eq!(program.files[&1], 1);
+    assert_eq!(program.line_numbers[&2], 2);
+}
+
+#[test]
+fn include_paths_are_searched_after_the_including_directory() {
+    let root = project(
+        "search",
+        &[
+            (
+                "src/main.asm",
+                ".include \"defs.asm\"\n.include \"macros.asm\"\nLLI R1, VALUE\n",
+            ),
+            ("src/defs.asm", ".equ VALUE, 1\n"),
+            ("include/defs.asm", ".equ VALUE, 2\n"),
+            ("include/macros.asm", ".equ OTHER, 3\n"),
+        ],
+    );
+    let mut sources = load(&root, "src/main.asm");
+    let options = AssembleOptions {
+        include_paths: vec![root.join("include")],
+        ..AssembleOptions::default()
+    };
+    let program = assemble_sources(&mut sources, &options).unwrap();
+    assert_eq!(program.words, vec![0b0011_001_000000001]);
+}
+
+#[test]
+fn errors_in_an_included_file_point_at_that_file() {
+    let root = project(
+        "errors",
+        &[
+            ("main.asm", "NOP\n.include \"bad.asm\"\n"),
+            ("bad.asm", "; helpers\nLW R1, R2, 99\n"),
+        ],
+    );
+    let mut sources = load(&root, "main.asm");
+    let diagnostics = assemble_sources(&mut sources, &AssembleOptions::default()).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    let diagnostic = &diagnostics[0];
+    assert_eq!(diagnostic.file, 1);
+    assert_eq!(&sources.files[1].text[diagnostic.span.clone()], "99");
+    let rendered = sources.render(diagnostic);
+    assert!(rendered.contains("bad.asm:2:12"), "{}", rendered);
+    assert!(rendered.contains("2 | LW R1, R2, 99"), "{}", rendered);
+}
+
+#[test]
+fn missing_files_are_reported_with_the_search_path() {
+    let root = project(
+        "missing",
+        &[("main.asm", ".include \"nowhere.asm\"\nNOP\n")],
+    );
+    let mut sources = load(&root, "main.asm");
+    let options = AssembleOptions {
+        include_paths: vec![root.join("include")],
+        ..AssembleOptions::default()
+    };
+    let diagnostics = assemble_sources(&mut sources, &options).unwrap_err();
+    assert_eq!(diagnostics[0].message, "Cannot find nowhere.asm");
+    assert_eq!(diagnostics[0].file, 0);
+    assert_eq!(diagnostics[0].span, 9..22);
+    assert!(diagnostics[0].help.as_ref().unwrap().contains("include"));
+}
+
+#[test]
+fn include_cycles_are_reported() {
+    let root = project(
+        "cycle",
+        &[
+            ("a.asm", ".include \"b.asm\"\nNOP\n"),
+            ("b.asm", ".include \"a.asm\"\n"),
+        ],
+    );
+    let mut sources = load(&root, "a.asm");
+    let diagnostics = assemble_sources(&mut sources, &AssembleOptions::default()).unwrap_err();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].message,
+        "Include cycle: a.asm -> b.asm -> a.asm"
+    );
+    assert_eq!(diagnostics[0].file, 1);
+}
+
+#[test]
+fn a_file_can_be_included_twice_without_a_cycle() {
+    let root = project(
+        "twice",
+        &[
+            ("main.asm", ".include \"nop.asm\"\n.include \"nop.asm\"\n"),
+            ("nop.asm", "NOP\n"),
+        ],
+    );
+    let mut sources = load(&root, "main.asm");
+    let program = assemble_sources(&mut sources, &AssembleOptions::default()).unwrap();
+    assert_eq!(program.words.len(), 2);
+}
+
+#[test]
+fn malformed_includes_are_reported() {
+    let first = |source: &str| {
+        assemble(source, &AssembleOptions::default())
+            .unwrap_err()
+            .remove(0)
+    };
+    assert_eq!(first(".include\n").message, ".include expects \"FILE\"");
+    assert_eq!(
+        first(".include stack.asm\n").message,
+        ".include expects \"FILE\""
+    );
+    assert_eq!(
+        first(".include \"stack.asm\n").message,
+        "Unterminated string: expected a closing '\"'"
+    );
+}
+
+#[test]
+fn includes_in_skipped_blocks_are_not_read() {
+    let program = assemble(
+        ".if 0\n.include \"does_not_exist.asm\"\n.endif\nNOP\n",
+        &AssembleOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(program.words.len(), 1);
+}
@@ -0,0 +1,54 @@
+// Label resolution: branch and jump targets become PC-relative offsets.
+
+use iitb_cpu::parser::Parser;
+
+fn parse(source: &str) -> Result<Parser, String> {
+    let mut parser = Parser::new(source);
+    match parser.parse().into_iter().find(|d| d.is_error()) {
+        Some(diagnostic) => Err(diagnostic.message),
+        None => Ok(parser),
+    }
+}
+
+#[test]
+fn labels_resolve_to_pc_relative_offsets() {
+    let parser = parse(
+        "START: ADI R1, R1, 1\n\
+         LOOP:  BEQ R1, R2, DONE\n\
+                ADI R1, R1, 1\n\
+                JAL R7, LOOP\n\
+         DONE:  JAL R0, START\n",
+    )
+    .unwrap();
+
+    assert_eq!(parser.symbol_table["START"], 0);
+    assert_eq!(parser.symbol_table["LOOP"], 1);
+    assert_eq!(parser.symbol_table["DONE"], 4);
+
+    let offsets: Vec<i32> = parser.instructions.iter().map(|i| i.imm).collect();
+    assert_eq!(offsets, vec![1, 3, 1, -2, -4]);
+}
+
+#[test]
+fn undefined_label_is_an_error() {
+    let error = parse("BEQ R1, R2, NOWHERE\n").unwrap_err();
+    assert_eq!(error, "Undefined label: NOWHERE");
+}
+
+#[test]
+fn duplicate_label_is_an_error() {
+    let error = parse("A: ADI R1, R1, 1\nA: ADI R1, R1, 1\n").unwrap_err();
+    assert_eq!(error, "Duplicate label: A");
+}
+
+#[test]
+fn out_of_range_target_is_an_error() {
+    let mut source = String::from("BEQ R1, R2, FAR\n");
+    for _ in 0..40 {
+        source.push_str("ADI R1, R1, 1\n");
+    }
+    source.push_str("FAR: ADI R1, R1, 1\n");
+
+    let error = parse(&source).unwrap_err();
+    assert!(error.starts_with("Target FAR is out of range"), "{}", error);
+}
@@ -0,0 +1,137 @@
+// The .lst listing: encodings by source line, the symbol table and the cross-reference.
+
+use std::collections::BTreeMap;
+use std::fs;
+
+use iitb_cpu::crates::assembler::{assemble_sources, AssembleOptions};
+use iitb_cpu::crates::listing::listing;
+use iitb_cpu::source::SourceMap;
+
+fn list_sources(mut sources: SourceMap, options: &AssembleOptions) -> String {
+    let program = assemble_sources(&mut sources, options).unwrap();
+    listing(&program, &sources)
+}
+
+fn list(source: &str) -> String {
+    let mut sources = SourceMap::new();
+    sources.add("<input>", source);
+    list_sources(sources, &AssembleOptions::default())
+}
+
+#[test]
+fn lines_are_listed_with_their_fields() {
+    let source = "\
+; count down
+.equ COUNT, 3
+START: LLI R1, COUNT
+LOOP:  ADC R2, R2, R1
+       ADI R1, R1, -1
+       BEQ R1, R0, DONE
+       JAL R0, LOOP
+DONE:  LW R3, R2, 1
+TABLE: .word START, 0xBEEF
+";
+    assert_eq!(
+        list(source),
+        "\
+<input>
+ LINE  ADDR  HEX   BINARY                 SOURCE
+    1                                     ; count down
+    2                                     .equ COUNT, 3
+    3  0000  3203  0011 001 000000011     START: LLI R1, COUNT
+    4  0001  148A  0001 010 010 001 0 10  LOOP:  ADC R2, R2, R1
+    5  0002  027F  0000 001 001 111111           ADI R1, R1, -1
+    6  0003  8202  1000 001 000 000010           BEQ R1, R0, DONE
+    7  0004  C1FD  1100 000 111111101            JAL R0, LOOP
+    8  0005  4681  0100 011 010 000001    DONE:  LW R3, R2, 1
+    9  0006  0000  0000000000000000       TABLE: .word START, 0xBEEF
+       0007  BEEF  1011111011101111
+
+Symbols
+  NAME   VALUE   DEFINED
+  COUNT  3       <input>:2
+  DONE   0x0005  <input>:8
+  LOOP   0x0001  <input>:4
+  START  0x0000  <input>:3
+  TABLE  0x0006  <input>:9
+
+Cross-reference
+  COUNT  3
+  DONE   6
+  LOOP   7
+  START  9
+  TABLE  not used
+  R0     6, 7
+  R1     3, 4, 5, 6
+  R2     4, 8
+  R3     8
+"
+    );
+}
+
+#[test]
+fn pseudo_instructions_and_macros_list_every_word_at_the_call() {
+    let source = "\
+.macro PUSH reg
+       SW reg, R6, 0
+       ADI R6, R6, -1
+.endm
+       PUSH R5
+       NOP
+";
+    let text = list(source);
+    let rows: Vec<&str> = text.lines().skip(2).take(6).collect();
+    assert_eq!(
+        rows,
+        [
+            "    1                                     .macro PUSH reg",
+            "    2                                            SW reg, R6, 0",
+            "    3                                            ADI R6, R6, -1",
+            "    4                                     .endm",
+            "    5  0000  5B80  0101 101 110 000000           PUSH R5",
+            "       0001  0DBF  0000 110 110 111111",
+        ]
+    );
+    assert!(text.contains("    6  0002  8001  1000 000 000 000001           NOP\n"));
+    // the body is where R6 is written, the call where R5 is
+    assert!(text.contains("  R5  5\n"));
+    assert!(text.contains("  R6  2, 3\n"));
+}
+
+#[test]
+fn constants_defined_from_outside_are_listed() {
+    let mut defines = BTreeMap::new();
+    defines.insert("SIZE".to_string(), 8);
+    let mut sources = SourceMap::new();
+    sources.add("main.asm", "LLI R1, SIZE\n");
+    let options = AssembleOptions {
+        defines,
+        ..AssembleOptions::default()
+    };
+    let text = list_sources(sources, &options);
+    assert!(text.contains("  SIZE  8      -D\n"));
+    assert!(text.contains("  SIZE  1\n"));
+}
+
+#[test]
+fn included_files_are_listed_after_the_top_level_file() {
+    let root = std::env::temp_dir().join(format!("iitb_cpu_listing_{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("main.asm"), ".include \"lib.asm\"\nJAL R0, INC\n").unwrap();
+    fs::write(root.join("lib.asm"), "INC: ADI R1, R1, 1\n").unwrap();
+    let mut sources = SourceMap::new();
+    sources.load(&root.join("main.asm")).unwrap();
+    let text = list_sources(sources, &AssembleOptions::default());
+    let main = root.join("main.asm").display().to_string();
+    let lib = root.join("lib.asm").display().to_string();
+
+    assert!(text.starts_with(&format!(
+        "{}\n LINE  ADDR  HEX   BINARY                 SOURCE\n\
+         \x20   1                                     .include \"lib.asm\"\n\
+         \x20   2  0001  C1FF  1100 000 111111111     JAL R0, INC\n\n{}\n",
+        main, lib
+    )));
+    assert!(text.contains("    1  0000  0241  0000 001 001 000001    INC: ADI R1, R1, 1\n"));
+    assert!(text.contains(&format!("  INC   0x0000  {}:1\n", lib)));
+    assert!(text.contains(&format!("  R1   {}:1\n", lib)));
+}
@@ -0,0 +1,123 @@
+// Local labels (.loop) scoped to the global label before them, and numeric labels (1f, 1b).
+
+mod common;
+use common::{assemble_pipelined, words};
+
+#[test]
+fn dot_labels_are_scoped_to_the_global_label_before_them() {
+    let source = "\
+SUM:   LLI R3, 0
+.loop: ADA R3, R3, R1
+       BEQ R2, R0, .done
+       JAL R0, .loop
+.done: JRI R7, 0
+COUNT: LLI R3, 0
+.loop: ADI R3, R3, 1
+       JAL R0, .loop
+";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(program.symbol_table["SUM.loop"], 1);
+    assert_eq!(program.symbol_table["SUM.done"], 4);
+    assert_eq!(program.symbol_table["COUNT.loop"], 6);
+    assert!(!program.symbol_table.contains_key(".loop"));
+    assert_eq!(
+        program.words,
+        words(
+            "LLI R3, 0\nADA R3, R3, R1\nBEQ R2, R0, 2\nJAL R0, -2\nJRI R7, 0\n\
+             LLI R3, 0\nADI R3, R3, 1\nJAL R0, -1\n"
+        )
+    );
+}
+
+#[test]
+fn local_labels_can_be_named_in_full_from_elsewhere() {
+    let source = "\
+MAIN:  JAL R7, SUM.loop
+SUM:   NOP
+.loop: NOP
+";
+    assert_eq!(words(source), words("JAL R7, 2\nNOP\nNOP\n"));
+}
+
+#[test]
+fn numeric_labels_are_found_forwards_and_backwards() {
+    let source = "\
+FILL: LLI R1, 0
+1:    ADI R1, R1, 1
+      BEQ R1, R2, 1f
+      JAL R0, 1b
+1:    JRI R7, 0
+      JAL R0, 1b
+";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(program.symbol_table["FILL.1"], 1);
+    assert_eq!(program.symbol_table["FILL.1#2"], 4);
+    assert_eq!(
+        program.words,
+        words("LLI R1, 0\nADI R1, R1, 1\nBEQ R1, R2, 2\nJAL R0, -2\nJRI R7, 0\nJAL R0, -1\n")
+    );
+}
+
+#[test]
+fn local_labels_inside_macros_belong_to_the_caller() {
+    let source = "\
+.macro WAIT n
+       LLI R5, n
+1:     ADI R5, R5, -1
+       BEQ R5, R0, 1f
+       JAL R0, 1b
+1:
+.endm
+MAIN:  WAIT 3
+.next: WAIT 2
+       JAL R0, .next
+";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(program.symbol_table["MAIN.next"], 4);
+    assert_eq!(program.symbol_table["MAIN.1"], 1);
+    assert_eq!(program.symbol_table["MAIN.1#4"], 8);
+    let wait = "LLI R5, 3\nADI R5, R5, -1\nBEQ R5, R0, 2\nJAL R0, -2\n";
+    assert_eq!(
+        program.words,
+        words(&format!(
+            "{}{}JAL R0, -4\n",
+            wait,
+            wait.replace("R5, 3", "R5, 2")
+        ))
+    );
+}
+
+#[test]
+fn unresolved_local_labels_are_errors() {
+    let first = |source: &str| assemble_pipelined(source).unwrap_err().remove(0).message;
+    assert_eq!(
+        first("MAIN: JAL R0, 1b\n1: NOP\n1: NOP\n"),
+        "Undefined label: 1b"
+    );
+    assert_eq!(first("1: NOP\nJAL R0, 1f\n"), "Undefined label: 1f");
+    assert_eq!(
+        first("A: NOP\n.x: NOP\nB: JAL R0, .x\n"),
+        "Undefined label: B.x"
+    );
+    assert_eq!(first("A: NOP\n.x: NOP\n.x: NOP\n"), "Duplicate label: A.x");
+}
+
+#[test]
+fn numeric_references_need_a_numeric_label() {
+    // with a 0: in the file, 0b and 0f are references to it
+    let source = "0: LLI R1, 0b\n   LLI R2, 0f\n0: NOP\n";
+    assert_eq!(words(source), words("LLI R1, 0\nLLI R2, 2\nNOP\n"));
+
+    // without one they are literals missing their digits
+    let first = |source: &str| assemble_pipelined(source).unwrap_err().remove(0).message;
+    assert_eq!(first("LLI R1, 0b\n"), "Invalid number 0b: missing digits");
+    assert_eq!(
+        first("LLI R1, 0f\n"),
+        "Invalid number 0f: 'f' is not a decimal digit (write hexadecimal with a 0x prefix, e.g. 0x1F)"
+    );
+    assert_eq!(first("LLI R1, 0x\n"), "Invalid number 0x: missing digits");
+    assert_eq!(
+        first("1: NOP\nJAL R0, 2b\n"),
+        "Invalid number 2b: 'b' is not a decimal digit (write hexadecimal with a 0x prefix, e.g. 0x1F)"
+    );
+}
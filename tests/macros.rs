@@ -0,0 +1,133 @@
+// .macro definitions, expansion, local labels and diagnostics inside expansions.
+
+use iitb_cpu::diagnostic::Severity;
+
+mod common;
+use common::{assemble_pipelined, words};
+
+#[test]
+fn calls_expand_with_their_arguments() {
+    let source = "\
+.macro PUSH reg
+       SW reg, R6, 0
+       ADI R6, R6, -1
+.endm
+.macro SAVE list
+       SM R6, list
+.endm
+MAIN:  PUSH R1
+       PUSH R2
+       SAVE {R0-R2}
+       JAL R0, MAIN
+";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(program.symbol_table["MAIN"], 0);
+    assert_eq!(
+        program.words,
+        words(
+            "SW R1, R6, 0\nADI R6, R6, -1\nSW R2, R6, 0\nADI R6, R6, -1\nSM R6, {R0-R2}\nJAL R0, -5\n"
+        )
+    );
+}
+
+#[test]
+fn labels_are_local_to_each_expansion() {
+    let source = "\
+.macro TIMES3 dst, src
+       ADA dst, src, R0
+LOOP:  ADA dst, dst, src
+       BEQ dst, dst, LOOP
+.endm
+       TIMES3 R1, R2
+       TIMES3 R3, R4
+       TIMES3 R5, R6
+";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(program.symbol_table["LOOP@1"], 1);
+    assert_eq!(program.symbol_table["LOOP@2"], 4);
+    assert_eq!(program.symbol_table["LOOP@3"], 7);
+    assert!(!program.symbol_table.contains_key("LOOP"));
+    assert_eq!(program.words[2], words("BEQ R1, R1, -1")[0]);
+}
+
+#[test]
+fn errors_in_an_expansion_point_at_the_body_and_the_call() {
+    let source = "\
+.macro CLEAR reg
+       LLI reg, 512
+.endm
+       CLEAR R1
+";
+    let diagnostics = assemble_pipelined(source).unwrap_err();
+    let summary: Vec<(Severity, &str)> = diagnostics
+        .iter()
+        .map(|d| (d.severity, &source[d.span.clone()]))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![(Severity::Error, "512"), (Severity::Note, "CLEAR R1")]
+    );
+    assert_eq!(diagnostics[1].message, "in expansion of macro CLEAR");
+    assert!(diagnostics[0]
+        .render("test.asm", source)
+        .contains("2 |        LLI reg, 512"));
+
+    // an argument that is wrong for the body is reported where it was written
+    let source = ".macro M reg\n  ADI reg, reg, 1\n.endm\n  M 5\n";
+    let diagnostics = assemble_pipelined(source).unwrap_err();
+    assert_eq!(&source[diagnostics[0].span.clone()], "5");
+    assert_eq!(&source[diagnostics[1].span.clone()], "M 5");
+}
+
+#[test]
+fn nested_calls_note_every_level() {
+    let source = "\
+.macro INNER
+       ADI R1, R1, 99
+.endm
+.macro OUTER
+       INNER
+.endm
+       OUTER
+";
+    let diagnostics = assemble_pipelined(source).unwrap_err();
+    let notes: Vec<&str> = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Note)
+        .map(|d| &source[d.span.clone()])
+        .collect();
+    // the outer call is later in the file, so it sorts after the error
+    assert_eq!(notes, vec!["INNER", "OUTER"]);
+}
+
+#[test]
+fn malformed_macros_are_errors() {
+    for (source, message) in [
+        (".macro\n.endm\n", ".macro expects NAME PARAMETER, ..."),
+        (
+            ".macro ADI\n.endm\n",
+            "Expected a macro name that is not an instruction or register",
+        ),
+        (
+            ".macro M R1\n.endm\n",
+            "Expected a parameter name that is not an instruction or register",
+        ),
+        (".macro M a, a\n.endm\n", "Duplicate parameter: a"),
+        (".macro M\n", "Macro M has no .endm"),
+        (".endm\n", ".endm without a .macro"),
+        (
+            ".macro M\n.macro N\n.endm\n",
+            "A macro cannot be defined inside another macro",
+        ),
+        (".macro M\n.endm\n.macro M\n.endm\n", "Duplicate macro: M"),
+        (
+            ".macro M a, b\n.endm\nM R1\n",
+            "Macro M expects 2 argument(s) (a, b), found 1",
+        ),
+        (".macro M a\n.endm\nM R1,\n", "Argument 2 of M is empty"),
+        (".macro M\nM\n.endm\nM\n", "Macro M calls itself"),
+    ] {
+        let diagnostics = assemble_pipelined(source).unwrap_err();
+        assert_eq!(diagnostics[0].message, message, "{}", source);
+    }
+}
@@ -0,0 +1,247 @@
+// Relocatable objects: what they hold, the object file, and linking them together.
+
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions};
+use iitb_cpu::crates::object::{assemble_object, link, LinkOptions, Object};
+use iitb_cpu::diagnostic::Diagnostic;
+use iitb_cpu::isa::Immediate;
+use iitb_cpu::parser::{Relocation, RelocationKind, Section};
+use iitb_cpu::source::SourceMap;
+
+const MAIN: &str = "\
+        .global MAIN
+        .extern PRINT, COUNT
+MAIN:   LLI R1, 3
+        CALL PRINT
+        LW R2, R0, COUNT
+LOOP:   BEQ R0, R0, LOOP
+        .data
+PTR:    .word MAIN, PRINT+1, PTR
+";
+
+const PRINT: &str = "\
+        .global PRINT, COUNT
+PRINT:  ADI R1, R1, -1
+        JLR R0, R7
+        .data
+COUNT:  .word 7
+";
+
+fn object(source: &str) -> Result<Object, Vec<Diagnostic>> {
+    let mut sources = SourceMap::new();
+    sources.add("module.asm", source);
+    assemble_object(&mut sources, &AssembleOptions::default())
+}
+
+fn first_error(source: &str) -> Diagnostic {
+    object(source).unwrap_err().remove(0)
+}
+
+fn objects() -> Vec<(String, Object)> {
+    vec![
+        ("main.o".to_string(), object(MAIN).unwrap()),
+        ("print.o".to_string(), object(PRINT).unwrap()),
+    ]
+}
+
+fn relocation(section: Section, offset: u16, kind: RelocationKind, symbol: &str) -> Relocation {
+    Relocation {
+        section,
+        offset,
+        kind,
+        symbol: symbol.to_string(),
+        addend: 0,
+    }
+}
+
+#[test]
+fn objects_hold_their_sections_symbols_and_relocations() {
+    let main = object(MAIN).unwrap();
+    assert_eq!(main.text, [0x3203, 0xCE00, 0x4400, 0x8000]);
+    assert_eq!(main.data, [0, 0, 0]);
+    assert_eq!(main.globals["MAIN"], (Section::Text, 0));
+    assert_eq!(main.externs.iter().collect::<Vec<_>>(), ["COUNT", "PRINT"]);
+    // the branch to LOOP stays within .text and needs no relocation
+    assert_eq!(
+        main.relocations,
+        [
+            relocation(
+                Section::Text,
+                1,
+                RelocationKind::Field(Immediate::Offset(9)),
+                "PRINT"
+            ),
+            relocation(
+                Section::Text,
+                2,
+                RelocationKind::Field(Immediate::Signed(6)),
+                "COUNT"
+            ),
+            relocation(Section::Data, 0, RelocationKind::Word, ".text"),
+            Relocation {
+                addend: 1,
+                ..relocation(Section::Data, 1, RelocationKind::Word, "PRINT")
+            },
+            relocation(Section::Data, 2, RelocationKind::Word, ".data"),
+        ]
+    );
+    assert_eq!(object(PRINT).unwrap().globals["COUNT"], (Section::Data, 0));
+}
+
+#[test]
+fn object_files_read_back_what_was_written() {
+    let main = object(MAIN).unwrap();
+    let text = main.write();
+    assert_eq!(
+        text,
+        "\
+iitb-object Pipelined
+.text 4
+3203 CE00 4400 8000
+.data 3
+0000 0000 0000
+.global MAIN .text 0
+.extern COUNT
+.extern PRINT
+.reloc .text 1 offset9 PRINT 0
+.reloc .text 2 imm6 COUNT 0
+.reloc .data 0 word .text 0
+.reloc .data 1 word PRINT 1
+.reloc .data 2 word .data 0
+"
+    );
+    assert_eq!(Object::read(&text).unwrap(), main);
+
+    assert_eq!(
+        Object::read("iitb-object Pipelined\n.text 2\n3203\n").unwrap_err(),
+        "the object file ends 1 words early"
+    );
+    assert_eq!(
+        Object::read("iitb-object Pipelined\n.reloc .text 0 imm12 X 0\n").unwrap_err(),
+        "line 2: invalid field imm12"
+    );
+    assert!(Object::read(":020000040000FA\n").is_err());
+}
+
+#[test]
+fn linking_places_the_sections_and_patches_the_fields() {
+    let image = link(&objects(), &LinkOptions::default()).unwrap();
+    assert_eq!(
+        image.words,
+        [
+            0x3203, // LLI R1, 3
+            0xCE03, // CALL PRINT: 3 words ahead
+            0x4409, // LW R2, R0, COUNT
+            0x8000, // BEQ R0, R0, LOOP
+            0x027F, // PRINT: ADI R1, R1, -1
+            0xD1C0, // JLR R0, R7
+            0x0000, // MAIN
+            0x0005, // PRINT+1
+            0x0006, // PTR
+            0x0007, // COUNT
+        ]
+    );
+    assert_eq!(image.symbol_table["PRINT"], 4);
+    assert_eq!(image.symbol_table["COUNT"], 9);
+
+    // the linked program is the one assembled in one piece
+    let whole = assemble(
+        &format!(
+            "{}\n.text\n{}",
+            MAIN.replace("        .extern PRINT, COUNT\n", ""),
+            PRINT.replace(".data\n", ".data\n.org 9\n")
+        ),
+        &AssembleOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(whole.words, image.words);
+}
+
+#[test]
+fn sections_go_where_they_are_asked() {
+    let options = LinkOptions {
+        text: 0x10,
+        data: Some(0x18),
+    };
+    let image = link(&objects(), &options).unwrap();
+    assert_eq!(image.words.len(), 0x1C);
+    assert_eq!(image.words[0x10..0x13], [0x3203, 0xCE03, 0x441B]);
+    assert_eq!(image.words[0x18..0x1C], [0x10, 0x15, 0x18, 7]);
+
+    let overlap = LinkOptions {
+        text: 0,
+        data: Some(2),
+    };
+    assert_eq!(
+        link(&objects(), &overlap).unwrap_err(),
+        [".text (0x0000 to 0x0005) and .data (0x0002 to 0x0005) overlap"]
+    );
+}
+
+#[test]
+fn missing_and_duplicate_symbols_are_reported() {
+    let main = object(MAIN).unwrap();
+    assert_eq!(
+        link(
+            &[("main.o".to_string(), main.clone())],
+            &LinkOptions::default()
+        )
+        .unwrap_err(),
+        [
+            "Undefined symbol COUNT, used by main.o",
+            "Undefined symbol PRINT, used by main.o"
+        ]
+    );
+    let mut with_copy = objects();
+    with_copy.push(("copy.o".to_string(), main));
+    assert_eq!(
+        link(&with_copy, &LinkOptions::default()).unwrap_err(),
+        ["MAIN is defined by both main.o and copy.o"]
+    );
+
+    // LW can only reach the first 32 words
+    let far = LinkOptions {
+        text: 0,
+        data: Some(0x100),
+    };
+    assert_eq!(
+        link(&objects(), &far).unwrap_err(),
+        ["main.o: .text+2: COUNT is a value of 259, which does not fit in IMM6 (-32 to 31)"]
+    );
+}
+
+#[test]
+fn objects_reject_what_the_linker_cannot_patch() {
+    assert_eq!(
+        first_error(".org 4\nNOP\n").message,
+        "An object cannot use .org: the linker places its sections"
+    );
+    let diagnostic = first_error(".extern TABLE\nLI R1, TABLE\n");
+    assert_eq!(diagnostic.message, "LI cannot load TABLE in an object");
+    assert_eq!(
+        first_error("START: NOP\nLLI R1, lo(START)\n").message,
+        "lo(START) cannot be relocated by the linker"
+    );
+    assert_eq!(
+        first_error(".extern PRINT\nPRINT: NOP\n").message,
+        "PRINT is declared .extern but is defined in this file"
+    );
+    assert_eq!(
+        first_error(".global MAIN\nNOP\n").message,
+        "MAIN is declared .global but is not defined"
+    );
+    // the distance between two labels of one section does not move
+    let object = object("START: NOP\nEND: .word END-START\n.data\nX: .word 0\nLLI R1, X-X\n");
+    assert!(object.unwrap().relocations.is_empty());
+}
+
+#[test]
+fn externs_need_an_object() {
+    let diagnostic = assemble(".extern PRINT\nCALL PRINT\n", &AssembleOptions::default())
+        .unwrap_err()
+        .remove(0);
+    assert_eq!(diagnostic.message, "Undefined label: PRINT");
+    assert_eq!(
+        diagnostic.help.as_deref(),
+        Some("PRINT is .extern: assemble this file as an object and link it")
+    );
+}
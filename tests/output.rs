@@ -0,0 +1,161 @@
+// Memory image writers and readers.
+
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions};
+use iitb_cpu::crates::disassembler::disassemble;
+use iitb_cpu::crates::output::{read, read_intel_hex, read_mif, write, Format};
+use iitb_cpu::lexer::Processor;
+
+use std::fs;
+use std::process::Command;
+
+const WORDS: [u16; 10] = [
+    0x3201, 0x1298, 0x0000, 0xFFFF, 0x8001, 0x7F00, 0x00FF, 0x1234, 0xABCD, 0x5555,
+];
+
+fn text(words: &[u16], format: Format) -> String {
+    String::from_utf8(write(words, format)).unwrap()
+}
+
+#[test]
+fn every_format_reads_back_what_it_wrote() {
+    for format in Format::ALL {
+        for words in [&WORDS[..], &WORDS[..1], &[]] {
+            assert_eq!(
+                read(&write(words, format), format).unwrap(),
+                words,
+                "{}",
+                format
+            );
+        }
+    }
+}
+
+#[test]
+fn assembled_programs_round_trip() {
+    let program = assemble(
+        "LLI R1, 5\nLOOP: ADI R1, R1, -1\nBEQ R1, R0, 1\nJAL R0, LOOP\n.word 0xBEEF\n",
+        &AssembleOptions::default(),
+    )
+    .unwrap();
+    for format in Format::ALL {
+        assert_eq!(
+            read(&write(&program.words, format), format).unwrap(),
+            program.words
+        );
+    }
+}
+
+#[test]
+fn raw_images_have_the_requested_byte_order() {
+    assert_eq!(
+        write(&[0x1234, 0xABCD], Format::RawBigEndian),
+        [0x12, 0x34, 0xAB, 0xCD]
+    );
+    assert_eq!(
+        write(&[0x1234, 0xABCD], Format::RawLittleEndian),
+        [0x34, 0x12, 0xCD, 0xAB]
+    );
+    assert!(read(&[1, 2, 3], Format::RawBigEndian).is_err());
+}
+
+#[test]
+fn intel_hex_records_use_word_addresses_and_checksums() {
+    assert_eq!(
+        text(&WORDS, Format::IntelHex),
+        ":10000000320112980000FFFF80017F0000FF1234D0\n\
+         :04000800ABCD5555D2\n\
+         :00000001FF\n"
+    );
+    assert_eq!(
+        read_intel_hex(":0400080012345678E0\n:00000001FF\n").unwrap(),
+        [0, 0, 0, 0, 0, 0, 0, 0, 0x1234, 0x5678]
+    );
+    assert_eq!(
+        read_intel_hex(":0400080012345678E1\n:00000001FF\n").unwrap_err(),
+        "line 1: checksum does not match"
+    );
+    assert!(read_intel_hex(":0400080012345678E0\n").is_err());
+}
+
+#[test]
+fn mem_files_hold_one_word_per_line() {
+    assert_eq!(
+        text(&WORDS[..2], Format::MemBinary),
+        "0011001000000001\n0001001010011000\n"
+    );
+    assert_eq!(text(&WORDS[..2], Format::MemHex), "3201\n1298\n");
+    assert_eq!(
+        read(
+            "// boot\n3201 1298\n@4 ffff // jump\n".as_bytes(),
+            Format::MemHex
+        )
+        .unwrap(),
+        [0x3201, 0x1298, 0, 0, 0xFFFF]
+    );
+}
+
+#[test]
+fn mif_and_coe_files_have_their_headers() {
+    assert_eq!(
+        text(&WORDS[..2], Format::Mif),
+        "DEPTH = 2;\nWIDTH = 16;\nADDRESS_RADIX = HEX;\nDATA_RADIX = BIN;\nCONTENT\nBEGIN\n\
+         0000 : 0011001000000001;\n0001 : 0001001010011000;\nEND;\n"
+    );
+    assert_eq!(
+        text(&WORDS[..2], Format::Coe),
+        "memory_initialization_radix=2;\nmemory_initialization_vector=\n\
+         0011001000000001,\n0001001010011000;\n"
+    );
+    let mif = "-- ROM\nDEPTH = 8;\nWIDTH = 16;\nADDRESS_RADIX = DEC;\nDATA_RADIX = HEX;\n\
+               CONTENT BEGIN\n0 : 3201 1298;\n[4..5] : FFFF;\nEND;\n";
+    assert_eq!(
+        read_mif(mif).unwrap(),
+        [0x3201, 0x1298, 0, 0, 0xFFFF, 0xFFFF]
+    );
+    assert!(read_mif("WIDTH = 8;\nCONTENT BEGIN\nEND;\n").is_err());
+    assert_eq!(
+        read(
+            "; ROM\nmemory_initialization_radix=16;\nmemory_initialization_vector=3201, 1298;\n"
+                .as_bytes(),
+            Format::Coe
+        )
+        .unwrap(),
+        [0x3201, 0x1298]
+    );
+}
+
+#[test]
+fn formats_are_named_and_inferred_from_extensions() {
+    assert_eq!(Format::from_name("IHEX"), Some(Format::IntelHex));
+    assert_eq!(Format::from_name("srec"), None);
+    assert_eq!(Format::from_extension("rom.mif"), Some(Format::Mif));
+    assert_eq!(
+        Format::from_extension("out/rom.MEM"),
+        Some(Format::MemBinary)
+    );
+    assert_eq!(Format::from_extension("rom"), None);
+}
+
+#[test]
+fn disasm_prints_the_assembly_of_an_image() {
+    let source = "LLI R1, 5\nLOOP: ADI R1, R1, -1\nBEQ R1, R0, 1\nJAL R0, LOOP\n.word 0xBEEF\n";
+    let program = assemble(source, &AssembleOptions::default()).unwrap();
+    let root = std::env::temp_dir().join(format!("iitb_cpu_disasm_{}", std::process::id()));
+    fs::create_dir_all(&root).unwrap();
+
+    for format in Format::ALL {
+        let image = root.join(format!("rom.{}", format.name()));
+        fs::write(&image, write(&program.words, format)).unwrap();
+        let run = Command::new(env!("CARGO_BIN_EXE_iitb_cpu"))
+            .args(["disasm", "--format", format.name()])
+            .arg(&image)
+            .output()
+            .unwrap();
+        assert!(run.status.success(), "{}", format);
+
+        let text = String::from_utf8(run.stdout).unwrap();
+        assert_eq!(text, disassemble(&program.words, Processor::Pipelined));
+        let reassembled = assemble(&text, &AssembleOptions::default()).unwrap();
+        assert_eq!(reassembled.words, program.words, "{}", format);
+    }
+}
@@ -0,0 +1,200 @@
+// Pseudo-instructions and the real instructions they are lowered to.
+
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions};
+use iitb_cpu::crates::disassembler::{decode_instruction, disassemble};
+use iitb_cpu::isa::Opcode;
+use iitb_cpu::lexer::Processor;
+use iitb_cpu::parser::Parser;
+use iitb_cpu::pseudo::PseudoOp;
+
+mod common;
+use common::{assemble_pipelined, first_error};
+
+fn words(source: &str, processor: Processor) -> Vec<u16> {
+    assemble(
+        source,
+        &AssembleOptions {
+            processor,
+            ..AssembleOptions::default()
+        },
+    )
+    .unwrap()
+    .words
+}
+
+// Runs a sequence of LLI, ADI and ADA/ADD instructions and returns the registers.
+fn run(words: &[u16], processor: Processor) -> [u16; 8] {
+    let mut registers = [0u16; 8];
+    for word in words {
+        let instruction = decode_instruction(*word, processor).unwrap();
+        let a = instruction.reg_a as usize;
+        let b = instruction.reg_b.unwrap_or(0) as usize;
+        match instruction.opcode {
+            Opcode::Lli => registers[a] = instruction.imm as u16,
+            Opcode::Adi => registers[b] = registers[a].wrapping_add(instruction.imm as u16),
+            Opcode::Ada | Opcode::Add => {
+                let c = instruction.reg_c.unwrap() as usize;
+                registers[c] = registers[a].wrapping_add(registers[b]);
+            }
+            opcode => panic!("unexpected {} in a constant load", opcode),
+        }
+    }
+    registers
+}
+
+#[test]
+fn single_instruction_pseudo_ops_lower_to_real_ones() {
+    let source = "\
+START: NOP
+       MOV R1, R2
+       CLR R3
+       B START
+       CALL START
+       RET
+";
+    assert_eq!(
+        words(source, Processor::Pipelined),
+        words(
+            "BEQ R0, R0, 1\nADI R2, R1, 0\nLLI R3, 0\nBEQ R0, R0, -3\nJAL R7, -4\nJRI R7, 0\n",
+            Processor::Pipelined
+        )
+    );
+    assert_eq!(
+        words(source, Processor::SingleCycle),
+        words(
+            "BEQ R0, R0, 1\nADI R2, R1, 0\nLLI R3, 0\nBEQ R0, R0, -3\nJAL R7, -4\nJLR R7, R7\n",
+            Processor::SingleCycle
+        )
+    );
+}
+
+#[test]
+fn mnemonics_are_case_insensitive() {
+    assert_eq!(
+        words("nop\nmov r1, r2\n", Processor::Pipelined),
+        words("NOP\nMOV R1, R2\n", Processor::Pipelined)
+    );
+}
+
+#[test]
+fn li_loads_every_kind_of_value() {
+    for processor in [Processor::Pipelined, Processor::SingleCycle] {
+        for value in [
+            0, 5, 255, 256, 511, 512, 0x1234, 0x8000, 0xFFFF, 0xFFE0, -1, -32768,
+        ] {
+            let words = words(&format!("LI R4, {}\n", value), processor);
+            assert_eq!(
+                run(&words, processor)[4],
+                value as u16,
+                "LI R4, {} on {:?}",
+                value,
+                processor
+            );
+        }
+    }
+}
+
+#[test]
+fn li_is_as_short_as_the_value_allows() {
+    let length = |source: &str| words(source, Processor::Pipelined).len();
+    assert_eq!(length("LI R1, 300\n"), 1);
+    assert_eq!(length("LI R1, -5\n"), 2);
+    assert_eq!(length(".equ BIG, 0x1000\nLI R1, BIG\n"), 8);
+    assert_eq!(length("LI R1, 0x1234\n"), 10);
+}
+
+#[test]
+fn li_of_a_label_takes_the_full_sequence() {
+    let source = "\
+       LI R2, TABLE
+       LI R3, TABLE
+TABLE: .word 7
+";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(program.symbol_table["TABLE"], 20);
+    let registers = run(&program.words[..20], Processor::Pipelined);
+    assert_eq!(registers[2], 20);
+    assert_eq!(registers[3], 20);
+}
+
+#[test]
+fn li_disassembles_to_source_that_assembles_again() {
+    for processor in [Processor::Pipelined, Processor::SingleCycle] {
+        let image = words("LI R1, 0x1234\nLI R2, -5\n", processor);
+        let text = disassemble(&image, processor);
+        assert_eq!(words(&text, processor), image, "{:?}:\n{}", processor, text);
+    }
+    // only the adds may repeat a register, to double it
+    assert_eq!(
+        first_error("ADC R1, R2, R2\n").message,
+        "Register B and Register C must be different"
+    );
+}
+
+#[test]
+fn labels_after_pseudo_ops_account_for_their_length() {
+    let source = "\
+       LI R1, 0x1234
+       B END
+       NOP
+END:   RET
+";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(program.symbol_table["END"], 12);
+    assert_eq!(program.line_numbers[&0], 1);
+    assert_eq!(program.line_numbers[&9], 1);
+    assert_eq!(program.line_numbers[&10], 2);
+}
+
+#[test]
+fn lowered_instructions_remember_their_pseudo_op() {
+    let mut parser = Parser::new("LI R1, 600\nMOV R2, R1\nADI R1, R1, 0\n");
+    assert!(parser.parse().is_empty());
+    let pseudo: Vec<Option<PseudoOp>> = parser
+        .instructions
+        .iter()
+        .map(|instruction| instruction.pseudo)
+        .collect();
+    assert_eq!(
+        pseudo[..pseudo.len() - 2],
+        vec![Some(PseudoOp::Li); pseudo.len() - 2]
+    );
+    assert_eq!(pseudo[pseudo.len() - 2..], [Some(PseudoOp::Mov), None]);
+    // every instruction of the LI points back at the LI line
+    assert!(parser.instructions[..pseudo.len() - 2]
+        .iter()
+        .all(|instruction| instruction.span == (0..10) && instruction.line_number == 1));
+}
+
+#[test]
+fn disassembly_shows_pseudo_ops() {
+    let source = "NOP\nMOV R1, R2\nCLR R3\nLOOP: B LOOP\nCALL LOOP\nRET\nADI R1, R2, 3\n";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(
+        disassemble(&program.words, Processor::Pipelined),
+        "BEQ R0, R0, 1 ; NOP\n\
+         ADI R2, R1, 0 ; MOV R1, R2\n\
+         LLI R3, 0 ; CLR R3\n\
+         BEQ R0, R0, 0 ; B 0\n\
+         JAL R7, -1 ; CALL -1\n\
+         JRI R7, 0 ; RET\n\
+         ADI R1, R2, 3\n"
+    );
+}
+
+#[test]
+fn bad_pseudo_op_operands_are_reported() {
+    assert_eq!(first_error("MOV R1\n").message, "Expected comma");
+    assert_eq!(first_error("CLR 5\n").message, "Expected register");
+    assert_eq!(
+        first_error("LI R1, 70000\n").message,
+        "Value 70000 does not fit in a 16-bit word (-32768 to 65535)"
+    );
+    assert!(first_error("NOP R1\n")
+        .message
+        .starts_with("Unexpected token after operands"));
+    assert_eq!(
+        first_error("B FAR\n.space 40\nFAR: NOP\n").message,
+        "Target FAR is out of range: offset 41 does not fit in IMM6 (-32 to 31)"
+    );
+}
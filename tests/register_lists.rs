@@ -0,0 +1,74 @@
+// LM and SM take their 8-bit mask as a register list: {R0, R2-R4, R7}.
+
+#![allow(clippy::unusual_byte_groupings)]
+
+use iitb_cpu::crates::disassembler::{disassemble, register_list};
+use iitb_cpu::diagnostic::Severity;
+use iitb_cpu::lexer::Processor;
+
+mod common;
+use common::{assemble_pipelined, words};
+
+#[test]
+fn lists_and_ranges_become_masks() {
+    let words = words(
+        "LM R6, {R0, R2-R4, R7}\n\
+         SM R6, {R1}\n\
+         LM R0, {R1-R7}\n\
+         SM R7, 0b10100001\n",
+    );
+    assert_eq!(
+        words,
+        vec![
+            0b0110_110_0_10111001,
+            0b0111_110_0_01000000,
+            0b0110_000_0_01111111,
+            0b0111_111_0_10100001,
+        ]
+    );
+}
+
+#[test]
+fn empty_and_malformed_lists_are_rejected() {
+    for (source, message) in [
+        ("LM R6, {}", "Register list is empty"),
+        ("LM R6, {R0,}", "Expected register"),
+        ("LM R6, {R0 R1}", "Expected ',' or '}' in register list"),
+        ("LM R6, {R0, R1", "Expected ',' or '}' in register list"),
+        ("LM R6, {R4-R2}", "Register range R4-R2 runs backwards"),
+        ("LLI R6, {R1}", "Expected immediate"),
+    ] {
+        let diagnostics = assemble_pipelined(source).unwrap_err();
+        assert_eq!(diagnostics[0].message, message, "{}", source);
+    }
+
+    let diagnostics = assemble_pipelined("LM R6, {}").unwrap_err();
+    assert_eq!(diagnostics[0].span, 7..9);
+}
+
+#[test]
+fn base_register_in_the_list_is_a_warning() {
+    let source = "SM R3, {R0, R2-R4}\n";
+    let program = assemble_pipelined(source).unwrap();
+    assert_eq!(program.words, vec![0b0111_011_0_10111000]);
+    assert_eq!(program.warnings.len(), 1);
+    assert_eq!(program.warnings[0].severity, Severity::Warning);
+    assert_eq!(
+        program.warnings[0].message,
+        "Base register R3 is also in the register list"
+    );
+    assert_eq!(&source[program.warnings[0].span.clone()], "R2-R4");
+}
+
+#[test]
+fn disassembler_prints_register_lists() {
+    assert_eq!(register_list(0b10111001), "{R0, R2-R4, R7}");
+    assert_eq!(register_list(0b11000000), "{R0, R1}");
+    assert_eq!(register_list(0b11111111), "{R0-R7}");
+    assert_eq!(register_list(0), "0");
+
+    let image = [0b0110_110_0_10111001, 0b0111_000_0_00000001];
+    let text = disassemble(&image, Processor::Pipelined);
+    assert_eq!(text, "LM R6, {R0, R2-R4, R7}\nSM R0, {R7}\n");
+    assert_eq!(words(&text), image);
+}
@@ -0,0 +1,121 @@
+// Help for typos: the nearest mnemonic, register or label, and the expected operands.
+
+use iitb_cpu::diagnostic::Diagnostic;
+use iitb_cpu::lexer::Processor;
+use iitb_cpu::parser::Parser;
+use iitb_cpu::suggest::{closest, edit_distance};
+
+fn first_error(source: &str, processor: Processor) -> Diagnostic {
+    let mut parser = Parser::with_processor(source, processor);
+    parser
+        .parse()
+        .into_iter()
+        .find(|diagnostic| diagnostic.is_error())
+        .unwrap()
+}
+
+fn message_and_help(source: &str) -> (String, Option<String>) {
+    let diagnostic = first_error(source, Processor::Pipelined);
+    (diagnostic.message, diagnostic.help)
+}
+
+#[test]
+fn edit_distance_counts_single_character_edits() {
+    assert_eq!(edit_distance("ADDI", "ADI"), 1);
+    assert_eq!(edit_distance("lw", "LW"), 0);
+    assert_eq!(edit_distance("BQE", "BEQ"), 1);
+    assert_eq!(edit_distance("JAL", "JRI"), 2);
+    assert_eq!(edit_distance("", "NOP"), 3);
+    assert_eq!(closest("LOPP", ["LOOP", "DONE"]), Some("LOOP"));
+    assert_eq!(closest("XYZ", ["LOOP", "DONE"]), None);
+}
+
+#[test]
+fn unknown_mnemonics_suggest_the_nearest_one() {
+    assert_eq!(
+        message_and_help("ADDI R1, R2, 3\n"),
+        (
+            "Unknown instruction: ADDI".to_string(),
+            Some("did you mean ADI?".to_string())
+        )
+    );
+    assert_eq!(
+        message_and_help("CALLL DONE\nDONE: NOP\n").1.as_deref(),
+        Some("did you mean CALL?")
+    );
+    assert_eq!(
+        message_and_help(".macro PUSH reg\nSW reg, R6, 0\n.endm\nPUHS R1\n")
+            .1
+            .as_deref(),
+        Some("did you mean PUSH?")
+    );
+    assert_eq!(message_and_help("FROBNICATE R1\n").1, None);
+    assert_eq!(
+        message_and_help(".wrod 5\n"),
+        (
+            "Unknown directive: .wrod".to_string(),
+            Some("did you mean .word?".to_string())
+        )
+    );
+}
+
+#[test]
+fn instructions_of_the_other_processor_are_named() {
+    let diagnostic = first_error("ADD R1, R2, R3\n", Processor::Pipelined);
+    assert_eq!(
+        diagnostic.help.as_deref(),
+        Some("ADD is an instruction of the SingleCycle processor, not the Pipelined one")
+    );
+}
+
+#[test]
+fn register_lookalikes_list_the_registers() {
+    assert_eq!(
+        message_and_help("LW R8, R1, 0\n"),
+        (
+            "Expected register".to_string(),
+            Some("registers are R0 to R7".to_string())
+        )
+    );
+    assert_eq!(
+        message_and_help("LLI R1, R9\n").1.as_deref(),
+        Some("registers are R0 to R7")
+    );
+}
+
+#[test]
+fn undefined_labels_suggest_the_nearest_symbol() {
+    assert_eq!(
+        message_and_help("LOOP: BEQ R1, R2, LOPP\n"),
+        (
+            "Undefined label: LOPP".to_string(),
+            Some("did you mean LOOP?".to_string())
+        )
+    );
+    assert_eq!(
+        message_and_help(".equ COUNT, 3\n.word COUNTS\n")
+            .1
+            .as_deref(),
+        Some("did you mean COUNT?")
+    );
+}
+
+#[test]
+fn operand_errors_show_the_expected_form() {
+    for (source, help) in [
+        ("LW R1, 5, 5\n", "LW expects RA, RB, IMM6"),
+        ("ADA R1, R2\n", "ADA expects RA, RB, RC"),
+        ("JLR R1 R2\n", "JLR expects RA, RB"),
+        ("LLI R1\n", "LLI expects RA, IMM9"),
+        ("LM R1, R2\n", "LM expects RA, {REGISTERS}"),
+        ("MOV R1\n", "MOV expects RA, RB"),
+        ("NOP R1\n", "NOP expects no operands"),
+    ] {
+        assert_eq!(
+            first_error(source, Processor::Pipelined).help.as_deref(),
+            Some(help),
+            "{}",
+            source
+        );
+    }
+}
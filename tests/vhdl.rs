@@ -0,0 +1,106 @@
+// VHDL packages and ROM entities holding the memory image.
+
+use iitb_cpu::crates::assembler::{assemble, AssembleOptions, Program};
+use iitb_cpu::crates::vhdl::{write_vhdl, VhdlOptions};
+use iitb_cpu::source::SourceMap;
+
+const SOURCE: &str = "\
+START: LLI R1, 5
+       MOV R2, R1
+.org 4
+TABLE: .word 0xBEEF
+";
+
+fn program() -> (Program, SourceMap) {
+    let mut sources = SourceMap::new();
+    sources.add("main.asm", SOURCE);
+    let program =
+        iitb_cpu::crates::assembler::assemble_sources(&mut sources, &AssembleOptions::default())
+            .unwrap();
+    (program, sources)
+}
+
+#[test]
+fn the_package_holds_every_word_with_its_source_line() {
+    let (program, sources) = program();
+    assert_eq!(
+        write_vhdl(&program, &sources, &VhdlOptions::default()).unwrap(),
+        "\
+-- program_rom: generated by iitb_cpu from main.asm
+library ieee;
+use ieee.std_logic_1164.all;
+
+package program_rom is
+    constant ROM_WIDTH : natural := 16;
+    constant ROM_DEPTH : natural := 8;
+    type rom_type is array (0 to ROM_DEPTH - 1) of std_logic_vector(ROM_WIDTH - 1 downto 0);
+    constant ROM : rom_type := (
+        0 => \"0011001000000101\", -- LLI    main.asm:1  START: LLI R1, 5
+        1 => \"0000001010000000\", -- ADI    main.asm:2  MOV R2, R1
+        4 => \"1011111011101111\", -- .word  main.asm:4  TABLE: .word 0xBEEF
+        others => (others => '0')
+    );
+end package program_rom;
+"
+    );
+}
+
+#[test]
+fn width_and_depth_can_be_chosen() {
+    let (program, sources) = program();
+    let options = VhdlOptions {
+        name: "boot".to_string(),
+        width: 18,
+        depth: Some(256),
+        entity: false,
+    };
+    let vhdl = write_vhdl(&program, &sources, &options).unwrap();
+    assert!(vhdl.contains("constant ROM_WIDTH : natural := 18;"));
+    assert!(vhdl.contains("constant ROM_DEPTH : natural := 256;"));
+    assert!(vhdl.contains("0 => \"000011001000000101\""));
+    assert!(vhdl.contains("package boot is"));
+
+    let too_narrow = VhdlOptions {
+        width: 8,
+        ..VhdlOptions::default()
+    };
+    assert!(write_vhdl(&program, &sources, &too_narrow).is_err());
+    let too_shallow = VhdlOptions {
+        depth: Some(4),
+        ..VhdlOptions::default()
+    };
+    assert_eq!(
+        write_vhdl(&program, &sources, &too_shallow).unwrap_err(),
+        "A depth of 4 words cannot hold the 5 words of the program"
+    );
+    let bad_name = VhdlOptions {
+        name: "2nd-rom".to_string(),
+        ..VhdlOptions::default()
+    };
+    assert!(write_vhdl(&program, &sources, &bad_name).is_err());
+}
+
+#[test]
+fn an_entity_reads_the_rom_on_the_clock() {
+    let (program, sources) = program();
+    let options = VhdlOptions {
+        entity: true,
+        ..VhdlOptions::default()
+    };
+    let vhdl = write_vhdl(&program, &sources, &options).unwrap();
+    assert!(vhdl.contains("entity program_rom is"));
+    assert!(vhdl.contains("address : in  std_logic_vector(2 downto 0);"));
+    assert!(vhdl.contains("architecture rtl of program_rom is"));
+    assert!(vhdl.contains("data <= ROM(to_integer(unsigned(address)));"));
+    assert!(vhdl.ends_with("end architecture rtl;\n"));
+}
+
+#[test]
+fn programs_without_a_file_name_still_convert() {
+    let program = assemble("NOP\n", &AssembleOptions::default()).unwrap();
+    let mut sources = SourceMap::new();
+    sources.add("<input>", "NOP\n");
+    let vhdl = write_vhdl(&program, &sources, &VhdlOptions::default()).unwrap();
+    assert!(vhdl.contains("0 => \"1000000000000001\", -- BEQ    <input>:1  NOP"));
+    assert!(vhdl.contains("ROM_DEPTH : natural := 1;"));
+}